@@ -0,0 +1,278 @@
+use crate::core::{Bytes, Text, Value};
+
+/// The largest power of two which fits in a `f64` mantissa, integers with
+/// a magnitude at or below this convert to a float without any loss.
+const MAX_SAFE_INTEGER: u64 = 1 << 53;
+
+/// Collects array elements into the most specialised array variant in a
+/// single pass.
+///
+/// Elements are kept in a typed vec for as long as they share a type.
+/// Mixed integers widen to `i64` when every value fits and integers mixed
+/// with floats widen to `f64` when every integer converts losslessly.
+/// Any other mix falls back to `Value::ArrayDynamic`, keeping the elements
+/// as they were widened so far.
+///
+/// Nulls always produce a dynamic array as the typed variants have no way
+/// of representing a missing element.
+pub(crate) struct ArrayBuilder {
+    state: State,
+    capacity: usize,
+}
+
+enum State {
+    Empty,
+    Bool(Vec<bool>),
+    String(Vec<Text>),
+    Bytes(Vec<Bytes>),
+    U64(Vec<u64>),
+    I64(Vec<i64>),
+    F64(Vec<f64>),
+    Date(Vec<i64>),
+    Dynamic(Vec<Value>),
+}
+
+impl ArrayBuilder {
+    /// Creates a new builder expecting roughly `capacity` elements.
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            state: State::Empty,
+            capacity,
+        }
+    }
+
+    /// Adds an element to the end of the array.
+    pub(crate) fn push(&mut self, value: Value) {
+        match (&mut self.state, value) {
+            (State::Bool(values), Value::Bool(v)) => values.push(v),
+            (State::String(values), Value::String(v)) => values.push(v),
+            (State::Bytes(values), Value::Bytes(v)) => values.push(v),
+            (State::U64(values), Value::U64(v)) => values.push(v),
+            (State::I64(values), Value::I64(v)) => values.push(v),
+            (State::F64(values), Value::F64(v)) => values.push(v),
+            (State::Date(values), Value::Date(v)) => values.push(v),
+            (State::Dynamic(values), value) => values.push(value),
+            (State::Empty, value) => self.state = self.start(value),
+            (State::U64(values), Value::I64(v)) => match widen_to_i64(values) {
+                Some(mut widened) => {
+                    widened.push(v);
+                    self.state = State::I64(widened);
+                },
+                None => self.widen_to_f64(Value::I64(v)),
+            },
+            (State::I64(values), Value::U64(v)) => match i64::try_from(v) {
+                Ok(v) => values.push(v),
+                Err(_) => self.widen_to_f64(Value::U64(v)),
+            },
+            (State::F64(values), value @ (Value::U64(_) | Value::I64(_))) => {
+                match lossless_f64(&value) {
+                    Some(v) => values.push(v),
+                    None => self.push_dynamic(value),
+                }
+            },
+            (State::U64(_) | State::I64(_), Value::F64(v)) => {
+                self.widen_to_f64(Value::F64(v))
+            },
+            (_, value) => self.push_dynamic(value),
+        }
+    }
+
+    /// Consumes the builder producing the array value.
+    pub(crate) fn finish(self) -> Value {
+        match self.state {
+            State::Empty => Value::ArrayDynamic(Vec::new()),
+            State::Bool(values) => Value::ArrayBool(values),
+            State::String(values) => Value::ArrayString(values),
+            State::Bytes(values) => Value::ArrayBytes(values),
+            State::U64(values) => Value::ArrayU64(values),
+            State::I64(values) => Value::ArrayI64(values),
+            State::F64(values) => Value::ArrayF64(values),
+            State::Date(values) => Value::ArrayDate(values),
+            State::Dynamic(values) => Value::ArrayDynamic(values),
+        }
+    }
+
+    fn start(&self, value: Value) -> State {
+        macro_rules! typed {
+            ($variant:ident, $v:expr) => {{
+                let mut values = Vec::with_capacity(self.capacity);
+                values.push($v);
+                State::$variant(values)
+            }};
+        }
+
+        match value {
+            Value::Bool(v) => typed!(Bool, v),
+            Value::String(v) => typed!(String, v),
+            Value::Bytes(v) => typed!(Bytes, v),
+            Value::U64(v) => typed!(U64, v),
+            Value::I64(v) => typed!(I64, v),
+            Value::F64(v) => typed!(F64, v),
+            Value::Date(v) => typed!(Date, v),
+            other => typed!(Dynamic, other),
+        }
+    }
+
+    /// Converts the integers pushed so far into floats and pushes `value`,
+    /// falling back to a dynamic array if any of them would lose precision.
+    fn widen_to_f64(&mut self, value: Value) {
+        let widened = match (&self.state, lossless_f64(&value)) {
+            (State::U64(values), Some(v)) => values
+                .iter()
+                .map(|v| lossless_f64(&Value::U64(*v)))
+                .chain(Some(Some(v)))
+                .collect::<Option<Vec<_>>>(),
+            (State::I64(values), Some(v)) => values
+                .iter()
+                .map(|v| lossless_f64(&Value::I64(*v)))
+                .chain(Some(Some(v)))
+                .collect::<Option<Vec<_>>>(),
+            _ => None,
+        };
+
+        match widened {
+            Some(values) => self.state = State::F64(values),
+            None => self.push_dynamic(value),
+        }
+    }
+
+    fn push_dynamic(&mut self, value: Value) {
+        let mut values = self.take_dynamic();
+        values.push(value);
+        self.state = State::Dynamic(values);
+    }
+
+    /// Moves the elements pushed so far into a vec of dynamic values.
+    fn take_dynamic(&mut self) -> Vec<Value> {
+        fn convert<T>(
+            values: Vec<T>,
+            capacity: usize,
+            map: impl Fn(T) -> Value,
+        ) -> Vec<Value> {
+            let mut converted = Vec::with_capacity(capacity.max(values.len() + 1));
+            converted.extend(values.into_iter().map(map));
+            converted
+        }
+
+        match std::mem::replace(&mut self.state, State::Empty) {
+            State::Empty => Vec::with_capacity(self.capacity),
+            State::Bool(values) => convert(values, self.capacity, Value::from),
+            State::String(values) => convert(values, self.capacity, Value::from),
+            State::Bytes(values) => convert(values, self.capacity, Value::from),
+            State::U64(values) => convert(values, self.capacity, Value::from),
+            State::I64(values) => convert(values, self.capacity, Value::from),
+            State::F64(values) => convert(values, self.capacity, Value::from),
+            State::Date(values) => convert(values, self.capacity, Value::Date),
+            State::Dynamic(values) => values,
+        }
+    }
+}
+
+/// Reinterprets a vec of `u64`s as `i64`s in place if every value fits.
+fn widen_to_i64(values: &mut Vec<u64>) -> Option<Vec<i64>> {
+    if values.iter().any(|v| *v > i64::MAX as u64) {
+        return None;
+    }
+
+    // Every value is at most `i64::MAX` so the bit patterns are identical.
+    let values = std::mem::take(values);
+    Some(values.into_iter().map(|v| v as i64).collect())
+}
+
+fn lossless_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::U64(v) if *v <= MAX_SAFE_INTEGER => Some(*v as f64),
+        Value::I64(v) if v.unsigned_abs() <= MAX_SAFE_INTEGER => Some(*v as f64),
+        Value::F64(v) => Some(*v),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(values: Vec<Value>) -> Value {
+        let mut builder = ArrayBuilder::with_capacity(values.len());
+        for value in values {
+            builder.push(value);
+        }
+        builder.finish()
+    }
+
+    #[test]
+    fn test_homogeneous_arrays() {
+        assert_eq!(build(vec![]), Value::ArrayDynamic(vec![]));
+        assert_eq!(
+            build(vec![Value::Bool(true), Value::Bool(false)]),
+            Value::ArrayBool(vec![true, false])
+        );
+        assert_eq!(
+            build(vec![Value::U64(1), Value::U64(2)]),
+            Value::ArrayU64(vec![1, 2])
+        );
+        assert_eq!(
+            build(vec![Value::String(Text::from("a"))]),
+            Value::ArrayString(vec![Text::from("a")])
+        );
+        assert_eq!(
+            build(vec![Value::Date(1), Value::Date(2)]),
+            Value::ArrayDate(vec![1, 2])
+        );
+    }
+
+    #[test]
+    fn test_widen_integers() {
+        assert_eq!(
+            build(vec![Value::U64(1), Value::I64(-2)]),
+            Value::ArrayI64(vec![1, -2])
+        );
+        assert_eq!(
+            build(vec![Value::I64(-2), Value::U64(1)]),
+            Value::ArrayI64(vec![-2, 1])
+        );
+        assert_eq!(
+            build(vec![Value::U64(1), Value::F64(0.5), Value::I64(-1)]),
+            Value::ArrayF64(vec![1.0, 0.5, -1.0])
+        );
+        assert_eq!(
+            build(vec![Value::F64(0.5), Value::U64(1)]),
+            Value::ArrayF64(vec![0.5, 1.0])
+        );
+        assert_eq!(
+            build(vec![Value::U64(u64::MAX), Value::I64(-1)]),
+            Value::ArrayDynamic(vec![Value::U64(u64::MAX), Value::I64(-1)]),
+            "Values outside of the i64 and f64 ranges should not be widened"
+        );
+        assert_eq!(
+            build(vec![Value::I64(-1), Value::U64(1 << 60), Value::F64(0.5)]),
+            Value::ArrayDynamic(vec![
+                Value::I64(-1),
+                Value::I64(1 << 60),
+                Value::F64(0.5)
+            ])
+        );
+        assert_eq!(
+            build(vec![Value::F64(0.5), Value::U64((1 << 53) + 1)]),
+            Value::ArrayDynamic(vec![Value::F64(0.5), Value::U64((1 << 53) + 1)]),
+            "Integers which lose precision should not be widened"
+        );
+    }
+
+    #[test]
+    fn test_dynamic_arrays() {
+        assert_eq!(
+            build(vec![Value::U64(1), Value::Null]),
+            Value::ArrayDynamic(vec![Value::U64(1), Value::Null])
+        );
+        assert_eq!(
+            build(vec![Value::U64(1), Value::I64(-1), Value::Bool(true)]),
+            Value::ArrayDynamic(vec![Value::I64(1), Value::I64(-1), Value::Bool(true)])
+        );
+        assert_eq!(
+            build(vec![Value::Date(1), Value::I64(2)]),
+            Value::ArrayDynamic(vec![Value::Date(1), Value::I64(2)]),
+            "Dates should not be mixed with plain integers"
+        );
+    }
+}
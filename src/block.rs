@@ -0,0 +1,1180 @@
+//! Block compressed document streams
+//!
+//! A block stream is laid out as:
+//! `| blocks | dictionary | block index | trailer |`
+//!
+//! Each block holds a run of documents encoded by an [Encoder] and compressed as
+//! a single unit, so small documents share their redundancy. The block index
+//! records where each block starts, its sizes, the documents it holds and a
+//! checksum of its compressed bytes, so any block can be decompressed without
+//! reading the rest of the stream.
+//!
+//! Documents within a block use the default alignment and framing, and the
+//! checksum algorithm of the stream, which also checksums each compressed
+//! block. The block index and trailer are always checksummed with CRC32, like
+//! the trailer of a segment, as they record the algorithm.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, Write};
+
+use rkyv::AlignedVec;
+
+use crate::{
+    BoxedError,
+    ChecksumAlgorithm,
+    Decoder,
+    Document,
+    Encoder,
+    Error,
+    Result,
+    Schema,
+    ScratchStats,
+    SegmentFlags,
+    DEFAULT_SCRATCH_SPACE,
+};
+
+/// The magic bytes at the end of every block stream.
+pub const BLOCK_MAGIC: [u8; 4] = *b"BLNB";
+
+/// The size of each entry in the block index.
+pub const BLOCK_INDEX_ENTRY_SIZE: usize = 48;
+
+/// The size of the block stream trailer.
+///
+/// The trailer is written in the format of:
+/// `| index offset(8) | blocks(8) | largest block length(8) | dictionary length(4) |`
+/// `| compression(1) | checksum algorithm(1) | flags(1) | reserved(1) | checksum(4) |`
+/// `| magic(4) |`
+///
+/// The flags share their bits with [SegmentFlags], only
+/// [SegmentFlags::CANONICAL] is recorded.
+pub const BLOCK_TRAILER_SIZE: usize = 40;
+
+/// The default maximum number of documents in a block.
+pub const DEFAULT_BLOCK_DOCUMENTS: u32 = 1024;
+
+/// The default size, before compression, a block is closed at.
+pub const DEFAULT_BLOCK_SIZE: usize = 64 << 10;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+/// The algorithm each block is compressed with.
+///
+/// Each algorithm is only available with its feature enabled, so matches
+/// outside of this crate must handle algorithms they do not know of.
+pub enum BlockCompression {
+    #[cfg(feature = "zstd")]
+    /// Zstandard at the given compression level.
+    Zstd { level: i32 },
+    #[cfg(feature = "lz4")]
+    /// LZ4, which decompresses faster but compresses less than Zstandard.
+    Lz4,
+}
+
+impl BlockCompression {
+    #[inline]
+    /// The id of the algorithm written to the trailer.
+    fn id(self) -> u8 {
+        match self {
+            #[cfg(feature = "zstd")]
+            Self::Zstd { .. } => 1,
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A dictionary shared by every block, improving the compression of
+/// small blocks.
+///
+/// The dictionary is written to the stream, so the [BlockDecoder] always
+/// decompresses with the same dictionary.
+pub struct BlockDictionary(Vec<u8>);
+
+impl BlockDictionary {
+    #[inline]
+    /// Creates a dictionary from its raw bytes.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    #[cfg(feature = "zstd")]
+    /// Trains a dictionary of at most `max_size` bytes from the encoded
+    /// sample documents.
+    ///
+    /// The samples should be representative of the documents being encoded,
+    /// zstd needs at least a few hundred samples to produce a useful dictionary.
+    pub fn train(samples: &[Document], max_size: usize) -> Result<Self> {
+        let mut encoder = Encoder::<_, DEFAULT_SCRATCH_SPACE>::new(AlignedVec::new());
+        encoder.enable_position_index();
+        for sample in samples {
+            encoder.encode(sample)?;
+        }
+
+        let buf = encoder.writer();
+        let mut start = 0;
+        let records = encoder
+            .positions()
+            .iter()
+            .map(|end| {
+                let record = &buf[start..*end as usize];
+                start = *end as usize;
+                record
+            })
+            .collect::<Vec<_>>();
+
+        let dictionary = zstd::dict::from_samples(&records, max_size)?;
+        Ok(Self(dictionary))
+    }
+
+    #[inline]
+    /// The raw bytes of the dictionary.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// The location of a single block within the stream.
+///
+/// Each entry is written in the format of:
+/// `| offset(8) | compressed length(8) | length(8) | first document(8) |`
+/// `| documents(4) | reserved(4) | checksum(8) |`
+pub struct BlockEntry {
+    /// The byte offset of the compressed block.
+    pub offset: u64,
+    /// The length of the compressed block.
+    pub compressed_len: u64,
+    /// The length of the block once decompressed.
+    pub len: u64,
+    /// The index of the first document in the block.
+    pub first_document: u64,
+    /// The number of documents in the block.
+    pub documents: u32,
+    /// The checksum of the compressed block, calculated with the checksum
+    /// algorithm of the stream.
+    pub checksum: u64,
+}
+
+impl BlockEntry {
+    fn read(bytes: &[u8]) -> Self {
+        Self {
+            offset: read_u64(bytes),
+            compressed_len: read_u64(&bytes[8..]),
+            len: read_u64(&bytes[16..]),
+            first_document: read_u64(&bytes[24..]),
+            documents: read_u32(&bytes[32..]),
+            checksum: read_u64(&bytes[40..]),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; BLOCK_INDEX_ENTRY_SIZE] {
+        let mut entry = [0; BLOCK_INDEX_ENTRY_SIZE];
+        entry[..8].copy_from_slice(&self.offset.to_le_bytes());
+        entry[8..16].copy_from_slice(&self.compressed_len.to_le_bytes());
+        entry[16..24].copy_from_slice(&self.len.to_le_bytes());
+        entry[24..32].copy_from_slice(&self.first_document.to_le_bytes());
+        entry[32..36].copy_from_slice(&self.documents.to_le_bytes());
+        entry[40..].copy_from_slice(&self.checksum.to_le_bytes());
+        entry
+    }
+}
+
+#[derive(Debug)]
+/// The reason a block stream could not be opened or a block could not be read.
+pub enum BlockError {
+    /// The buffer is too short to hold a block stream.
+    Truncated {
+        /// The length of the buffer.
+        len: usize,
+    },
+    /// The buffer does not end with [BLOCK_MAGIC].
+    BadMagic,
+    /// The blocks were compressed with an algorithm this build does not support.
+    UnsupportedCompression {
+        /// The identifier of the compression algorithm in the trailer.
+        id: u8,
+    },
+    /// The blocks were checksummed with an algorithm this build does not support.
+    UnsupportedChecksum {
+        /// The identifier of the checksum algorithm in the trailer.
+        id: u8,
+    },
+    /// The checksum of the block index did not match the one in the trailer.
+    ChecksumMismatch {
+        /// The checksum stored in the trailer.
+        expected: u32,
+        /// The checksum of the block index and trailer.
+        actual: u32,
+    },
+    /// The block index offset in the trailer lies outside the stream.
+    InvalidIndexOffset {
+        /// The offset in the trailer.
+        offset: u64,
+    },
+    /// The block lies outside the blocks of the stream.
+    OutOfBounds {
+        /// The index of the block.
+        block: usize,
+    },
+    /// The block is larger than the decoder will decompress.
+    BlockTooLarge {
+        /// The index of the block.
+        block: usize,
+        /// The decompressed length in the block's entry.
+        len: u64,
+        /// The largest block the decoder will decompress.
+        max: u64,
+    },
+    /// The checksum of the compressed block did not match the one in its entry.
+    BlockChecksumMismatch {
+        /// The index of the block.
+        block: usize,
+        /// The checksum stored in the block's entry.
+        expected: u64,
+        /// The checksum of the compressed block.
+        actual: u64,
+    },
+    /// The block could not be decompressed.
+    Decompress {
+        /// The index of the block.
+        block: usize,
+        /// The decompression error.
+        source: BoxedError,
+    },
+    /// The block decompressed to a different length than in its entry.
+    LengthMismatch {
+        /// The index of the block.
+        block: usize,
+        /// The decompressed length in the block's entry.
+        expected: u64,
+        /// The number of bytes the block decompressed to.
+        actual: usize,
+    },
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated { len } => {
+                write!(
+                    f,
+                    "buffer of length {len} is too short to be a block stream"
+                )
+            },
+            Self::BadMagic => write!(f, "buffer is not a bellini block stream"),
+            Self::UnsupportedCompression { id } => {
+                write!(f, "block compression {id} is not supported by this build")
+            },
+            Self::UnsupportedChecksum { id } => {
+                write!(f, "block checksum {id} is not supported by this build")
+            },
+            Self::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "block index has checksum {actual:#010x}, expected {expected:#010x}"
+            ),
+            Self::InvalidIndexOffset { offset } => {
+                write!(f, "block index offset {offset} is out of bounds")
+            },
+            Self::OutOfBounds { block } => {
+                write!(f, "block {block} lies outside the block stream")
+            },
+            Self::BlockTooLarge { block, len, max } => write!(
+                f,
+                "block {block} of length {len} is larger than the maximum of {max}"
+            ),
+            Self::BlockChecksumMismatch {
+                block,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "block {block} has checksum {actual:#010x}, expected {expected:#010x}"
+            ),
+            Self::Decompress { block, source } => {
+                write!(f, "block {block} could not be decompressed: {source}")
+            },
+            Self::LengthMismatch {
+                block,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "block {block} decompressed to {actual} bytes, expected {expected}"
+            ),
+        }
+    }
+}
+
+impl StdError for BlockError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Decompress { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// A document encoder that groups documents into compressed blocks.
+///
+/// A block is closed once it holds [BlockEncoder::set_max_block_documents]
+/// documents or grows beyond [BlockEncoder::set_max_block_size] bytes, so a
+/// single large document can exceed the block size. The stream is incomplete
+/// until [BlockEncoder::finish] writes the final block, the dictionary, the
+/// block index and the trailer.
+pub struct BlockEncoder<W: Write, const N: usize = DEFAULT_SCRATCH_SPACE> {
+    writer: W,
+    encoder: Encoder<AlignedVec, N>,
+    compression: BlockCompression,
+    compressor: Option<Compressor>,
+    dictionary: Option<BlockDictionary>,
+    max_documents: u32,
+    max_size: usize,
+    index: Vec<BlockEntry>,
+    position: u64,
+}
+
+impl<W: Write, const N: usize> BlockEncoder<W, N> {
+    /// Create a new block encoder compressing each block with the given algorithm.
+    pub fn new(writer: W, compression: BlockCompression) -> Self {
+        Self {
+            writer,
+            encoder: Encoder::new(AlignedVec::new()),
+            compression,
+            compressor: None,
+            dictionary: None,
+            max_documents: DEFAULT_BLOCK_DOCUMENTS,
+            max_size: DEFAULT_BLOCK_SIZE,
+            index: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Sets the maximum number of documents in each block.
+    ///
+    /// Defaults to [DEFAULT_BLOCK_DOCUMENTS].
+    ///
+    /// ### Panics
+    /// If the number of documents is zero.
+    pub fn set_max_block_documents(&mut self, documents: u32) {
+        assert!(documents > 0, "Blocks must hold at least one document");
+        self.max_documents = documents;
+    }
+
+    /// Sets the size, before compression, each block is closed at.
+    ///
+    /// Defaults to [DEFAULT_BLOCK_SIZE].
+    pub fn set_max_block_size(&mut self, size: usize) {
+        self.max_size = size;
+    }
+
+    /// Sets the algorithm used to checksum each document and each compressed
+    /// block, see [Encoder::set_checksum_algorithm].
+    ///
+    /// The algorithm is recorded in the trailer, so the [BlockDecoder] picks
+    /// it automatically.
+    ///
+    /// ### Panics
+    /// If any documents have already been encoded.
+    pub fn set_checksum_algorithm(&mut self, checksum: ChecksumAlgorithm) {
+        self.encoder.set_checksum_algorithm(checksum);
+    }
+
+    /// Compresses every block with the given dictionary, see [BlockDictionary].
+    ///
+    /// ### Panics
+    /// If any documents have already been encoded.
+    pub fn set_dictionary(&mut self, dictionary: BlockDictionary) {
+        assert_eq!(
+            self.encoder.documents(),
+            0,
+            "The dictionary must be set before encoding any documents"
+        );
+        self.dictionary = Some(dictionary);
+    }
+
+    /// Enables the canonical document layout, see
+    /// [Encoder::enable_canonical_layout].
+    ///
+    /// The layout is recorded in the trailer, see [BlockDecoder::is_canonical].
+    pub fn enable_canonical_layout(&mut self) {
+        self.encoder.enable_canonical_layout();
+    }
+
+    /// Validates every document against the schema before encoding it,
+    /// see [Encoder::set_schema].
+    pub fn set_schema(&mut self, schema: Schema) {
+        self.encoder.set_schema(schema);
+    }
+
+    /// Limits the heap scratch space used while serializing a single document,
+    /// see [Encoder::set_scratch_heap_limit].
+    pub fn set_scratch_heap_limit(&mut self, limit: Option<usize>) {
+        self.encoder.set_scratch_heap_limit(limit);
+    }
+
+    #[inline]
+    /// Returns how often serializing has spilled out of the stack scratch space.
+    pub fn scratch_stats(&self) -> ScratchStats {
+        self.encoder.scratch_stats()
+    }
+
+    #[inline]
+    /// The number of documents which have been encoded.
+    pub fn documents(&self) -> usize {
+        self.encoder.documents()
+    }
+
+    #[inline]
+    /// The entries of every block which has been written.
+    pub fn blocks(&self) -> &[BlockEntry] {
+        &self.index
+    }
+
+    #[inline]
+    /// Return a reference to the given writer.
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+
+    /// Encode a document into the current block, writing the block out
+    /// once it is full.
+    pub fn encode(&mut self, document: &Document) -> Result<()> {
+        self.encoder.encode(document)?;
+
+        let block_documents = self.encoder.documents() - self.first_document();
+        if block_documents >= self.max_documents as usize
+            || self.encoder.position() >= self.max_size
+        {
+            self.flush_block()?;
+        }
+        Ok(())
+    }
+
+    /// Compresses and writes out the current block, even if it is not full.
+    pub fn flush_block(&mut self) -> Result<()> {
+        let first_document = self.first_document();
+        let documents = self.encoder.documents() - first_document;
+        if documents == 0 {
+            return Ok(());
+        }
+
+        let dictionary = self.dictionary.as_ref().map_or(&[][..], |d| d.as_bytes());
+        let compressor = match self.compressor.as_mut() {
+            Some(compressor) => compressor,
+            None => self
+                .compressor
+                .insert(Compressor::new(self.compression, dictionary)?),
+        };
+
+        let block = self.encoder.writer();
+        let compressed = compressor.compress(block)?;
+        self.writer.write_all(&compressed)?;
+
+        self.index.push(BlockEntry {
+            offset: self.position,
+            compressed_len: compressed.len() as u64,
+            len: block.len() as u64,
+            first_document: first_document as u64,
+            documents: documents as u32,
+            checksum: self.encoder.checksum_algorithm().checksum(&compressed),
+        });
+        self.position += compressed.len() as u64;
+        self.encoder.clear();
+        Ok(())
+    }
+
+    /// Writes the final block, the dictionary, the block index and the trailer,
+    /// completing the stream, and returns the inner writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.flush_block()?;
+
+        let dictionary = self.dictionary.as_ref().map_or(&[][..], |d| d.as_bytes());
+        self.writer.write_all(dictionary)?;
+        let index_offset = self.position + dictionary.len() as u64;
+
+        let mut hasher = crc32fast::Hasher::new();
+        for entry in &self.index {
+            let entry = entry.to_bytes();
+            hasher.update(&entry);
+            self.writer.write_all(&entry)?;
+        }
+
+        let max_block_len = self.index.iter().map(|entry| entry.len).max();
+        let mut trailer = [0; BLOCK_TRAILER_SIZE];
+        trailer[..8].copy_from_slice(&index_offset.to_le_bytes());
+        trailer[8..16].copy_from_slice(&(self.index.len() as u64).to_le_bytes());
+        trailer[16..24].copy_from_slice(&max_block_len.unwrap_or(0).to_le_bytes());
+        trailer[24..28].copy_from_slice(&(dictionary.len() as u32).to_le_bytes());
+        trailer[28] = self.compression.id();
+        trailer[29] = self.encoder.checksum_algorithm().id();
+        if self.encoder.is_canonical() {
+            trailer[30] = SegmentFlags::CANONICAL.bits() as u8;
+        }
+        hasher.update(&trailer[..32]);
+        trailer[32..36].copy_from_slice(&hasher.finalize().to_le_bytes());
+        trailer[36..].copy_from_slice(&BLOCK_MAGIC);
+        self.writer.write_all(&trailer)?;
+
+        Ok(self.writer)
+    }
+
+    #[inline]
+    /// The index of the first document in the current block.
+    fn first_document(&self) -> usize {
+        self.index.last().map_or(0, |entry| {
+            (entry.first_document + entry.documents as u64) as usize
+        })
+    }
+}
+
+/// A decoder for block streams written by a [BlockEncoder].
+///
+/// Blocks are decompressed on demand into a buffer which is reused between
+/// blocks, so only one block can be read at a time.
+pub struct BlockDecoder<'a> {
+    buf: &'a [u8],
+    index: &'a [u8],
+    decompressor: Decompressor<'a>,
+    checksum: ChecksumAlgorithm,
+    canonical: bool,
+    /// The largest block which will be decompressed.
+    max_block_len: u64,
+    block: AlignedVec,
+    validate_checksum: bool,
+}
+
+impl<'a> BlockDecoder<'a> {
+    /// Opens a block stream, checking the trailer and the block index are intact.
+    ///
+    /// The blocks themselves, and any dictionary, are only checked once they
+    /// are read.
+    pub fn open(buf: &'a [u8]) -> Result<Self> {
+        let index_end = buf
+            .len()
+            .checked_sub(BLOCK_TRAILER_SIZE)
+            .ok_or(BlockError::Truncated { len: buf.len() })?;
+
+        let trailer = &buf[index_end..];
+        if trailer[36..] != BLOCK_MAGIC {
+            return Err(BlockError::BadMagic.into());
+        }
+
+        let offset = read_u64(trailer);
+        let blocks = read_u64(&trailer[8..]);
+        let max_block_len = read_u64(&trailer[16..]);
+        let dictionary_len = read_u32(&trailer[24..]) as usize;
+        let index = usize::try_from(offset)
+            .ok()
+            .and_then(|start| buf[..index_end].get(start..))
+            .filter(|index| {
+                blocks.checked_mul(BLOCK_INDEX_ENTRY_SIZE as u64)
+                    == Some(index.len() as u64)
+            })
+            .ok_or(BlockError::InvalidIndexOffset { offset })?;
+        let index_offset = index_end - index.len();
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(index);
+        hasher.update(&trailer[..32]);
+        let expected = read_u32(&trailer[32..]);
+        let actual = hasher.finalize();
+        if expected != actual {
+            return Err(BlockError::ChecksumMismatch { expected, actual }.into());
+        }
+
+        let dictionary = index_offset
+            .checked_sub(dictionary_len)
+            .map(|start| &buf[start..index_offset])
+            .ok_or(BlockError::InvalidIndexOffset { offset })?;
+
+        let decompressor = Decompressor::new(trailer[28], dictionary)?;
+        let checksum = ChecksumAlgorithm::from_id(trailer[29])
+            .ok_or(BlockError::UnsupportedChecksum { id: trailer[29] })?;
+        let flags = SegmentFlags::from_bits(trailer[30] as u32);
+
+        Ok(Self {
+            buf: &buf[..index_offset - dictionary_len],
+            index,
+            decompressor,
+            checksum,
+            canonical: flags.contains(SegmentFlags::CANONICAL),
+            max_block_len,
+            block: AlignedVec::new(),
+            validate_checksum: false,
+        })
+    }
+
+    #[inline]
+    /// Enables validation of the checksum of each document within a block.
+    ///
+    /// The checksum of each compressed block is always validated.
+    pub fn enable_checksum_validation(&mut self) {
+        self.validate_checksum = true;
+    }
+
+    #[inline]
+    /// Lowers the largest block which will be decompressed, blocks beyond it
+    /// are rejected with a [BlockError::BlockTooLarge] error.
+    ///
+    /// Defaults to the largest block recorded in the trailer, which only
+    /// guards against a corrupt block index. Streams from untrusted sources
+    /// should set a limit, as the trailer can be rewritten too.
+    pub fn set_max_block_len(&mut self, max: u64) {
+        self.max_block_len = self.max_block_len.min(max);
+    }
+
+    #[inline]
+    /// The checksum algorithm the documents and blocks were written with.
+    pub fn checksum_algorithm(&self) -> ChecksumAlgorithm {
+        self.checksum
+    }
+
+    #[inline]
+    /// Returns if the documents were encoded in the canonical layout, see
+    /// [Decoder::as_canonical].
+    pub fn is_canonical(&self) -> bool {
+        self.canonical
+    }
+
+    #[inline]
+    /// The number of blocks in the stream.
+    pub fn blocks(&self) -> usize {
+        self.index.len() / BLOCK_INDEX_ENTRY_SIZE
+    }
+
+    #[inline]
+    /// The total number of documents in the stream.
+    pub fn documents(&self) -> u64 {
+        self.blocks()
+            .checked_sub(1)
+            .and_then(|last| self.block_entry(last))
+            .map_or(0, |entry| {
+                entry.first_document.saturating_add(entry.documents as u64)
+            })
+    }
+
+    #[inline]
+    /// Gets the index entry of the given block.
+    pub fn block_entry(&self, block: usize) -> Option<BlockEntry> {
+        let start = block.checked_mul(BLOCK_INDEX_ENTRY_SIZE)?;
+        let entry = self.index.get(start..start + BLOCK_INDEX_ENTRY_SIZE)?;
+        Some(BlockEntry::read(entry))
+    }
+
+    /// Finds the block holding the document with the given index.
+    pub fn find_block(&self, document: u64) -> Option<usize> {
+        let (mut low, mut high) = (0, self.blocks());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let entry = self.block_entry(mid)?;
+            if document < entry.first_document {
+                high = mid;
+            } else if document
+                >= entry.first_document.saturating_add(entry.documents as u64)
+            {
+                low = mid + 1;
+            } else {
+                return Some(mid);
+            }
+        }
+        None
+    }
+
+    /// Decompresses the given block, returning a [Decoder] over its documents.
+    ///
+    /// The decompressed block is only valid until the next block is read.
+    pub fn read_block(&mut self, block: usize) -> Result<Option<Decoder<'_>>> {
+        let entry = match self.block_entry(block) {
+            None => return Ok(None),
+            Some(entry) => entry,
+        };
+
+        let compressed = usize::try_from(entry.offset)
+            .ok()
+            .zip(usize::try_from(entry.compressed_len).ok())
+            .and_then(|(start, len)| self.buf.get(start..start.checked_add(len)?))
+            .ok_or(BlockError::OutOfBounds { block })?;
+        if entry.len > self.max_block_len {
+            return Err(BlockError::BlockTooLarge {
+                block,
+                len: entry.len,
+                max: self.max_block_len,
+            }
+            .into());
+        }
+        let len =
+            usize::try_from(entry.len).map_err(|_| BlockError::OutOfBounds { block })?;
+
+        let actual = self.checksum.checksum(compressed);
+        if actual != entry.checksum {
+            return Err(BlockError::BlockChecksumMismatch {
+                block,
+                expected: entry.checksum,
+                actual,
+            }
+            .into());
+        }
+
+        self.block.clear();
+        self.block.resize(len, 0);
+        let written = self
+            .decompressor
+            .decompress(compressed, &mut self.block)
+            .map_err(|source| BlockError::Decompress { block, source })?;
+        if written != len {
+            return Err(BlockError::LengthMismatch {
+                block,
+                expected: entry.len,
+                actual: written,
+            }
+            .into());
+        }
+
+        let mut decoder = Decoder::new(&self.block);
+        decoder.set_checksum_algorithm(self.checksum);
+        decoder.set_canonical(self.canonical);
+        if self.validate_checksum {
+            decoder.enable_checksum_validation();
+        }
+        Ok(Some(decoder))
+    }
+}
+
+/// A compressor for the algorithm chosen at runtime.
+enum Compressor {
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::bulk::Compressor<'static>),
+    #[cfg(feature = "lz4")]
+    Lz4 { dictionary: Vec<u8> },
+}
+
+impl Compressor {
+    fn new(compression: BlockCompression, dictionary: &[u8]) -> io::Result<Self> {
+        match compression {
+            #[cfg(feature = "zstd")]
+            BlockCompression::Zstd { level } => Ok(Self::Zstd(
+                zstd::bulk::Compressor::with_dictionary(level, dictionary)?,
+            )),
+            #[cfg(feature = "lz4")]
+            BlockCompression::Lz4 => Ok(Self::Lz4 {
+                dictionary: dictionary.to_vec(),
+            }),
+        }
+    }
+
+    #[inline]
+    fn compress(&mut self, block: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Self::Zstd(compressor) => compressor.compress(block),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 { dictionary } => {
+                Ok(lz4_flex::block::compress_with_dict(block, dictionary))
+            },
+        }
+    }
+}
+
+/// A decompressor for the algorithm recorded in the stream.
+enum Decompressor<'a> {
+    #[cfg(feature = "zstd")]
+    Zstd {
+        /// Created when the first block is decompressed, so an invalid
+        /// dictionary is reported as a block which could not be decompressed.
+        decompressor: Option<zstd::bulk::Decompressor<'a>>,
+        dictionary: &'a [u8],
+    },
+    #[cfg(feature = "lz4")]
+    Lz4 { dictionary: &'a [u8] },
+}
+
+impl<'a> Decompressor<'a> {
+    fn new(id: u8, dictionary: &'a [u8]) -> Result<Self, BlockError> {
+        match id {
+            #[cfg(feature = "zstd")]
+            1 => Ok(Self::Zstd {
+                decompressor: None,
+                dictionary,
+            }),
+            #[cfg(feature = "lz4")]
+            2 => Ok(Self::Lz4 { dictionary }),
+            id => Err(BlockError::UnsupportedCompression { id }),
+        }
+    }
+
+    #[inline]
+    fn decompress(
+        &mut self,
+        compressed: &[u8],
+        block: &mut [u8],
+    ) -> Result<usize, BoxedError> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Self::Zstd {
+                decompressor,
+                dictionary,
+            } => {
+                let decompressor = match decompressor {
+                    Some(decompressor) => decompressor,
+                    None => decompressor
+                        .insert(zstd::bulk::Decompressor::with_dictionary(dictionary)?),
+                };
+                Ok(decompressor.decompress_to_buffer(compressed, block)?)
+            },
+            #[cfg(feature = "lz4")]
+            Self::Lz4 { dictionary } => Ok(lz4_flex::block::decompress_into_with_dict(
+                compressed, block, dictionary,
+            )?),
+        }
+    }
+}
+
+impl From<BlockError> for Error {
+    fn from(e: BlockError) -> Self {
+        Self::Block(e)
+    }
+}
+
+#[inline]
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+#[inline]
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::documents;
+
+    fn compressions() -> Vec<BlockCompression> {
+        vec![
+            #[cfg(feature = "zstd")]
+            BlockCompression::Zstd { level: 3 },
+            #[cfg(feature = "lz4")]
+            BlockCompression::Lz4,
+        ]
+    }
+
+    fn encode_blocks(
+        documents: &[Document],
+        compression: BlockCompression,
+        max_documents: u32,
+        dictionary: Option<BlockDictionary>,
+    ) -> Vec<u8> {
+        let mut encoder =
+            BlockEncoder::<_, DEFAULT_SCRATCH_SPACE>::new(Vec::new(), compression);
+        encoder.set_max_block_documents(max_documents);
+        if let Some(dictionary) = dictionary {
+            encoder.set_dictionary(dictionary);
+        }
+        for document in documents {
+            encoder.encode(document).expect("Encode document");
+        }
+        encoder.finish().expect("Finish block stream")
+    }
+
+    /// Reads every document from every block, oldest first.
+    fn read_all(decoder: &mut BlockDecoder) -> Vec<Document> {
+        let mut returned = Vec::new();
+        for block in 0..decoder.blocks() {
+            let block = decoder
+                .read_block(block)
+                .expect("Read block")
+                .expect("Block should exist");
+            let mut documents = block
+                .deserializer_iter()
+                .collect::<Result<Vec<_>>>()
+                .expect("Every document should be readable");
+            documents.reverse();
+            returned.extend(documents);
+        }
+        returned
+    }
+
+    #[test]
+    fn test_block_roundtrip() {
+        let documents = documents(100);
+        for compression in compressions() {
+            let buf = encode_blocks(&documents, compression, 16, None);
+
+            let mut decoder = BlockDecoder::open(&buf).expect("Open block stream");
+            decoder.enable_checksum_validation();
+            assert_eq!(decoder.blocks(), 7);
+            assert_eq!(decoder.documents(), 100);
+            assert_eq!(read_all(&mut decoder), documents);
+
+            let entry = decoder.block_entry(6).unwrap();
+            assert_eq!(entry.first_document, 96);
+            assert_eq!(entry.documents, 4);
+            assert!(decoder.block_entry(7).is_none());
+            assert!(decoder.read_block(7).unwrap().is_none());
+
+            assert_eq!(decoder.find_block(0), Some(0));
+            assert_eq!(decoder.find_block(17), Some(1));
+            assert_eq!(decoder.find_block(99), Some(6));
+            assert_eq!(decoder.find_block(100), None);
+
+            let block = decoder.read_block(1).unwrap().unwrap();
+            let archived = unsafe { block.archived_iter() }
+                .next_back()
+                .unwrap()
+                .expect("Archive document");
+            assert_eq!(archived.get("id").and_then(|id| id.as_u64()), Some(16));
+        }
+    }
+
+    #[test]
+    fn test_block_size_limit() {
+        for compression in compressions() {
+            let mut encoder =
+                BlockEncoder::<_, DEFAULT_SCRATCH_SPACE>::new(Vec::new(), compression);
+            encoder.set_max_block_size(256);
+            for document in documents(20) {
+                encoder.encode(&document).expect("Encode document");
+            }
+            assert!(encoder.blocks().len() > 1, "Blocks should be split by size");
+            assert!(encoder.blocks().iter().all(|entry| entry.len >= 256));
+            let buf = encoder.finish().expect("Finish block stream");
+
+            let mut decoder = BlockDecoder::open(&buf).expect("Open block stream");
+            assert_eq!(read_all(&mut decoder), documents(20));
+        }
+    }
+
+    #[test]
+    fn test_canonical_block_stream() {
+        for compression in compressions() {
+            let mut encoder =
+                BlockEncoder::<_, DEFAULT_SCRATCH_SPACE>::new(Vec::new(), compression);
+            encoder.enable_canonical_layout();
+            for document in documents(4) {
+                encoder.encode(&document).expect("Encode document");
+            }
+            let buf = encoder.finish().expect("Finish block stream");
+
+            let mut decoder = BlockDecoder::open(&buf).expect("Open block stream");
+            assert!(decoder.is_canonical());
+            let block = decoder.read_block(0).unwrap().unwrap();
+            let archived = block.checked_archived_iter().next().unwrap().unwrap();
+            let canonical = block
+                .as_canonical(archived)
+                .expect("Block should be canonical");
+            assert_eq!(canonical.get_u64("id"), Some(3));
+
+            let buf = encode_blocks(&documents(4), compression, 16, None);
+            let mut decoder = BlockDecoder::open(&buf).expect("Open block stream");
+            assert!(!decoder.is_canonical());
+            let block = decoder.read_block(0).unwrap().unwrap();
+            let archived = block.checked_archived_iter().next().unwrap().unwrap();
+            assert!(block.as_canonical(archived).is_none());
+        }
+    }
+
+    #[test]
+    fn test_empty_block_stream() {
+        for compression in compressions() {
+            let buf = encode_blocks(&[], compression, 16, None);
+            assert_eq!(buf.len(), BLOCK_TRAILER_SIZE);
+
+            let mut decoder = BlockDecoder::open(&buf).expect("Open block stream");
+            assert_eq!(decoder.blocks(), 0);
+            assert_eq!(decoder.documents(), 0);
+            assert!(decoder.read_block(0).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_block_dictionary() {
+        let documents = documents(1000);
+        let dictionary =
+            BlockDictionary::train(&documents, 4 << 10).expect("Train dictionary");
+
+        for compression in compressions() {
+            let plain = encode_blocks(&documents, compression, 1, None);
+            let buf =
+                encode_blocks(&documents, compression, 1, Some(dictionary.clone()));
+            assert!(
+                buf.len() < plain.len(),
+                "{compression:?} should compress small blocks better with a dictionary"
+            );
+
+            let mut decoder = BlockDecoder::open(&buf).expect("Open block stream");
+            assert_eq!(decoder.blocks(), 1000);
+            assert_eq!(read_all(&mut decoder), documents);
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_corrupt_dictionary() {
+        let documents = documents(1000);
+        let dictionary =
+            BlockDictionary::train(&documents, 4 << 10).expect("Train dictionary");
+        let compression = BlockCompression::Zstd { level: 3 };
+        let mut buf =
+            encode_blocks(&documents, compression, 16, Some(dictionary.clone()));
+
+        let blocks = BlockDecoder::open(&buf)
+            .expect("Open block stream")
+            .blocks();
+        let dictionary_start = buf.len()
+            - BLOCK_TRAILER_SIZE
+            - blocks * BLOCK_INDEX_ENTRY_SIZE
+            - dictionary.as_bytes().len();
+        // Corrupt the entropy tables following the magic bytes and id.
+        buf[dictionary_start + 8..dictionary_start + 64].fill(0xFF);
+
+        let mut decoder = BlockDecoder::open(&buf)
+            .expect("The dictionary should only be loaded when reading a block");
+        assert!(matches!(
+            decoder.read_block(0),
+            Err(Error::Block(BlockError::Decompress { block: 0, .. }))
+        ));
+    }
+
+    #[test]
+    fn test_corrupt_block_stream() {
+        for compression in compressions() {
+            let buf = encode_blocks(&documents(32), compression, 16, None);
+
+            let mut corrupt = buf.clone();
+            corrupt[0] ^= 0xFF;
+            let mut decoder = BlockDecoder::open(&corrupt).expect("Open block stream");
+            assert!(matches!(
+                decoder.read_block(0),
+                Err(Error::Block(BlockError::BlockChecksumMismatch {
+                    block: 0,
+                    ..
+                }))
+            ));
+            assert!(
+                decoder.read_block(1).is_ok(),
+                "Other blocks should be unaffected"
+            );
+
+            let mut corrupt = buf.clone();
+            let index_entry = corrupt.len() - BLOCK_TRAILER_SIZE - 1;
+            corrupt[index_entry] ^= 0xFF;
+            assert!(matches!(
+                BlockDecoder::open(&corrupt),
+                Err(Error::Block(BlockError::ChecksumMismatch { .. }))
+            ));
+
+            let mut corrupt = buf.clone();
+            let len = corrupt.len();
+            corrupt[len - 1] = b'X';
+            assert!(matches!(
+                BlockDecoder::open(&corrupt),
+                Err(Error::Block(BlockError::BadMagic))
+            ));
+
+            let mut corrupt = buf.clone();
+            corrupt[len - BLOCK_TRAILER_SIZE + 28] = 0xFF;
+            assert!(matches!(
+                BlockDecoder::open(&corrupt),
+                Err(Error::Block(BlockError::ChecksumMismatch { .. }))
+            ));
+
+            assert!(matches!(
+                BlockDecoder::open(&buf[..BLOCK_TRAILER_SIZE - 1]),
+                Err(Error::Block(BlockError::Truncated { .. }))
+            ));
+        }
+    }
+
+    /// Rewrites the checksum of the block index and trailer.
+    fn reseal(buf: &mut [u8], blocks: usize) {
+        let trailer_start = buf.len() - BLOCK_TRAILER_SIZE;
+        let index_start = trailer_start - blocks * BLOCK_INDEX_ENTRY_SIZE;
+        let checksum = crc32fast::hash(&buf[index_start..trailer_start + 32]);
+        buf[trailer_start + 32..trailer_start + 36]
+            .copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn test_block_length_limit() {
+        for compression in compressions() {
+            let mut buf = encode_blocks(&documents(32), compression, 16, None);
+            let mut decoder = BlockDecoder::open(&buf).expect("Open block stream");
+            let largest = decoder.block_entry(0).unwrap().len;
+            decoder.set_max_block_len(largest - 1);
+            assert!(matches!(
+                decoder.read_block(0),
+                Err(Error::Block(BlockError::BlockTooLarge { block: 0, max, .. }))
+                    if max == largest - 1
+            ));
+            drop(decoder);
+
+            // A hostile length must be rejected before anything is allocated.
+            let len_start = buf.len() - BLOCK_TRAILER_SIZE - BLOCK_INDEX_ENTRY_SIZE + 16;
+            buf[len_start..len_start + 8].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+            reseal(&mut buf, 2);
+            let mut decoder = BlockDecoder::open(&buf).expect("Open block stream");
+            assert!(matches!(
+                decoder.read_block(1),
+                Err(Error::Block(BlockError::BlockTooLarge { block: 1, .. }))
+            ));
+            assert!(decoder.read_block(0).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_block_checksum_algorithms() {
+        let documents = documents(20);
+        for compression in compressions() {
+            for algorithm in [
+                ChecksumAlgorithm::Crc32,
+                #[cfg(feature = "xxh3")]
+                ChecksumAlgorithm::Xxh3,
+                ChecksumAlgorithm::None,
+            ] {
+                let mut encoder = BlockEncoder::<_, DEFAULT_SCRATCH_SPACE>::new(
+                    Vec::new(),
+                    compression,
+                );
+                encoder.set_checksum_algorithm(algorithm);
+                encoder.set_max_block_documents(8);
+                for document in &documents {
+                    encoder.encode(document).expect("Encode document");
+                }
+                let buf = encoder.finish().expect("Finish block stream");
+
+                let mut decoder = BlockDecoder::open(&buf).expect("Open block stream");
+                decoder.enable_checksum_validation();
+                assert_eq!(decoder.checksum_algorithm(), algorithm);
+                assert_eq!(read_all(&mut decoder), documents);
+
+                let mut corrupt = buf.clone();
+                corrupt[0] ^= 0xFF;
+                let mut decoder =
+                    BlockDecoder::open(&corrupt).expect("Open block stream");
+                let res = decoder.read_block(0);
+                if algorithm == ChecksumAlgorithm::None {
+                    assert!(
+                        !matches!(
+                            res,
+                            Err(Error::Block(BlockError::BlockChecksumMismatch { .. }))
+                        ),
+                        "Blocks should not be checksummed"
+                    );
+                } else {
+                    assert!(matches!(
+                        res,
+                        Err(Error::Block(BlockError::BlockChecksumMismatch {
+                            block: 0,
+                            ..
+                        }))
+                    ));
+                }
+            }
+        }
+    }
+}
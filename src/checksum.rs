@@ -0,0 +1,344 @@
+//! The checksum algorithms used to verify each document
+//!
+//! The algorithm is chosen when encoding and recorded in the segment header,
+//! so [Decoder::open](crate::Decoder::open) verifies documents with the same
+//! algorithm automatically.
+//!
+//! CRC32C and xxHash3 are enabled by the `crc32c` and `xxh3` features.
+
+/// A checksum algorithm which can be used for each document's footer.
+pub trait Checksum: Default + private::Sealed {
+    /// The algorithm, as recorded in the stream.
+    const ALGORITHM: ChecksumAlgorithm;
+
+    /// Adds the bytes to the checksum.
+    fn update(&mut self, bytes: &[u8]);
+
+    /// The checksum of every byte added so far.
+    ///
+    /// 32 bit checksums are zero extended.
+    fn finish(&self) -> u64;
+
+    #[inline]
+    /// Calculates the checksum of the given bytes.
+    fn checksum(bytes: &[u8]) -> u64 {
+        let mut checksum = Self::default();
+        checksum.update(bytes);
+        checksum.finish()
+    }
+}
+
+mod private {
+    pub trait Sealed {}
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+/// The checksum algorithms which can be recorded in the stream.
+///
+/// Some algorithms are only available with their feature enabled, so matches
+/// outside of this crate must handle algorithms they do not know of.
+pub enum ChecksumAlgorithm {
+    #[default]
+    /// A 32 bit CRC, see [Crc32].
+    Crc32,
+    #[cfg(feature = "crc32c")]
+    /// A 32 bit CRC using the Castagnoli polynomial, see [Crc32c].
+    Crc32c,
+    #[cfg(feature = "xxh3")]
+    /// The 64 bit xxHash3, see [Xxh3].
+    Xxh3,
+    /// No checksum is written, see [NoChecksum].
+    None,
+}
+
+impl ChecksumAlgorithm {
+    #[inline]
+    /// The id of the algorithm written to the stream.
+    pub fn id(self) -> u8 {
+        match self {
+            Self::Crc32 => 0,
+            #[cfg(feature = "crc32c")]
+            Self::Crc32c => 1,
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3 => 2,
+            Self::None => 3,
+        }
+    }
+
+    #[inline]
+    /// Gets the algorithm with the given id.
+    ///
+    /// Algorithms whose feature is not enabled are unknown to this build.
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Self::Crc32),
+            #[cfg(feature = "crc32c")]
+            1 => Some(Self::Crc32c),
+            #[cfg(feature = "xxh3")]
+            2 => Some(Self::Xxh3),
+            3 => Some(Self::None),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    /// The number of bytes the checksum takes up in each footer.
+    pub fn size(self) -> usize {
+        match self {
+            Self::Crc32 => 4,
+            #[cfg(feature = "crc32c")]
+            Self::Crc32c => 4,
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3 => 8,
+            Self::None => 0,
+        }
+    }
+
+    #[inline]
+    /// Calculates the checksum of the given bytes.
+    pub fn checksum(self, bytes: &[u8]) -> u64 {
+        match self {
+            Self::Crc32 => Crc32::checksum(bytes),
+            #[cfg(feature = "crc32c")]
+            Self::Crc32c => Crc32c::checksum(bytes),
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3 => Xxh3::checksum(bytes),
+            Self::None => NoChecksum::checksum(bytes),
+        }
+    }
+
+    /// Creates an incremental hasher for the algorithm.
+    pub(crate) fn hasher(self) -> ChecksumHasher {
+        match self {
+            Self::Crc32 => ChecksumHasher::Crc32(Crc32::default()),
+            #[cfg(feature = "crc32c")]
+            Self::Crc32c => ChecksumHasher::Crc32c(Crc32c::default()),
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3 => ChecksumHasher::Xxh3(Box::default()),
+            Self::None => ChecksumHasher::None,
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+/// A 32 bit CRC, the default algorithm.
+pub struct Crc32(crc32fast::Hasher);
+
+impl private::Sealed for Crc32 {}
+
+impl Checksum for Crc32 {
+    const ALGORITHM: ChecksumAlgorithm = ChecksumAlgorithm::Crc32;
+
+    #[inline]
+    fn update(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0.clone().finalize() as u64
+    }
+}
+
+#[cfg(feature = "crc32c")]
+#[derive(Debug, Default, Copy, Clone)]
+/// A 32 bit CRC using the Castagnoli polynomial, which is hardware
+/// accelerated on most targets.
+pub struct Crc32c(u32);
+
+#[cfg(feature = "crc32c")]
+impl private::Sealed for Crc32c {}
+
+#[cfg(feature = "crc32c")]
+impl Checksum for Crc32c {
+    const ALGORITHM: ChecksumAlgorithm = ChecksumAlgorithm::Crc32c;
+
+    #[inline]
+    fn update(&mut self, bytes: &[u8]) {
+        self.0 = crc32c::crc32c_append(self.0, bytes);
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0 as u64
+    }
+}
+
+#[cfg(feature = "xxh3")]
+#[derive(Clone)]
+/// The 64 bit xxHash3, which is faster and stronger than the 32 bit CRCs
+/// for large documents.
+pub struct Xxh3(xxhash_rust::xxh3::Xxh3);
+
+#[cfg(feature = "xxh3")]
+impl Default for Xxh3 {
+    fn default() -> Self {
+        Self(xxhash_rust::xxh3::Xxh3::new())
+    }
+}
+
+#[cfg(feature = "xxh3")]
+impl private::Sealed for Xxh3 {}
+
+#[cfg(feature = "xxh3")]
+impl Checksum for Xxh3 {
+    const ALGORITHM: ChecksumAlgorithm = ChecksumAlgorithm::Xxh3;
+
+    #[inline]
+    fn update(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0.digest()
+    }
+
+    #[inline]
+    fn checksum(bytes: &[u8]) -> u64 {
+        xxhash_rust::xxh3::xxh3_64(bytes)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+/// Writes no checksum, documents are never rejected by checksum validation.
+pub struct NoChecksum;
+
+impl private::Sealed for NoChecksum {}
+
+impl Checksum for NoChecksum {
+    const ALGORITHM: ChecksumAlgorithm = ChecksumAlgorithm::None;
+
+    #[inline]
+    fn update(&mut self, _bytes: &[u8]) {}
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        0
+    }
+}
+
+/// An incremental hasher for an algorithm chosen at runtime.
+pub(crate) enum ChecksumHasher {
+    Crc32(Crc32),
+    #[cfg(feature = "crc32c")]
+    Crc32c(Crc32c),
+    #[cfg(feature = "xxh3")]
+    Xxh3(Box<Xxh3>),
+    None,
+}
+
+impl ChecksumHasher {
+    #[inline]
+    /// The algorithm the hasher calculates.
+    pub(crate) fn algorithm(&self) -> ChecksumAlgorithm {
+        match self {
+            Self::Crc32(_) => Crc32::ALGORITHM,
+            #[cfg(feature = "crc32c")]
+            Self::Crc32c(_) => Crc32c::ALGORITHM,
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3(_) => Xxh3::ALGORITHM,
+            Self::None => NoChecksum::ALGORITHM,
+        }
+    }
+
+    #[inline]
+    /// Adds the bytes to the checksum.
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Crc32(checksum) => checksum.update(bytes),
+            #[cfg(feature = "crc32c")]
+            Self::Crc32c(checksum) => checksum.update(bytes),
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3(checksum) => checksum.update(bytes),
+            Self::None => {},
+        }
+    }
+
+    #[inline]
+    /// Returns the checksum of every byte added since the last reset.
+    pub(crate) fn finish(&self) -> u64 {
+        match self {
+            Self::Crc32(checksum) => checksum.finish(),
+            #[cfg(feature = "crc32c")]
+            Self::Crc32c(checksum) => checksum.finish(),
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3(checksum) => checksum.finish(),
+            Self::None => 0,
+        }
+    }
+
+    #[inline]
+    /// Resets the hasher, keeping any allocation.
+    pub(crate) fn reset(&mut self) {
+        match self {
+            Self::Crc32(checksum) => *checksum = Crc32::default(),
+            #[cfg(feature = "crc32c")]
+            Self::Crc32c(checksum) => *checksum = Crc32c::default(),
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3(checksum) => checksum.0.reset(),
+            Self::None => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHMS: &[ChecksumAlgorithm] = &[
+        ChecksumAlgorithm::Crc32,
+        #[cfg(feature = "crc32c")]
+        ChecksumAlgorithm::Crc32c,
+        #[cfg(feature = "xxh3")]
+        ChecksumAlgorithm::Xxh3,
+        ChecksumAlgorithm::None,
+    ];
+
+    #[test]
+    fn test_algorithm_ids() {
+        for &algorithm in ALGORITHMS {
+            assert_eq!(ChecksumAlgorithm::from_id(algorithm.id()), Some(algorithm));
+        }
+        assert_eq!(ChecksumAlgorithm::from_id(4), None);
+    }
+
+    #[test]
+    fn test_incremental_checksums() {
+        let data = b"Hello, world! This is a document.";
+        assert_eq!(
+            ChecksumAlgorithm::Crc32.checksum(data),
+            crc32fast::hash(data) as u64
+        );
+        #[cfg(feature = "crc32c")]
+        assert_eq!(
+            ChecksumAlgorithm::Crc32c.checksum(data),
+            crc32c::crc32c(data) as u64
+        );
+        #[cfg(feature = "xxh3")]
+        assert_eq!(
+            ChecksumAlgorithm::Xxh3.checksum(data),
+            xxhash_rust::xxh3::xxh3_64(data)
+        );
+
+        for &algorithm in ALGORITHMS {
+            let mut hasher = algorithm.hasher();
+            assert_eq!(hasher.algorithm(), algorithm);
+            hasher.update(b"garbage");
+            hasher.reset();
+            for chunk in data.chunks(5) {
+                hasher.update(chunk);
+            }
+            assert_eq!(
+                hasher.finish(),
+                algorithm.checksum(data),
+                "Incremental {algorithm:?} checksum should match"
+            );
+            assert!(
+                (hasher.finish() as u128) < 1 << (algorithm.size() * 8),
+                "{algorithm:?} checksum should fit in its footer"
+            );
+        }
+    }
+}
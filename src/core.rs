@@ -10,16 +10,32 @@ use std::time::Duration;
 
 use rkyv::{Archive, Deserialize, Serialize};
 
+mod canonical;
+
+pub(crate) use self::canonical::Canonical;
+
 #[repr(C)]
-#[derive(Archive, Serialize, Deserialize, Default, PartialEq, Debug)]
-#[archive_attr(repr(C))]
+#[derive(Archive, Serialize, Deserialize, Default, Clone, Debug)]
+#[archive_attr(repr(C), derive(Debug))]
 #[cfg_attr(any(feature = "validation", test), archive(check_bytes))]
 /// A wrapper around a given set of document object keys and values.
 ///
 /// The document has a specialised ID field but this is not set by default.
+///
+/// Documents in the canonical layout have their fields, and any nested
+/// object entries, sorted by key which allows lookups to binary search
+/// rather than scan the fields.
+///
+/// Whether the document is canonical is not archived and is ignored when
+/// comparing documents, so looking up a key in an [ArchivedDocument] always
+/// scans its fields. Segments and block streams record the layout, their
+/// documents can be viewed as a [CanonicalDocument](crate::CanonicalDocument)
+/// with [Decoder::as_canonical](crate::Decoder::as_canonical).
 pub struct Document {
     id: u64,
     fields: Vec<(Text, Value)>,
+    #[with(rkyv::with::Skip)]
+    canonical: bool,
 }
 
 impl Document {
@@ -28,6 +44,7 @@ impl Document {
         Self {
             id: 0,
             fields: Vec::with_capacity(capacity),
+            canonical: false,
         }
     }
 
@@ -57,15 +74,35 @@ impl Document {
 
     #[inline]
     /// Get a mutable reference to the document fields.
+    ///
+    /// This removes the document from the canonical layout.
     pub fn fields_mut(&mut self) -> &mut [(Text, Value)] {
+        self.canonical = false;
         &mut self.fields
     }
 
     #[inline]
     /// Insert a new entry in the doc.
+    ///
+    /// This removes the document from the canonical layout.
     pub fn insert(&mut self, key: impl Into<Text>, value: Value) {
+        self.canonical = false;
         self.fields.push((key.into(), value));
     }
+
+    #[inline]
+    /// Returns if the document is in the canonical sorted-key layout.
+    pub fn is_canonical(&self) -> bool {
+        self.canonical
+    }
+
+    /// Sorts the document fields and any nested object entries by key.
+    ///
+    /// The sort is stable so entries sharing a key keep their insertion order.
+    pub fn canonicalize(&mut self) {
+        canonicalize_entries(&mut self.fields);
+        self.canonical = true;
+    }
 }
 
 impl ArchivedDocument {
@@ -82,16 +119,30 @@ impl ArchivedDocument {
     }
 }
 
+impl PartialEq for Document {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.fields == other.fields
+    }
+}
+
 impl From<Vec<(Text, Value)>> for Document {
     fn from(value: Vec<(Text, Value)>) -> Self {
         Self {
             id: 0,
             fields: value,
+            canonical: false,
         }
     }
 }
 
-#[derive(Archive, Serialize, Deserialize, Debug, PartialEq)]
+fn canonicalize_entries(entries: &mut [(Text, Value)]) {
+    entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+    for (_, value) in entries {
+        value.canonicalize();
+    }
+}
+
+#[derive(Archive, Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
 #[cfg_attr(any(feature = "validation", test), archive(check_bytes))]
 #[cfg_attr(
@@ -140,6 +191,9 @@ pub enum Value {
     /// This is much less performant than using
     /// concrete types, this is only for supporting
     /// the JSON spec.
+    ///
+    /// Arrays containing nulls are always dynamic as
+    /// the typed arrays cannot represent a null.
     ArrayDynamic(
         #[omit_bounds]
         #[cfg_attr(any(feature = "validation", test), archive_attr(omit_bounds))]
@@ -156,28 +210,171 @@ pub enum Value {
 impl Value {
     /// Get the string representation of the enum type.
     pub fn as_type(&self) -> &'static str {
+        self.kind().as_type()
+    }
+
+    /// Get the kind of value stored.
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            Value::Null => ValueKind::Null,
+            Value::Bool(_) => ValueKind::Bool,
+            Value::String(_) => ValueKind::String,
+            Value::Bytes(_) => ValueKind::Bytes,
+            Value::U64(_) => ValueKind::U64,
+            Value::I64(_) => ValueKind::I64,
+            Value::F64(_) => ValueKind::F64,
+            Value::Date(_) => ValueKind::Date,
+            Value::ArrayBool(_) => ValueKind::ArrayBool,
+            Value::ArrayString(_) => ValueKind::ArrayString,
+            Value::ArrayBytes(_) => ValueKind::ArrayBytes,
+            Value::ArrayU64(_) => ValueKind::ArrayU64,
+            Value::ArrayI64(_) => ValueKind::ArrayI64,
+            Value::ArrayF64(_) => ValueKind::ArrayF64,
+            Value::ArrayDate(_) => ValueKind::ArrayDate,
+            Value::ArrayDynamic(_) => ValueKind::ArrayDynamic,
+            Value::Object(_) => ValueKind::Object,
+        }
+    }
+
+    fn canonicalize(&mut self) {
         match self {
-            Value::Null => "null",
-            Value::Bool(_) => "bool",
-            Value::String(_) => "string",
-            Value::Bytes(_) => "bytes",
-            Value::U64(_) => "u64",
-            Value::I64(_) => "i64",
-            Value::F64(_) => "f64",
-            Value::Date(_) => "datetime",
-            Value::ArrayBool(_) => "array<bool>",
-            Value::ArrayString(_) => "array<string>",
-            Value::ArrayBytes(_) => "array<bytes>",
-            Value::ArrayU64(_) => "array<u64>",
-            Value::ArrayI64(_) => "array<i64>",
-            Value::ArrayF64(_) => "array<f64>",
-            Value::ArrayDate(_) => "array<datetime>",
-            Value::ArrayDynamic(_) => "array<any>",
-            Value::Object(_) => "object",
+            Value::Object(entries) => canonicalize_entries(entries),
+            Value::ArrayDynamic(values) => {
+                values.iter_mut().for_each(Value::canonicalize)
+            },
+            _ => {},
         }
     }
 }
 
+impl ArchivedValue {
+    /// Get the string representation of the enum type.
+    pub fn as_type(&self) -> &'static str {
+        self.kind().as_type()
+    }
+
+    /// Get the kind of value stored.
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            ArchivedValue::Null => ValueKind::Null,
+            ArchivedValue::Bool(_) => ValueKind::Bool,
+            ArchivedValue::String(_) => ValueKind::String,
+            ArchivedValue::Bytes(_) => ValueKind::Bytes,
+            ArchivedValue::U64(_) => ValueKind::U64,
+            ArchivedValue::I64(_) => ValueKind::I64,
+            ArchivedValue::F64(_) => ValueKind::F64,
+            ArchivedValue::Date(_) => ValueKind::Date,
+            ArchivedValue::ArrayBool(_) => ValueKind::ArrayBool,
+            ArchivedValue::ArrayString(_) => ValueKind::ArrayString,
+            ArchivedValue::ArrayBytes(_) => ValueKind::ArrayBytes,
+            ArchivedValue::ArrayU64(_) => ValueKind::ArrayU64,
+            ArchivedValue::ArrayI64(_) => ValueKind::ArrayI64,
+            ArchivedValue::ArrayF64(_) => ValueKind::ArrayF64,
+            ArchivedValue::ArrayDate(_) => ValueKind::ArrayDate,
+            ArchivedValue::ArrayDynamic(_) => ValueKind::ArrayDynamic,
+            ArchivedValue::Object(_) => ValueKind::Object,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// The kind of a [Value] without any of its data.
+pub enum ValueKind {
+    Null,
+    Bool,
+    String,
+    Bytes,
+    U64,
+    I64,
+    F64,
+    Date,
+    ArrayBool,
+    ArrayString,
+    ArrayBytes,
+    ArrayU64,
+    ArrayI64,
+    ArrayF64,
+    ArrayDate,
+    ArrayDynamic,
+    Object,
+}
+
+impl ValueKind {
+    /// Every kind of value.
+    pub const ALL: [ValueKind; 17] = [
+        ValueKind::Null,
+        ValueKind::Bool,
+        ValueKind::String,
+        ValueKind::Bytes,
+        ValueKind::U64,
+        ValueKind::I64,
+        ValueKind::F64,
+        ValueKind::Date,
+        ValueKind::ArrayBool,
+        ValueKind::ArrayString,
+        ValueKind::ArrayBytes,
+        ValueKind::ArrayU64,
+        ValueKind::ArrayI64,
+        ValueKind::ArrayF64,
+        ValueKind::ArrayDate,
+        ValueKind::ArrayDynamic,
+        ValueKind::Object,
+    ];
+
+    /// Get the string representation of the kind, matching `Value::as_type`.
+    pub fn as_type(&self) -> &'static str {
+        match self {
+            ValueKind::Null => "null",
+            ValueKind::Bool => "bool",
+            ValueKind::String => "string",
+            ValueKind::Bytes => "bytes",
+            ValueKind::U64 => "u64",
+            ValueKind::I64 => "i64",
+            ValueKind::F64 => "f64",
+            ValueKind::Date => "datetime",
+            ValueKind::ArrayBool => "array<bool>",
+            ValueKind::ArrayString => "array<string>",
+            ValueKind::ArrayBytes => "array<bytes>",
+            ValueKind::ArrayU64 => "array<u64>",
+            ValueKind::ArrayI64 => "array<i64>",
+            ValueKind::ArrayF64 => "array<f64>",
+            ValueKind::ArrayDate => "array<datetime>",
+            ValueKind::ArrayDynamic => "array<any>",
+            ValueKind::Object => "object",
+        }
+    }
+
+    /// Get the kind from its `Value::as_type` name.
+    pub fn from_type(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.as_type() == name)
+    }
+
+    /// Returns if the kind is one of the array variants.
+    pub fn is_array(&self) -> bool {
+        self.element().is_some() || *self == ValueKind::ArrayDynamic
+    }
+
+    /// Get the element kind of the typed array variants.
+    pub fn element(&self) -> Option<ValueKind> {
+        match self {
+            ValueKind::ArrayBool => Some(ValueKind::Bool),
+            ValueKind::ArrayString => Some(ValueKind::String),
+            ValueKind::ArrayBytes => Some(ValueKind::Bytes),
+            ValueKind::ArrayU64 => Some(ValueKind::U64),
+            ValueKind::ArrayI64 => Some(ValueKind::I64),
+            ValueKind::ArrayF64 => Some(ValueKind::F64),
+            ValueKind::ArrayDate => Some(ValueKind::Date),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ValueKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_type())
+    }
+}
+
 impl Display for Value {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.as_type())
@@ -271,7 +468,7 @@ impl Debug for ArchivedValue {
 }
 
 #[repr(C)]
-#[derive(Archive, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Archive, Serialize, Deserialize, Clone, Eq, PartialEq)]
 #[archive_attr(repr(C))]
 #[cfg_attr(any(feature = "validation", test), archive(check_bytes))]
 /// A UTF-8 encoded string.
@@ -342,7 +539,7 @@ impl Display for ArchivedText {
 }
 
 #[repr(C)]
-#[derive(Archive, Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Archive, Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[archive_attr(repr(C), derive(Debug))]
 #[cfg_attr(any(feature = "validation", test), archive(check_bytes))]
 /// An arbitrary byte slice backed by a `Cow`
@@ -359,3 +556,9 @@ impl AsRef<[u8]> for Bytes {
         self.0.as_ref()
     }
 }
+
+impl AsRef<[u8]> for ArchivedBytes {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
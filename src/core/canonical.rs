@@ -0,0 +1,131 @@
+//! Canonical layout serialization
+//!
+//! These wrappers archive a borrowed document in the same layout as the
+//! derived implementations, only emitting object entries in key order, this
+//! lets the encoder produce a canonical archive without needing to clone or
+//! mutate the document it is given.
+
+use rkyv::ser::{ScratchSpace, Serializer};
+use rkyv::vec::{ArchivedVec, VecResolver};
+use rkyv::{out_field, Archive, Archived, Resolver, Serialize};
+
+use super::{ArchivedDocument, Document, DocumentResolver, Text, Value, ValueResolver};
+
+/// A wrapper which archives the inner value in the canonical sorted-key layout.
+pub(crate) struct Canonical<'a, T>(pub(crate) &'a T);
+
+impl Archive for Canonical<'_, Document> {
+    type Archived = ArchivedDocument;
+    type Resolver = DocumentResolver;
+
+    #[inline]
+    unsafe fn resolve(
+        &self,
+        pos: usize,
+        resolver: Self::Resolver,
+        out: *mut Self::Archived,
+    ) {
+        let (fp, fo) = out_field!(out.id);
+        self.0.id.resolve(pos + fp, (), fo);
+        let (fp, fo) = out_field!(out.fields);
+        ArchivedVec::resolve_from_len(
+            self.0.fields.len(),
+            pos + fp,
+            resolver.fields,
+            fo,
+        );
+    }
+}
+
+impl<S: ScratchSpace + Serializer + ?Sized> Serialize<S> for Canonical<'_, Document> {
+    #[inline]
+    fn serialize(&self, serializer: &mut S) -> Result<Self::Resolver, S::Error> {
+        if self.0.canonical {
+            return self.0.serialize(serializer);
+        }
+
+        Ok(DocumentResolver {
+            id: self.0.id.serialize(serializer)?,
+            fields: serialize_entries(&self.0.fields, serializer)?,
+            canonical: (),
+        })
+    }
+}
+
+impl Archive for Canonical<'_, Value> {
+    type Archived = Archived<Value>;
+    type Resolver = ValueResolver;
+
+    #[inline]
+    unsafe fn resolve(
+        &self,
+        pos: usize,
+        resolver: Self::Resolver,
+        out: *mut Self::Archived,
+    ) {
+        // Re-ordering entries does not change their count, which is all the
+        // derived implementation requires to resolve the archived value.
+        self.0.resolve(pos, resolver, out)
+    }
+}
+
+impl<S: ScratchSpace + Serializer + ?Sized> Serialize<S> for Canonical<'_, Value> {
+    #[inline]
+    fn serialize(&self, serializer: &mut S) -> Result<Self::Resolver, S::Error> {
+        match self.0 {
+            Value::Object(entries) => Ok(ValueResolver::Object(serialize_entries(
+                entries, serializer,
+            )?)),
+            Value::ArrayDynamic(values) => {
+                let resolver =
+                    ArchivedVec::serialize_from_iter::<Canonical<Value>, _, _, _>(
+                        values.iter().map(Canonical),
+                        serializer,
+                    )?;
+                Ok(ValueResolver::ArrayDynamic(resolver))
+            },
+            other => other.serialize(serializer),
+        }
+    }
+}
+
+impl Archive for Canonical<'_, (Text, Value)> {
+    type Archived = Archived<(Text, Value)>;
+    type Resolver = Resolver<(Text, Value)>;
+
+    #[inline]
+    unsafe fn resolve(
+        &self,
+        pos: usize,
+        resolver: Self::Resolver,
+        out: *mut Self::Archived,
+    ) {
+        self.0.resolve(pos, resolver, out)
+    }
+}
+
+impl<S: ScratchSpace + Serializer + ?Sized> Serialize<S>
+    for Canonical<'_, (Text, Value)>
+{
+    #[inline]
+    fn serialize(&self, serializer: &mut S) -> Result<Self::Resolver, S::Error> {
+        // Tuples are serialized back to front, this must match for the
+        // archive to pass validation.
+        let value = Canonical(&self.0 .1).serialize(serializer)?;
+        let key = self.0 .0.serialize(serializer)?;
+        Ok((key, value))
+    }
+}
+
+fn serialize_entries<S: ScratchSpace + Serializer + ?Sized>(
+    entries: &[(Text, Value)],
+    serializer: &mut S,
+) -> Result<VecResolver, S::Error> {
+    let mut sorted = entries.iter().collect::<Vec<_>>();
+    sorted.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+
+    ArchivedVec::serialize_from_iter::<Canonical<(Text, Value)>, _, _, _>(
+        sorted.into_iter().map(Canonical),
+        serializer,
+    )
+}
@@ -0,0 +1,238 @@
+//! Date helpers for the microsecond offsets stored in `Value::Date`
+//!
+//! The civil calendar conversions follow Howard Hinnant's `days_from_civil`
+//! algorithms which are exact over the whole proleptic Gregorian calendar.
+
+use std::fmt;
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// The range of microsecond offsets which can be represented in RFC3339,
+/// `0000-01-01T00:00:00Z` to `9999-12-31T23:59:59.999999Z`.
+const RFC3339_RANGE: std::ops::RangeInclusive<i64> =
+    -62_167_219_200_000_000..=253_402_300_799_999_999;
+
+/// Displays a microsecond offset from `UNIX_EPOCH` as an RFC3339 UTC timestamp.
+pub(crate) struct Rfc3339(pub(crate) i64);
+
+impl Rfc3339 {
+    /// Returns if the offset can be displayed as a four digit year.
+    pub(crate) fn is_representable(&self) -> bool {
+        RFC3339_RANGE.contains(&self.0)
+    }
+}
+
+impl fmt::Display for Rfc3339 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.0.div_euclid(MICROS_PER_SECOND);
+        let micros = self.0.rem_euclid(MICROS_PER_SECOND);
+        let days = seconds.div_euclid(SECONDS_PER_DAY);
+        let second_of_day = seconds.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+
+        write!(
+            f,
+            "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+            second_of_day / 3600,
+            second_of_day % 3600 / 60,
+            second_of_day % 60,
+        )?;
+
+        if micros != 0 {
+            write!(f, ".{micros:06}")?;
+        }
+
+        write!(f, "Z")
+    }
+}
+
+/// Parses an RFC3339 or ISO-8601 timestamp into a microsecond offset from
+/// `UNIX_EPOCH`.
+///
+/// Both full timestamps with a `Z` or numeric UTC offset and plain
+/// `YYYY-MM-DD` dates, taken as midnight UTC, are accepted. Fractions beyond
+/// microsecond precision are truncated.
+pub(crate) fn parse_rfc3339(s: &str) -> Option<i64> {
+    let b = s.as_bytes();
+    if b.len() < 10 || b[4] != b'-' || b[7] != b'-' {
+        return None;
+    }
+
+    let year = digits(&b[0..4])? as i64;
+    let month = digits(&b[5..7])?;
+    let day = digits(&b[8..10])?;
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    if b.len() == 10 {
+        return Some(days * SECONDS_PER_DAY * MICROS_PER_SECOND);
+    }
+
+    if b.len() < 20
+        || !matches!(b[10], b'T' | b't' | b' ')
+        || b[13] != b':'
+        || b[16] != b':'
+    {
+        return None;
+    }
+    let hour = digits(&b[11..13])? as i64;
+    let minute = digits(&b[14..16])? as i64;
+    let second = digits(&b[17..19])? as i64;
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    let mut rest = &b[19..];
+    let mut micros = 0;
+    if let [b'.', fraction @ ..] = rest {
+        let len = fraction.iter().take_while(|c| c.is_ascii_digit()).count();
+        if len == 0 || len > 9 {
+            return None;
+        }
+        let precision = len.min(6);
+        micros =
+            digits(&fraction[..precision])? as i64 * 10i64.pow(6 - precision as u32);
+        rest = &fraction[len..];
+    }
+
+    let offset = match rest {
+        [b'Z' | b'z'] => 0,
+        [sign @ (b'+' | b'-'), h1, h2, b':', m1, m2]
+        | [sign @ (b'+' | b'-'), h1, h2, m1, m2] => {
+            let hours = digits(&[*h1, *h2])? as i64;
+            let minutes = digits(&[*m1, *m2])? as i64;
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            let offset = hours * 3600 + minutes * 60;
+            if *sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        },
+        _ => return None,
+    };
+
+    let seconds = days * SECONDS_PER_DAY + hour * 3600 + minute * 60 + second - offset;
+    Some(seconds * MICROS_PER_SECOND + micros)
+}
+
+/// Parses a run of ASCII digits.
+fn digits(b: &[u8]) -> Option<u32> {
+    b.iter().try_fold(0u32, |acc, c| {
+        c.is_ascii_digit().then(|| acc * 10 + (c - b'0') as u32)
+    })
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Converts a `(year, month, day)` date into days since `UNIX_EPOCH`.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = ((month + 9) % 12) as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Converts days since `UNIX_EPOCH` into a `(year, month, day)` date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_rfc3339() {
+        assert_eq!(Rfc3339(0).to_string(), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            Rfc3339(1_686_830_400_123_456).to_string(),
+            "2023-06-15T12:00:00.123456Z"
+        );
+        assert_eq!(
+            Rfc3339(951_782_400_000_000).to_string(),
+            "2000-02-29T00:00:00Z"
+        );
+        assert_eq!(Rfc3339(-1).to_string(), "1969-12-31T23:59:59.999999Z");
+        assert_eq!(
+            Rfc3339(*RFC3339_RANGE.start()).to_string(),
+            "0000-01-01T00:00:00Z"
+        );
+        assert_eq!(
+            Rfc3339(*RFC3339_RANGE.end()).to_string(),
+            "9999-12-31T23:59:59.999999Z"
+        );
+        assert!(!Rfc3339(i64::MAX).is_representable());
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_rfc3339("2023-06-15T12:00:00.123456Z"),
+            Some(1_686_830_400_123_456)
+        );
+        assert_eq!(
+            parse_rfc3339("2023-06-15T14:30:00.1234567+02:30"),
+            Some(1_686_830_400_123_456)
+        );
+        assert_eq!(
+            parse_rfc3339("2023-06-15 12:00:00-0100"),
+            Some(1_686_834_000_000_000)
+        );
+        assert_eq!(parse_rfc3339("2000-02-29"), Some(951_782_400_000_000));
+        assert_eq!(parse_rfc3339("1969-12-31t23:59:59.999999z"), Some(-1));
+        assert_eq!(
+            parse_rfc3339("9999-12-31T23:59:59.999999Z"),
+            Some(*RFC3339_RANGE.end())
+        );
+
+        for invalid in [
+            "",
+            "hello world",
+            "2023-06-15T",
+            "2023-06-15T12:00:00",
+            "2023-13-01",
+            "2023-02-29",
+            "2023-06-15T24:00:00Z",
+            "2023-06-15T12:00:00.Z",
+            "2023-06-15T12:00:00+1",
+            "2023-06-15T12:00:00Zjunk",
+            "+023-06-15",
+        ] {
+            assert_eq!(parse_rfc3339(invalid), None, "{invalid:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn test_parse_format_round_trip() {
+        for micros in [0, -1, 1_686_830_400_123_456, *RFC3339_RANGE.start()] {
+            let formatted = Rfc3339(micros).to_string();
+            assert_eq!(parse_rfc3339(&formatted), Some(micros));
+        }
+    }
+}
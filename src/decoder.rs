@@ -1,24 +1,189 @@
-use std::io::ErrorKind;
 use std::marker::PhantomData;
-use std::{io, mem};
+use std::mem;
+#[cfg(any(feature = "validation", test))]
+use std::ops::Range;
 
 #[cfg(any(feature = "validation", test))]
 pub use validation_archiver::{CheckedArchiver, DeserializerIterator};
 
-use crate::Document;
+use crate::checksum::ChecksumAlgorithm;
+use crate::encoder::Alignment;
+use crate::error::BoxedError;
+#[cfg(any(feature = "validation", test))]
+use crate::segment::read_segment;
+use crate::{ArchivedDocument, CanonicalDocument, Document, Error, Result};
+#[cfg(any(feature = "validation", test))]
+use crate::{SegmentError, SegmentFlags};
 
-const MINIMUM_BUFFER_LEN: usize = FOOTER_SIZE + 1;
+/// The size of the footer of each document with the default framing width
+/// and checksum algorithm.
 pub const FOOTER_SIZE: usize = 8;
 
+/// The size of the footer of a document framed with [FramingWidth::Wide]
+/// with the default checksum algorithm.
+pub const WIDE_FOOTER_SIZE: usize = 12;
+
+/// The magic bytes ending a buffer with a trailing position index, see
+/// [Encoder::write_position_index](crate::Encoder::write_position_index).
+pub const POSITION_INDEX_MAGIC: [u8; 8] = *b"BLNIPIDX";
+
+/// The size of the trailer after a position index.
+///
+/// The trailer is written in the format of:
+/// `| position index offset(8) | magic(8) |`
+pub const POSITION_INDEX_TRAILER_SIZE: usize = 16;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+/// How each document is framed within the buffer.
+pub enum Framing {
+    #[default]
+    /// `| data | padding | length | checksum |`
+    ///
+    /// Documents can only be located from the end of the buffer, iterating
+    /// from the front first has to walk the buffer to find each document.
+    Backward,
+    /// `| length | padding | data | padding | length | checksum |`
+    ///
+    /// Each document is also preceded by a header holding its length, padded
+    /// to the size of the alignment, so documents can be located from either
+    /// end of the buffer. This allows buffers which are still being appended to
+    /// to be streamed from the front.
+    Bidirectional,
+}
+
+impl Framing {
+    #[inline]
+    /// The size of the header written before each document.
+    pub(crate) fn header_size(self, alignment: Alignment) -> usize {
+        match self {
+            Self::Backward => 0,
+            Self::Bidirectional => alignment.get(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+/// The width of the length in each header and footer, and of the
+/// positions in the position index.
+pub enum FramingWidth {
+    #[default]
+    /// `| data | padding | length(4 bytes) | checksum(4 bytes) |`
+    ///
+    /// Documents are limited to 4 GiB, as are buffers with a position index.
+    Narrow,
+    /// `| data | padding | length(8 bytes) | checksum(4 bytes) |`
+    ///
+    /// Any header used by [Framing::Bidirectional] also holds an 8 byte length.
+    Wide,
+}
+
+impl FramingWidth {
+    #[inline]
+    /// The size of each length.
+    pub(crate) fn length_size(self) -> usize {
+        match self {
+            Self::Narrow => mem::size_of::<u32>(),
+            Self::Wide => mem::size_of::<u64>(),
+        }
+    }
+
+    #[inline]
+    /// The largest length or position which can be framed.
+    pub(crate) fn max_length(self) -> u64 {
+        match self {
+            Self::Narrow => u32::MAX as u64,
+            Self::Wide => u64::MAX,
+        }
+    }
+
+    #[inline]
+    /// Reads a length, returning `None` if it does not fit in a `usize`.
+    fn read_length(self, bytes: &[u8]) -> Option<usize> {
+        match self {
+            Self::Narrow => usize::try_from(read_u32(bytes)).ok(),
+            Self::Wide => {
+                let length = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+                usize::try_from(length).ok()
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+/// The layout of the records within a buffer.
+pub(crate) struct RecordLayout {
+    pub(crate) alignment: Alignment,
+    pub(crate) framing: Framing,
+    pub(crate) width: FramingWidth,
+    pub(crate) checksum: ChecksumAlgorithm,
+}
+
+impl RecordLayout {
+    #[inline]
+    /// The size of the footer written after each document.
+    pub(crate) fn footer_size(self) -> usize {
+        footer_size(self.width, self.checksum)
+    }
+}
+
+#[inline]
+/// The size of the footer written after each document.
+pub(crate) fn footer_size(width: FramingWidth, checksum: ChecksumAlgorithm) -> usize {
+    width.length_size() + checksum.size()
+}
+
+/// A table of document end positions, see [Decoder::using_known_positions].
+///
+/// This is implemented for archived `Vec<u32>` and `Vec<u64>` tables.
+pub trait PositionTable: private::Sealed + Sync {
+    /// The end position of the document at the given index.
+    fn position(&self, idx: usize) -> Option<u64>;
+
+    /// The number of documents in the table.
+    fn len(&self) -> usize;
+
+    /// Returns if the table has no documents.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> private::Sealed for rkyv::vec::ArchivedVec<T> {}
+
+impl<T: Copy + Into<u64> + Sync> PositionTable for rkyv::vec::ArchivedVec<T> {
+    #[inline]
+    fn position(&self, idx: usize) -> Option<u64> {
+        self.get(idx).map(|position| (*position).into())
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+}
+
 /// A document decoder that produces document from a given borrowed buffer.
 ///
 /// This decoder reads from the tail of the buffer, backwards to head of the buffer
 /// this is because the layout of the encoded documents is in the following format:
-/// `| data | length | checksum |`
+/// `| data | padding | length | checksum |`
+///
+/// Buffers encoded with [Framing::Bidirectional] can be read from either end,
+/// all of the iterators are double ended, producing the oldest documents first
+/// when iterated in reverse.
+///
+/// Every document slice produced by the decoder is checked to be aligned to the
+/// decoder's alignment, which must match the alignment used by the
+/// [Encoder](crate::Encoder). The buffer itself must therefore start on a
+/// multiple of that alignment, e.g. by using an `AlignedVec`. Buffers padded
+/// to [MIN_ALIGNMENT](crate::MIN_ALIGNMENT) are read by default, buffers
+/// encoded before documents could be aligned are read with [Alignment::None].
 pub struct Decoder<'a> {
     buf: &'a [u8],
     validate_checksum: bool,
-    known_positions: Option<&'a rkyv::Archived<Vec<u32>>>,
+    layout: RecordLayout,
+    known_positions: Option<&'a dyn PositionTable>,
+    canonical: bool,
 }
 
 impl<'a> Decoder<'a> {
@@ -30,38 +195,199 @@ impl<'a> Decoder<'a> {
         Self {
             buf,
             validate_checksum: false,
+            layout: RecordLayout::default(),
             known_positions: None,
+            canonical: false,
         }
     }
 
+    #[cfg(any(feature = "validation", test))]
+    /// Create a new document decoder over a buffer ending with a position index
+    /// written by [Encoder::write_position_index](crate::Encoder::write_position_index).
+    ///
+    /// The index is validated and loaded, and only the documents before it are
+    /// decoded, see [Decoder::read_position_index]. This expects the default
+    /// layout, buffers encoded with another layout are read by configuring a
+    /// decoder from [Decoder::new] before loading the index.
+    pub fn with_position_index(buf: &'a [u8]) -> Result<Self> {
+        let mut decoder = Self::new(buf);
+        decoder.read_position_index()?;
+        Ok(decoder)
+    }
+
     /// Create a new document decoder with a set of positions
     /// that can be looked up for direct access.
+    ///
+    /// Each position is the byte offset the document ends at, as recorded by
+    /// [Encoder::positions](crate::Encoder::positions). Positions can be
+    /// archived as either a `Vec<u32>` or a `Vec<u64>`.
     pub fn using_known_positions(
         buf: &'a [u8],
-        positions: &'a rkyv::Archived<Vec<u32>>,
+        positions: &'a impl PositionTable,
     ) -> Self {
         Self {
-            buf,
+            known_positions: Some(positions),
+            ..Self::new(buf)
+        }
+    }
+
+    #[cfg(any(feature = "validation", test))]
+    /// Opens a segment written by a [SegmentEncoder](crate::SegmentEncoder).
+    ///
+    /// The header is checked to be compatible with this build, and the
+    /// checksum of the segment and its position index are verified before any
+    /// documents can be read. Verifying the checksum reads the whole segment.
+    ///
+    /// The alignment and framing are set from the header, and documents can
+    /// be looked up directly with the `*_at` methods. The buffer must start on
+    /// a multiple of the segment's alignment.
+    ///
+    /// Segments flagged with [SegmentFlags::CANONICAL](crate::SegmentFlags::CANONICAL)
+    /// can have their documents viewed with [Decoder::as_canonical].
+    pub fn open(buf: &'a [u8]) -> Result<Self> {
+        let parts = open_segment(buf)?;
+        // SAFETY:
+        //  The position index was validated within this same buffer.
+        Ok(unsafe { Self::from_segment_parts(buf, &parts) })
+    }
+
+    #[cfg(any(feature = "validation", test))]
+    /// Creates a decoder over a segment which has already been opened.
+    ///
+    /// ### Safety
+    /// The parts must have been produced by [open_segment] from the same bytes.
+    pub(crate) unsafe fn from_segment_parts(
+        buf: &'a [u8],
+        parts: &SegmentParts,
+    ) -> Self {
+        let index = &buf[parts.index.clone()];
+        let positions: &'a dyn PositionTable = match parts.layout.width {
+            FramingWidth::Narrow => rkyv::archived_root::<Vec<u32>>(index),
+            FramingWidth::Wide => rkyv::archived_root::<Vec<u64>>(index),
+        };
+
+        Self {
+            buf: &buf[parts.documents.clone()],
             validate_checksum: false,
+            layout: parts.layout,
             known_positions: Some(positions),
+            canonical: parts.canonical,
         }
     }
 
     #[cfg(any(feature = "validation", test))]
+    /// Loads the position index written to the end of the buffer by
+    /// [Encoder::write_position_index](crate::Encoder::write_position_index).
+    ///
+    /// The index record is validated, then the decoder is limited to the
+    /// documents before it so the index is never decoded as a document. The
+    /// documents can then be looked up directly with the `*_at` methods. The
+    /// alignment, framing and width must be set before loading the index.
+    ///
+    /// Buffers which do not end with a position index are rejected and leave
+    /// the decoder unchanged.
+    pub fn read_position_index(&mut self) -> Result<()> {
+        let index_offset = trailing_index_offset(self.buf).ok_or_else(|| {
+            Error::InvalidPositionIndex {
+                offset: self.buf.len(),
+                source: "the buffer does not end with a position index".into(),
+            }
+        })?;
+
+        let indexed_buf = &self.buf[..self.buf.len() - POSITION_INDEX_TRAILER_SIZE];
+        let (record, positions) = read_index_record(indexed_buf, self.layout)?;
+        if record.start != index_offset {
+            return Err(Error::InvalidPositionIndex {
+                offset: indexed_buf.len(),
+                source: "the position index does not start at its recorded offset"
+                    .into(),
+            });
+        }
+        self.buf = &self.buf[..index_offset];
+        self.known_positions = Some(positions);
+        Ok(())
+    }
+
+    /// Walks the buffer once to build a table of the end position of every
+    /// document, oldest first.
+    ///
+    /// The table can be archived and persisted alongside the buffer, then
+    /// used with [Decoder::using_known_positions] for direct access to buffers
+    /// encoded without a position index. Checksums are verified if enabled.
+    ///
+    /// Any error found while walking the buffer is returned, indexed from the
+    /// oldest document, rather than producing an incomplete table.
+    pub fn build_index(&self) -> Result<Vec<u64>> {
+        let mut walker =
+            BufferWalker::from_layout(self.buf, self.validate_checksum, self.layout);
+        let mut positions = Vec::new();
+        while let Some(res) = walker.next_back() {
+            res?;
+            positions.push(walker.last_end() as u64);
+        }
+        Ok(positions)
+    }
+
     #[inline]
-    /// Deserialize the document at the given idx position.
-    pub fn deserialize_at(&self, idx: usize) -> io::Result<Option<Document>> {
-        let position_opt = self.known_positions.as_ref().and_then(|p| p.get(idx));
+    /// Locates the record of the document at the given idx position.
+    fn record_at(&self, idx: usize) -> Result<Option<Record<'a>>> {
+        let position_opt = self.known_positions.and_then(|p| p.position(idx));
         let start = match position_opt {
             None => return Ok(None),
-            Some(start) => *start as usize,
+            // Positions beyond `usize::MAX` are out of bounds of any buffer.
+            Some(start) => usize::try_from(start).unwrap_or(usize::MAX),
         };
 
-        match read_doc_slice_at_position(self.buf, start, self.validate_checksum) {
-            Some(Ok((doc_slice, _))) => rkyv::from_bytes(doc_slice)
-                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))
-                .map(Some),
-            Some(Err(e)) => Err(e),
+        read_record(self.buf, start, self.layout, idx).map(Some)
+    }
+
+    #[inline]
+    /// Locates the data of the document at the given idx position
+    /// along with its byte offset.
+    fn doc_slice_at(&self, idx: usize) -> Result<Option<(&'a [u8], usize)>> {
+        match self.record_at(idx)? {
+            Some(record) => {
+                let doc_slice =
+                    record.verify(self.validate_checksum, self.layout, idx)?;
+                Ok(Some((doc_slice, record.offset)))
+            },
+            None => Ok(None),
+        }
+    }
+
+    #[inline]
+    /// Locates the data of the document at the given idx position along with
+    /// its byte offset, without checking it is aligned.
+    pub(crate) fn unaligned_doc_slice_at(
+        &self,
+        idx: usize,
+    ) -> Result<Option<(&'a [u8], usize)>> {
+        match self.record_at(idx)? {
+            Some(record) => {
+                let doc_slice =
+                    record.verify_checksum(self.validate_checksum, self.layout, idx)?;
+                Ok(Some((doc_slice, record.offset)))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// A walker over the documents which does not check they are aligned.
+    pub(crate) fn unaligned_walker(&self) -> BufferWalker<'a> {
+        let mut walker =
+            BufferWalker::from_layout(self.buf, self.validate_checksum, self.layout);
+        walker.check_alignment = false;
+        walker
+    }
+
+    #[cfg(any(feature = "validation", test))]
+    #[inline]
+    /// Deserialize the document at the given idx position.
+    pub fn deserialize_at(&self, idx: usize) -> Result<Option<Document>> {
+        match self.doc_slice_at(idx)? {
+            Some((doc_slice, offset)) => {
+                validation_archiver::deserialize(doc_slice, idx, offset).map(Some)
+            },
             None => Ok(None),
         }
     }
@@ -70,23 +396,14 @@ impl<'a> Decoder<'a> {
     /// Gets the archived value located at the given idx position.
     ///
     /// ### Safety
-    /// This performs no validation, you must ensure the buffer is correctly aligned
-    /// and has the correct layout.
+    /// This performs no validation, you must ensure the buffer has the correct layout.
+    /// Misaligned documents are rejected with an error.
     pub unsafe fn archived_at(
         &self,
         idx: usize,
-    ) -> io::Result<Option<&'a rkyv::Archived<Document>>> {
-        let position_opt = self.known_positions.as_ref().and_then(|p| p.get(idx));
-        let start = match position_opt {
-            None => return Ok(None),
-            Some(start) => *start as usize,
-        };
-
-        match read_doc_slice_at_position(self.buf, start, self.validate_checksum) {
-            Some(Ok((doc_slice, _))) => {
-                Ok(Some(rkyv::archived_root::<Document>(doc_slice)))
-            },
-            Some(Err(e)) => Err(e),
+    ) -> Result<Option<&'a rkyv::Archived<Document>>> {
+        match self.doc_slice_at(idx)? {
+            Some((doc_slice, _)) => Ok(Some(rkyv::archived_root::<Document>(doc_slice))),
             None => Ok(None),
         }
     }
@@ -97,18 +414,15 @@ impl<'a> Decoder<'a> {
     pub fn checked_archived_at(
         &self,
         idx: usize,
-    ) -> io::Result<Option<&'a rkyv::Archived<Document>>> {
-        let position_opt = self.known_positions.as_ref().and_then(|p| p.get(idx));
-        let start = match position_opt {
-            None => return Ok(None),
-            Some(start) => *start as usize,
-        };
-
-        match read_doc_slice_at_position(self.buf, start, self.validate_checksum) {
-            Some(Ok((doc_slice, _))) => rkyv::check_archived_root::<Document>(doc_slice)
-                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))
+    ) -> Result<Option<&'a rkyv::Archived<Document>>> {
+        match self.doc_slice_at(idx)? {
+            Some((doc_slice, offset)) => CheckedArchiver::get_archived(doc_slice)
+                .map_err(|source| Error::InvalidArchive {
+                    index: idx,
+                    offset,
+                    source,
+                })
                 .map(Some),
-            Some(Err(e)) => Err(e),
             None => Ok(None),
         }
     }
@@ -118,13 +432,81 @@ impl<'a> Decoder<'a> {
         self.validate_checksum = true;
     }
 
+    #[inline]
+    /// Returns if the documents were encoded in the canonical layout.
+    ///
+    /// This is only known for segments and block streams which record the
+    /// layout, raw streams are never marked as canonical.
+    pub fn is_canonical(&self) -> bool {
+        self.canonical
+    }
+
+    #[inline]
+    /// Views a document produced by this decoder in the canonical layout,
+    /// allowing its fields to be binary searched, if the documents were
+    /// encoded in it.
+    ///
+    /// Documents from raw streams which are known to be canonical can be
+    /// viewed with [CanonicalDocument::new_unchecked] instead.
+    pub fn as_canonical(
+        &self,
+        document: &'a ArchivedDocument,
+    ) -> Option<CanonicalDocument<'a>> {
+        self.canonical
+            .then(|| CanonicalDocument::new_unchecked(document))
+    }
+
+    #[cfg(any(feature = "zstd", feature = "lz4"))]
+    #[inline]
+    /// Marks the documents as encoded in the canonical layout.
+    pub(crate) fn set_canonical(&mut self, canonical: bool) {
+        self.canonical = canonical;
+    }
+
+    /// Sets the alignment the documents were encoded with.
+    ///
+    /// Defaults to padding every document to [MIN_ALIGNMENT](crate::MIN_ALIGNMENT)
+    /// bytes, buffers encoded before documents could be aligned must be read
+    /// with [Alignment::None].
+    ///
+    /// ### Panics
+    /// If the alignment is padded but not a power of two or is less
+    /// than [MIN_ALIGNMENT](crate::MIN_ALIGNMENT).
+    pub fn set_alignment(&mut self, alignment: Alignment) {
+        alignment.assert_valid();
+        self.layout.alignment = alignment;
+    }
+
+    /// Sets the framing the documents were encoded with.
+    ///
+    /// Defaults to [Framing::Backward].
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.layout.framing = framing;
+    }
+
+    /// Sets the framing width the documents were encoded with.
+    ///
+    /// Defaults to [FramingWidth::Narrow].
+    pub fn set_framing_width(&mut self, width: FramingWidth) {
+        self.layout.width = width;
+    }
+
+    /// Sets the checksum algorithm the documents were encoded with.
+    ///
+    /// Defaults to [ChecksumAlgorithm::Crc32]. The algorithm is not recorded
+    /// in a raw stream, so it must match the encoder's for the footers to be
+    /// located, segments opened with [Decoder::open] set it from their header.
+    pub fn set_checksum_algorithm(&mut self, checksum: ChecksumAlgorithm) {
+        self.layout.checksum = checksum;
+    }
+
     #[cfg(any(feature = "validation", test))]
     /// Create a new iterator for deserializing all docs
     /// within the buffer.
     ///
     /// This deserializer requires validation to be enabled.
     pub fn deserializer_iter(&self) -> DeserializerIterator<'a> {
-        DeserializerIterator::new(self.buf, self.validate_checksum)
+        DeserializerIterator::new(self.buf, self.validate_checksum, self.layout)
     }
 
     /// An unsafe archive iterator.
@@ -135,10 +517,11 @@ impl<'a> Decoder<'a> {
     /// This comes at a cost with safety however:
     ///
     /// ### Safety
-    /// You **must** ensure the provided buffer is correctly aligned and has
-    /// the correct layout, otherwise this is immediately UB.
+    /// You **must** ensure the provided buffer has the correct layout,
+    /// otherwise this is immediately UB. Misaligned documents are rejected
+    /// with an error rather than being archived.
     pub unsafe fn archived_iter(&self) -> ArchivedIterator<'a, UnsafeArchiver> {
-        ArchivedIterator::new(self.buf, self.validate_checksum)
+        ArchivedIterator::new(self.buf, self.validate_checksum, self.layout)
     }
 
     #[cfg(any(feature = "validation", test))]
@@ -147,80 +530,584 @@ impl<'a> Decoder<'a> {
     /// This iterator uses requires validation to be enabled but provides a
     /// safe API rather than becoming UB on an invalid buffer being provided.
     pub fn checked_archived_iter(&self) -> ArchivedIterator<'a, CheckedArchiver> {
-        ArchivedIterator::new(self.buf, self.validate_checksum)
+        ArchivedIterator::new(self.buf, self.validate_checksum, self.layout)
     }
 }
 
-#[inline]
-fn read_doc_slice_at_position(
-    buf: &[u8],
-    start: usize,
-    validate_checksum: bool,
-) -> Option<io::Result<(&[u8], usize)>> {
-    let active_slice = &buf[..start];
-    let footer_slice = &active_slice[active_slice.len() - FOOTER_SIZE..];
-
-    let length =
-        u32::from_le_bytes(footer_slice[..mem::size_of::<u32>()].try_into().ok()?)
-            as usize;
-    let expected_checksum =
-        u32::from_le_bytes(footer_slice[mem::size_of::<u32>()..].try_into().ok()?);
-
-    let total_length = length + FOOTER_SIZE;
-    if active_slice.len() < total_length {
+#[cfg(any(feature = "validation", test))]
+/// The location of the documents and position index of an intact segment.
+pub(crate) struct SegmentParts {
+    pub(crate) layout: RecordLayout,
+    /// If the documents were encoded in the canonical layout.
+    pub(crate) canonical: bool,
+    /// The byte range of the documents.
+    pub(crate) documents: Range<usize>,
+    /// The byte range of the archived position index.
+    pub(crate) index: Range<usize>,
+}
+
+#[cfg(any(feature = "validation", test))]
+/// Checks the segment is intact and locates its documents and position index.
+pub(crate) fn open_segment(buf: &[u8]) -> Result<SegmentParts> {
+    let segment = read_segment(buf)?;
+    if !(buf.as_ptr() as usize).is_multiple_of(segment.header.alignment as usize) {
+        return Err(SegmentError::MisalignedBuffer {
+            alignment: segment.header.alignment,
+        }
+        .into());
+    }
+
+    let layout = RecordLayout {
+        alignment: Alignment::Padded(segment.header.alignment as usize),
+        framing: segment.header.framing(),
+        width: segment.header.width(),
+        checksum: segment.header.checksum,
+    };
+
+    let region = &buf[segment.documents_start..segment.index_end];
+    let (record, positions) = read_index_record(region, layout)?;
+
+    if segment.documents_start + record.start != segment.index_offset {
+        return Err(SegmentError::InvalidIndexOffset {
+            offset: segment.index_offset as u64,
+        }
+        .into());
+    }
+
+    if positions.len() as u64 != segment.documents {
+        return Err(SegmentError::DocumentCountMismatch {
+            expected: segment.documents,
+            found: positions.len(),
+        }
+        .into());
+    }
+
+    let index_start = segment.documents_start + record.offset;
+    Ok(SegmentParts {
+        layout,
+        canonical: segment.header.flags.contains(SegmentFlags::CANONICAL),
+        documents: segment.documents_start..segment.index_offset,
+        index: index_start..index_start + record.data.len(),
+    })
+}
+
+#[cfg(any(feature = "validation", test))]
+/// The offset the position index starts at, if the buffer ends with one.
+///
+/// A buffer of documents could end with the magic bytes by chance, the
+/// offset must at least lie within the buffer.
+fn trailing_index_offset(buf: &[u8]) -> Option<usize> {
+    let index_end = buf.len().checked_sub(POSITION_INDEX_TRAILER_SIZE)?;
+    let trailer = &buf[index_end..];
+    if trailer[8..] != POSITION_INDEX_MAGIC {
         return None;
     }
 
-    let doc_slice = &active_slice
-        [active_slice.len() - total_length..active_slice.len() - FOOTER_SIZE];
+    let offset = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+    usize::try_from(offset)
+        .ok()
+        .filter(|offset| *offset <= index_end)
+}
 
-    if validate_checksum {
-        let checksum = crc32fast::hash(doc_slice);
+#[cfg(any(feature = "validation", test))]
+/// Reads and validates the position index at the end of the buffer.
+fn read_index_record(
+    buf: &[u8],
+    layout: RecordLayout,
+) -> Result<(Record<'_>, &dyn PositionTable)> {
+    let end = buf.len();
+    let invalid = |source| Error::InvalidPositionIndex {
+        offset: end,
+        source,
+    };
+
+    let record = read_record(buf, end, layout, 0).map_err(|e| invalid(Box::new(e)))?;
+    let data = record
+        .verify(true, layout, 0)
+        .map_err(|e| invalid(Box::new(e)))?;
+    let positions: &dyn PositionTable = match layout.width {
+        FramingWidth::Narrow => rkyv::check_archived_root::<Vec<u32>>(data)
+            .map_err(|e| invalid(crate::error::boxed_message(e)))?,
+        FramingWidth::Wide => rkyv::check_archived_root::<Vec<u64>>(data)
+            .map_err(|e| invalid(crate::error::boxed_message(e)))?,
+    };
+
+    Ok((record, positions))
+}
+
+/// A single document located within the buffer by its header or footer.
+struct Record<'a> {
+    data: &'a [u8],
+    /// The byte offset of the document data.
+    offset: usize,
+    /// The byte offset the record starts at, including any header.
+    start: usize,
+    /// The byte offset the record ends at, after the footer.
+    end: usize,
+    checksum: u64,
+}
 
-        if checksum != expected_checksum {
-            return Some(Err(io::Error::new(
-                ErrorKind::InvalidData,
-                "Checksums did not match when validating doc data",
-            )));
+impl<'a> Record<'a> {
+    #[inline]
+    /// Checks the document data is aligned and, if enabled, matches its checksum.
+    fn verify(
+        &self,
+        validate_checksum: bool,
+        layout: RecordLayout,
+        index: usize,
+    ) -> Result<&'a [u8]> {
+        let alignment = layout.alignment.get();
+        if !(self.data.as_ptr() as usize).is_multiple_of(alignment) {
+            return Err(Error::Misaligned {
+                index,
+                offset: self.offset,
+                alignment,
+            });
         }
+
+        self.verify_checksum(validate_checksum, layout, index)
     }
 
-    Some(Ok((doc_slice, total_length)))
+    /// Checks the document data matches its checksum, if enabled,
+    /// without checking its alignment.
+    fn verify_checksum(
+        &self,
+        validate_checksum: bool,
+        layout: RecordLayout,
+        index: usize,
+    ) -> Result<&'a [u8]> {
+        if validate_checksum {
+            let checksum = layout.checksum.checksum(self.data);
+
+            if checksum != self.checksum {
+                return Err(Error::ChecksumMismatch {
+                    index,
+                    offset: self.offset,
+                    expected: self.checksum,
+                    actual: checksum,
+                });
+            }
+        }
+
+        Ok(self.data)
+    }
+}
+
+#[inline]
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..mem::size_of::<u32>()].try_into().unwrap())
 }
 
+#[inline]
+/// Reads the checksum from a footer, zero extending 32 bit checksums.
+fn read_checksum(bytes: &[u8], algorithm: ChecksumAlgorithm) -> u64 {
+    let mut checksum = [0; 8];
+    let size = algorithm.size();
+    checksum[..size].copy_from_slice(&bytes[..size]);
+    u64::from_le_bytes(checksum)
+}
+
+#[inline]
+/// The length of the data, padding and footer of a document with the given length.
+fn padded_length(length: usize, layout: RecordLayout) -> Option<usize> {
+    length
+        .checked_add(layout.footer_size())
+        .and_then(|total_length| layout.alignment.checked_padded(total_length))
+}
+
+#[inline]
+/// Reads the footer ending at `end` and locates the document data it describes.
+fn read_record(
+    buf: &[u8],
+    end: usize,
+    layout: RecordLayout,
+    index: usize,
+) -> Result<Record<'_>> {
+    let truncated = || Error::TruncatedFooter { index, offset: end };
+
+    let active_slice = buf.get(..end).ok_or(Error::OutOfBounds {
+        index,
+        offset: end,
+        len: buf.len(),
+    })?;
+    let footer_start = end
+        .checked_sub(layout.footer_size())
+        .ok_or_else(truncated)?;
+    let footer = &active_slice[footer_start..];
+    let length = layout.width.read_length(footer).ok_or_else(truncated)?;
+    let checksum = read_checksum(&footer[layout.width.length_size()..], layout.checksum);
+
+    // Lengths are untrusted so every step is checked, a hostile length
+    // must produce an error rather than an overflow or out of bounds slice.
+    let offset = padded_length(length, layout)
+        .and_then(|total_length| end.checked_sub(total_length))
+        .ok_or_else(truncated)?;
+    let start = offset
+        .checked_sub(layout.framing.header_size(layout.alignment))
+        .ok_or_else(truncated)?;
+
+    if layout.framing == Framing::Bidirectional
+        && layout.width.read_length(&active_slice[start..]) != Some(length)
+    {
+        return Err(Error::FramingMismatch {
+            index,
+            offset: start,
+        });
+    }
+
+    Ok(Record {
+        data: &active_slice[offset..offset + length],
+        offset,
+        start,
+        end,
+        checksum,
+    })
+}
+
+#[inline]
+/// Reads the header starting at `start` and locates the document data it describes.
+///
+/// This is only possible with [Framing::Bidirectional].
+fn read_record_forward(
+    buf: &[u8],
+    start: usize,
+    layout: RecordLayout,
+    index: usize,
+) -> Result<Record<'_>> {
+    debug_assert_eq!(layout.framing, Framing::Bidirectional);
+
+    let truncated = || Error::TruncatedHeader {
+        index,
+        offset: start,
+    };
+
+    let offset = start
+        .checked_add(layout.framing.header_size(layout.alignment))
+        .ok_or_else(truncated)?;
+    let header = buf.get(start..offset).ok_or_else(truncated)?;
+    let length = layout.width.read_length(header).ok_or_else(truncated)?;
+
+    let end = padded_length(length, layout)
+        .and_then(|total_length| offset.checked_add(total_length))
+        .filter(|end| *end <= buf.len())
+        .ok_or_else(truncated)?;
+
+    let footer = &buf[end - layout.footer_size()..end];
+    if layout.width.read_length(footer) != Some(length) {
+        return Err(Error::FramingMismatch {
+            index,
+            offset: start,
+        });
+    }
+
+    Ok(Record {
+        data: &buf[offset..offset + length],
+        offset,
+        start,
+        end,
+        checksum: read_checksum(&footer[layout.width.length_size()..], layout.checksum),
+    })
+}
+
+/// Walks the buffer producing the data of each document.
+///
+/// Iterating forwards produces the newest documents first, walking from the
+/// back of the buffer to the front. Iterating in reverse produces the oldest
+/// documents first, with [Framing::Backward] the document positions are first
+/// located by walking the buffer from the back once.
+///
+/// Documents which are misaligned or fail their checksum are produced as an
+/// error and skipped over. A truncated or mismatched header or footer ends the
+/// iteration as the documents beyond it can no longer be located.
+///
+/// Errors are indexed from the oldest document in either direction. When
+/// iterating forwards, the documents before the first error are located once
+/// to count them.
 pub struct BufferWalker<'a> {
     buf: &'a [u8],
     validate_checksum: bool,
-    cursor: usize,
+    layout: RecordLayout,
+    /// If misaligned documents are produced as an error.
+    check_alignment: bool,
+    /// The start of the documents which have not been produced yet.
+    front: usize,
+    /// The end of the documents which have not been produced yet.
+    back: usize,
+    /// The number of documents produced from the front.
+    front_index: usize,
+    /// The ends of the records before the first error found iterating from
+    /// the back, newest first, used to index errors from the oldest document.
+    older_ends: Option<Vec<usize>>,
+    /// The ends of the records located for reverse iteration, oldest last.
+    located_ends: Option<Vec<usize>>,
+    /// An error found while locating records, produced before any of them.
+    located_error: Option<Error>,
+    /// The index of the last document produced, if it has been counted.
+    last_index: Option<usize>,
+    /// The byte offset of the data of the last document produced.
+    last_offset: usize,
+    /// The byte offset the last document produced ends at, after its footer.
+    last_end: usize,
 }
 
 impl<'a> BufferWalker<'a> {
+    #[cfg(any(feature = "utils", test))]
     pub fn new(buf: &'a [u8], validate_checksum: bool) -> Self {
+        Self::with_alignment(buf, validate_checksum, Alignment::default())
+    }
+
+    #[cfg(any(feature = "utils", test))]
+    /// Creates a new walker for documents encoded with the given alignment.
+    ///
+    /// ### Panics
+    /// If the alignment is padded but not a power of two or is less
+    /// than [MIN_ALIGNMENT](crate::MIN_ALIGNMENT).
+    pub fn with_alignment(
+        buf: &'a [u8],
+        validate_checksum: bool,
+        alignment: Alignment,
+    ) -> Self {
+        Self::with_framing(buf, validate_checksum, alignment, Framing::default())
+    }
+
+    #[cfg(any(feature = "utils", test))]
+    /// Creates a new walker for documents encoded with the given
+    /// alignment and framing.
+    ///
+    /// ### Panics
+    /// If the alignment is padded but not a power of two or is less
+    /// than [MIN_ALIGNMENT](crate::MIN_ALIGNMENT).
+    pub fn with_framing(
+        buf: &'a [u8],
+        validate_checksum: bool,
+        alignment: Alignment,
+        framing: Framing,
+    ) -> Self {
+        alignment.assert_valid();
+        let layout = RecordLayout {
+            alignment,
+            framing,
+            width: FramingWidth::default(),
+            checksum: ChecksumAlgorithm::default(),
+        };
+        Self::from_layout(buf, validate_checksum, layout)
+    }
+
+    pub(crate) fn from_layout(
+        buf: &'a [u8],
+        validate_checksum: bool,
+        layout: RecordLayout,
+    ) -> Self {
         Self {
             buf,
             validate_checksum,
-            cursor: buf.len(),
+            layout,
+            check_alignment: true,
+            front: 0,
+            back: buf.len(),
+            front_index: 0,
+            older_ends: None,
+            located_ends: None,
+            located_error: None,
+            last_index: Some(0),
+            last_offset: 0,
+            last_end: 0,
+        }
+    }
+
+    #[inline]
+    /// The index and byte offset of the last document produced.
+    pub(crate) fn last_position(&mut self) -> (usize, usize) {
+        let index = match self.last_index {
+            Some(index) => index,
+            None => {
+                let index = self.index_of(self.last_end);
+                self.last_index = Some(index);
+                index
+            },
+        };
+        (index, self.last_offset)
+    }
+
+    /// The index of the record ending at `end`, counting the records before
+    /// it which have not been produced from the front.
+    ///
+    /// This is only needed for errors found iterating from the back, the
+    /// records are located on the first error and reused for any later ones.
+    fn index_of(&mut self, end: usize) -> usize {
+        if self.older_ends.is_none() {
+            let mut ends = Vec::new();
+            let mut cursor = end;
+            while cursor > self.front {
+                ends.push(cursor);
+                match self.read_unproduced(cursor) {
+                    Ok(record) => cursor = record.start,
+                    // Everything before a broken record counts as one document.
+                    Err(_) => break,
+                }
+            }
+            self.older_ends = Some(ends);
+        }
+
+        let ends = self.older_ends.as_deref().unwrap_or_default();
+        let newer = ends.partition_point(|&e| e >= end);
+        let remaining = ends.partition_point(|&e| e > self.front);
+        self.front_index + remaining.saturating_sub(newer)
+    }
+
+    #[inline]
+    /// The byte offset the last document produced ends at.
+    fn last_end(&self) -> usize {
+        self.last_end
+    }
+
+    #[inline]
+    /// Stops iterating in both directions.
+    fn fuse(&mut self) {
+        self.front = self.back;
+        self.located_ends = Some(Vec::new());
+    }
+
+    #[inline]
+    /// Produces the document of a located record, its index is counted
+    /// on demand if it is not known.
+    fn produce(&mut self, record: Record<'a>, index: Option<usize>) -> Result<&'a [u8]> {
+        self.last_index = index;
+        self.last_offset = record.offset;
+        self.last_end = record.end;
+        let index = index.unwrap_or_default();
+        let verified = if self.check_alignment {
+            record.verify(self.validate_checksum, self.layout, index)
+        } else {
+            record.verify_checksum(self.validate_checksum, self.layout, index)
+        };
+        verified.map_err(|e| e.with_index(self.last_position().0))
+    }
+
+    #[inline]
+    /// Reads the record ending at `end`, which must not overlap the documents
+    /// produced from the front.
+    ///
+    /// Errors are indexed by the caller, only once they are produced.
+    fn read_unproduced(&self, end: usize) -> Result<Record<'a>> {
+        let record = read_record(self.buf, end, self.layout, 0)?;
+        if record.start < self.front {
+            return Err(Error::FramingMismatch {
+                index: 0,
+                offset: record.start,
+            });
         }
+        Ok(record)
+    }
+
+    /// Walks the remaining records from the back to find where each ends.
+    ///
+    /// Nothing before a broken record can be located, so its error is kept
+    /// to be produced first, as the oldest remaining document.
+    fn locate_ends(&mut self) -> Vec<usize> {
+        let mut ends = Vec::new();
+        let mut cursor = self.back;
+        while cursor > self.front {
+            match self.read_unproduced(cursor) {
+                Ok(record) => {
+                    ends.push(cursor);
+                    cursor = record.start;
+                },
+                Err(e) => {
+                    self.located_error = Some(e);
+                    self.front = cursor;
+                    break;
+                },
+            }
+        }
+        ends
     }
 }
 
 impl<'a> Iterator for BufferWalker<'a> {
-    type Item = io::Result<&'a [u8]>;
+    type Item = Result<&'a [u8]>;
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        if self.cursor < MINIMUM_BUFFER_LEN {
+        if self.back <= self.front {
             return None;
         }
 
-        match read_doc_slice_at_position(self.buf, self.cursor, self.validate_checksum)?
-        {
-            Ok((doc_slice, total_length)) => {
-                self.cursor -= total_length;
-                Some(Ok(doc_slice))
+        let record = match self.read_unproduced(self.back) {
+            Ok(record) => record,
+            Err(e) => {
+                let index = self.index_of(self.back);
+                // Count the last document before the records are forgotten.
+                self.last_position();
+                self.fuse();
+                return Some(Err(e.with_index(index)));
             },
-            Err(e) => Some(Err(e)),
-        }
+        };
+
+        self.back = record.start;
+        Some(self.produce(record, None))
+    }
+}
+
+impl<'a> DoubleEndedIterator for BufferWalker<'a> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let index = self.front_index;
+
+        let record = match self.layout.framing {
+            Framing::Bidirectional => {
+                if self.back <= self.front {
+                    return None;
+                }
+
+                match read_record_forward(self.buf, self.front, self.layout, index) {
+                    Ok(record) if record.end <= self.back => record,
+                    Ok(record) => {
+                        self.fuse();
+                        self.front_index += 1;
+                        return Some(Err(Error::FramingMismatch {
+                            index,
+                            offset: record.start,
+                        }));
+                    },
+                    Err(e) => {
+                        self.fuse();
+                        self.front_index += 1;
+                        return Some(Err(e));
+                    },
+                }
+            },
+            Framing::Backward => {
+                if self.located_ends.is_none() {
+                    self.located_ends = Some(self.locate_ends());
+                }
+
+                if let Some(e) = self.located_error.take() {
+                    self.front_index += 1;
+                    return Some(Err(e.with_index(index)));
+                }
+
+                let end = self.located_ends.as_mut()?.pop()?;
+                if end > self.back {
+                    // Already produced by iterating from the back.
+                    return None;
+                }
+
+                // The record has already been located so this cannot fail.
+                match read_record(self.buf, end, self.layout, index) {
+                    Ok(record) => record,
+                    Err(e) => {
+                        self.fuse();
+                        self.front_index += 1;
+                        return Some(Err(e));
+                    },
+                }
+            },
+        };
+
+        self.front_index += 1;
+        self.front = record.end;
+        Some(self.produce(record, Some(index)))
     }
 }
 
@@ -235,25 +1122,44 @@ pub struct ArchivedIterator<'a, A: Archiver> {
 }
 
 impl<'a, A: Archiver> ArchivedIterator<'a, A> {
-    fn new(buf: &'a [u8], validate_checksum: bool) -> Self {
+    fn new(buf: &'a [u8], validate_checksum: bool, layout: RecordLayout) -> Self {
         Self {
-            walker: BufferWalker::new(buf, validate_checksum),
+            walker: BufferWalker::from_layout(buf, validate_checksum, layout),
             _phantom: PhantomData,
         }
     }
+
+    #[inline]
+    fn archive(
+        &mut self,
+        data: Result<&'a [u8]>,
+    ) -> Result<&'a rkyv::Archived<Document>> {
+        A::get_archived(data?).map_err(|source| {
+            let (index, offset) = self.walker.last_position();
+            Error::InvalidArchive {
+                index,
+                offset,
+                source,
+            }
+        })
+    }
 }
 
 impl<'a, A: Archiver> Iterator for ArchivedIterator<'a, A> {
-    type Item = io::Result<&'a rkyv::Archived<Document>>;
+    type Item = Result<&'a rkyv::Archived<Document>>;
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
-        let data = match self.walker.next()? {
-            Ok(data) => data,
-            Err(e) => return Some(Err(e)),
-        };
+        let data = self.walker.next()?;
+        Some(self.archive(data))
+    }
+}
 
-        Some(A::get_archived(data))
+impl<'a, A: Archiver> DoubleEndedIterator for ArchivedIterator<'a, A> {
+    #[inline]
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let data = self.walker.next_back()?;
+        Some(self.archive(data))
     }
 }
 
@@ -262,7 +1168,7 @@ impl<'a, A: Archiver> Iterator for ArchivedIterator<'a, A> {
 /// This makes not guarantees about safety and is down to the implementor
 /// of the trait to ensure things are correct.
 pub trait Archiver: private::Sealed {
-    fn get_archived(buf: &[u8]) -> io::Result<&rkyv::Archived<Document>>;
+    fn get_archived(buf: &[u8]) -> Result<&rkyv::Archived<Document>, BoxedError>;
 }
 
 mod private {
@@ -277,11 +1183,12 @@ mod private {
 /// WARNING:
 ///     This requires the buffer being passed into it to both be
 ///     correctly aligned and have the correct layout otherwise this is UB.
+///     Buffers produced by the [ArchivedIterator] are always aligned.
 pub struct UnsafeArchiver;
 impl private::Sealed for UnsafeArchiver {}
 impl Archiver for UnsafeArchiver {
     #[inline]
-    fn get_archived(buf: &[u8]) -> io::Result<&rkyv::Archived<Document>> {
+    fn get_archived(buf: &[u8]) -> Result<&rkyv::Archived<Document>, BoxedError> {
         // SAFETY:
         //  This requires the buffer to both be correctly aligned, and have
         //  the correct layout, otherwise this is UB.
@@ -291,40 +1198,73 @@ impl Archiver for UnsafeArchiver {
 
 #[cfg(any(feature = "validation", test))]
 mod validation_archiver {
-    use std::io;
-    use std::io::ErrorKind;
+    use rkyv::validation::validators::CheckDeserializeError;
 
-    use crate::decoder::{private, Archiver, BufferWalker};
-    use crate::Document;
+    use crate::decoder::{private, Archiver, BufferWalker, RecordLayout};
+    use crate::error::{boxed_message, BoxedError};
+    use crate::{Document, Error, Result};
+
+    #[inline]
+    /// Validates and deserializes the given document data.
+    pub(super) fn deserialize(
+        data: &[u8],
+        index: usize,
+        offset: usize,
+    ) -> Result<Document> {
+        rkyv::from_bytes(data).map_err(|e| match e {
+            CheckDeserializeError::CheckBytesError(e) => Error::InvalidArchive {
+                index,
+                offset,
+                source: boxed_message(e),
+            },
+            CheckDeserializeError::DeserializeError(e) => Error::Deserialize {
+                index,
+                offset,
+                source: Box::new(e),
+            },
+        })
+    }
 
     /// A iterator that deserializes and allocates the documents
-    /// from back to front.
+    /// from back to front, or front to back when reversed.
     pub struct DeserializerIterator<'a> {
         walker: BufferWalker<'a>,
     }
 
     impl<'a> DeserializerIterator<'a> {
-        pub(crate) fn new(buf: &'a [u8], validate_checksum: bool) -> Self {
+        pub(crate) fn new(
+            buf: &'a [u8],
+            validate_checksum: bool,
+            layout: RecordLayout,
+        ) -> Self {
             Self {
-                walker: BufferWalker::new(buf, validate_checksum),
+                walker: BufferWalker::from_layout(buf, validate_checksum, layout),
             }
         }
+
+        #[inline]
+        fn deserialize(&mut self, data: Result<&[u8]>) -> Result<Document> {
+            // The index is only counted if the document is rejected.
+            deserialize(data?, 0, self.walker.last_offset)
+                .map_err(|e| e.with_index(self.walker.last_position().0))
+        }
     }
 
     impl<'a> Iterator for DeserializerIterator<'a> {
-        type Item = io::Result<Document>;
+        type Item = Result<Document>;
 
         #[inline]
         fn next(&mut self) -> Option<Self::Item> {
-            let data = match self.walker.next()? {
-                Ok(data) => data,
-                Err(e) => return Some(Err(e)),
-            };
-
-            let doc = rkyv::from_bytes(data)
-                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()));
+            let data = self.walker.next()?;
+            Some(self.deserialize(data))
+        }
+    }
 
-            Some(doc)
+    impl<'a> DoubleEndedIterator for DeserializerIterator<'a> {
+        #[inline]
+        fn next_back(&mut self) -> Option<Self::Item> {
+            let data = self.walker.next_back()?;
+            Some(self.deserialize(data))
         }
     }
 
@@ -337,72 +1277,165 @@ mod validation_archiver {
     impl private::Sealed for CheckedArchiver {}
     impl Archiver for CheckedArchiver {
         #[inline]
-        fn get_archived<'a>(buf: &'a [u8]) -> io::Result<&'a rkyv::Archived<Document>> {
-            rkyv::check_archived_root::<Document>(buf)
-                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))
+        fn get_archived(buf: &[u8]) -> Result<&rkyv::Archived<Document>, BoxedError> {
+            rkyv::check_archived_root::<Document>(buf).map_err(boxed_message)
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
     use rkyv::de::deserializers::SharedDeserializeMap;
     use rkyv::{AlignedVec, Deserialize};
 
     use super::*;
-    use crate::{Encoder, Text, Value, DEFAULT_SCRATCH_SPACE};
+    use crate::encoder::padding_for;
+    use crate::test_utils::{document, documents, encode_documents, encode_indexed};
+    use crate::{Encoder, Text, Value, DEFAULT_SCRATCH_SPACE, MIN_ALIGNMENT};
+
+    const PADDED: Alignment = Alignment::Padded(MIN_ALIGNMENT);
+
+    fn padded() -> RecordLayout {
+        RecordLayout {
+            alignment: PADDED,
+            ..RecordLayout::default()
+        }
+    }
+
+    fn push_record(payload: &mut AlignedVec, msg: &[u8]) {
+        payload.extend_from_slice(msg);
+        let padding = padding_for(msg.len() + FOOTER_SIZE, MIN_ALIGNMENT);
+        payload.extend_from_slice(&vec![0; padding]);
+        payload.extend_from_slice(&(msg.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&crc32fast::hash(msg).to_le_bytes());
+    }
 
     #[test]
-    fn test_read_doc_slice_at_position() {
+    fn test_read_record() {
         let msg = b"Hello, world";
         let length = msg.len();
-        let checksum = crc32fast::hash(msg);
 
-        let mut payload = Vec::new();
-        payload.extend_from_slice(msg);
-        payload.extend_from_slice(&(length as u32).to_le_bytes());
-        payload.extend_from_slice(&checksum.to_le_bytes());
-        let data = read_doc_slice_at_position(&payload, payload.len(), true);
-
-        assert!(data.is_some(), "Slice should be produced");
-        let (data, _) = data.unwrap().expect("Checksums should match");
+        let mut payload = AlignedVec::new();
+        push_record(&mut payload, msg);
+        assert_eq!(
+            payload.len(),
+            32,
+            "Record should be padded to the alignment"
+        );
+        let record = read_record(&payload, payload.len(), padded(), 0)
+            .expect("Record should be located");
+        assert_eq!(record.offset, 0, "Record should start at the buffer start");
+        let data = record
+            .verify(true, padded(), 0)
+            .expect("Checksums should match");
         assert_eq!(data, &payload[..length], "Slices should match");
 
         let msg2 = b"Hello, world 2";
-        let length2 = msg2.len();
-        let checksum2 = crc32fast::hash(msg2);
         let msg_start = payload.len();
-        payload.extend_from_slice(msg2);
-        payload.extend_from_slice(&(length2 as u32).to_le_bytes());
-        payload.extend_from_slice(&checksum2.to_le_bytes());
+        push_record(&mut payload, msg2);
 
-        let data = read_doc_slice_at_position(&payload, payload.len(), true);
-        assert!(data.is_some(), "Slice should be produced");
-        let (data, _) = data.unwrap().expect("Checksums should match");
+        let record = read_record(&payload, payload.len(), padded(), 0)
+            .expect("Record should be located");
+        assert_eq!(
+            record.offset, msg_start,
+            "Record should start after the first"
+        );
+        let data = record
+            .verify(true, padded(), 0)
+            .expect("Checksums should match");
         assert_eq!(data, msg2, "Slices should match");
 
-        let data = read_doc_slice_at_position(&payload, msg_start, true);
-        assert!(data.is_some(), "Slice should be produced");
-        let (data, _) = data.unwrap().expect("Checksums should match");
+        let record = read_record(&payload, msg_start, padded(), 1)
+            .expect("Record should be located");
+        let data = record
+            .verify(true, padded(), 1)
+            .expect("Checksums should match");
         assert_eq!(data, msg, "Slices should match");
     }
 
+    #[test]
+    fn test_read_record_errors() {
+        let mut payload = AlignedVec::new();
+        payload.push(0);
+        push_record(&mut payload, b"Hello, world");
+
+        let record = read_record(&payload[1..], payload.len() - 1, padded(), 3)
+            .expect("Record should be located");
+        let err = record
+            .verify(false, padded(), 3)
+            .expect_err("Misaligned slice should be rejected");
+        assert!(matches!(
+            err,
+            Error::Misaligned {
+                index: 3,
+                offset: 0,
+                alignment: MIN_ALIGNMENT,
+            }
+        ));
+
+        let err = read_record(&payload[1..], 4, padded(), 0)
+            .err()
+            .expect("Short footer should be rejected");
+        assert!(matches!(
+            err,
+            Error::TruncatedFooter {
+                index: 0,
+                offset: 4
+            }
+        ));
+
+        let err = read_record(&payload[1..], 64, padded(), 0)
+            .err()
+            .expect("Positions past the buffer should be rejected");
+        assert!(matches!(
+            err,
+            Error::OutOfBounds {
+                index: 0,
+                offset: 64,
+                len: 32,
+            }
+        ));
+
+        let mut payload = AlignedVec::new();
+        push_record(&mut payload, b"Hello, world");
+        let len = payload.len();
+        payload[len - 8..len - 4].copy_from_slice(&64u32.to_le_bytes());
+        let err = read_record(&payload, len, padded(), 0)
+            .err()
+            .expect("Lengths past the buffer should be rejected");
+        assert!(matches!(
+            err,
+            Error::TruncatedFooter {
+                index: 0,
+                offset: 32
+            }
+        ));
+    }
+
+    #[test]
+    fn test_read_unpadded_records() {
+        let mut payload = AlignedVec::new();
+        for msg in [&b"Hello, w"[..], b"Hello, world 2!!"] {
+            payload.extend_from_slice(msg);
+            payload.extend_from_slice(&(msg.len() as u32).to_le_bytes());
+            payload.extend_from_slice(&crc32fast::hash(msg).to_le_bytes());
+        }
+
+        let mut walker = BufferWalker::with_alignment(&payload, true, Alignment::None);
+        assert_eq!(walker.next().unwrap().unwrap(), b"Hello, world 2!!");
+        assert_eq!(walker.last_position(), (1, 16));
+        assert_eq!(walker.next().unwrap().unwrap(), b"Hello, w");
+        assert!(walker.next().is_none(), "Document should not exist");
+    }
+
     #[test]
     fn test_buffer_walker() {
         let msg = b"Hello, world";
-        let length = msg.len();
-        let checksum = crc32fast::hash(msg);
-        let mut payload = Vec::new();
-        payload.extend_from_slice(msg);
-        payload.extend_from_slice(&(length as u32).to_le_bytes());
-        payload.extend_from_slice(&checksum.to_le_bytes());
-
         let msg2 = b"Hello, world 2";
-        let length2 = msg2.len();
-        let checksum2 = crc32fast::hash(msg2);
-        payload.extend_from_slice(msg2);
-        payload.extend_from_slice(&(length2 as u32).to_le_bytes());
-        payload.extend_from_slice(&checksum2.to_le_bytes());
+        let mut payload = AlignedVec::new();
+        push_record(&mut payload, msg);
+        push_record(&mut payload, msg2);
 
         let mut walker = BufferWalker::new(&payload, true);
 
@@ -421,6 +1454,94 @@ mod tests {
         assert!(doc3.is_none(), "Document should not exist");
     }
 
+    #[test]
+    fn test_buffer_walker_skips_corrupt_documents() {
+        let mut payload = AlignedVec::new();
+        push_record(&mut payload, b"Hello, world");
+        push_record(&mut payload, b"Hello, world 2");
+        push_record(&mut payload, b"Hello, world 3");
+        payload[32] ^= 1;
+
+        let mut walker = BufferWalker::with_alignment(&payload, true, PADDED);
+        assert_eq!(walker.next().unwrap().unwrap(), b"Hello, world 3");
+        let err = walker
+            .next()
+            .expect("Doc should be returned")
+            .expect_err("Checksum should not match");
+        assert!(err.is_corrupt_document());
+        assert!(matches!(
+            err,
+            Error::ChecksumMismatch {
+                index: 1,
+                offset: 32,
+                ..
+            }
+        ));
+        assert_eq!(walker.next().unwrap().unwrap(), b"Hello, world");
+        assert!(walker.next().is_none(), "Document should not exist");
+
+        let len = payload.len();
+        payload[len - 8..len - 4].copy_from_slice(&1000u32.to_le_bytes());
+        let mut walker = BufferWalker::with_alignment(&payload, true, PADDED);
+        let err = walker
+            .next()
+            .expect("Error should be returned")
+            .expect_err("Footer should be truncated");
+        assert!(!err.is_corrupt_document());
+        assert!(
+            walker.next().is_none(),
+            "Truncation should end the iteration"
+        );
+    }
+
+    #[test]
+    fn test_buffer_walker_indexes_from_oldest() {
+        let mut payload = AlignedVec::new();
+        for msg in [b"Hello, world 0", b"Hello, world 1", b"Hello, world 2"] {
+            push_record(&mut payload, msg);
+        }
+        push_record(&mut payload, b"Hello, world 3");
+        // Corrupt the second document, the third produced from the back.
+        payload[32] ^= 1;
+
+        let mut walker = BufferWalker::with_alignment(&payload, true, PADDED);
+        assert_eq!(walker.next().unwrap().unwrap(), b"Hello, world 3");
+        assert_eq!(walker.last_position(), (3, 96));
+        assert_eq!(walker.next().unwrap().unwrap(), b"Hello, world 2");
+        assert!(matches!(
+            walker.next(),
+            Some(Err(Error::ChecksumMismatch {
+                index: 1,
+                offset: 32,
+                ..
+            }))
+        ));
+        assert_eq!(walker.next().unwrap().unwrap(), b"Hello, world 0");
+        assert_eq!(walker.last_position(), (0, 0));
+
+        // Documents produced from the front are not counted twice.
+        let mut walker = BufferWalker::with_alignment(&payload, true, PADDED);
+        assert_eq!(walker.next_back().unwrap().unwrap(), b"Hello, world 0");
+        assert_eq!(walker.next().unwrap().unwrap(), b"Hello, world 3");
+        assert_eq!(walker.next().unwrap().unwrap(), b"Hello, world 2");
+        assert!(matches!(
+            walker.next(),
+            Some(Err(Error::ChecksumMismatch { index: 1, .. }))
+        ));
+
+        // Documents before a broken footer are counted as one, with the
+        // documents after it indexed from there.
+        let len = payload.len();
+        payload[len - 40..len - 36].copy_from_slice(&1000u32.to_le_bytes());
+        let mut walker = BufferWalker::with_alignment(&payload, true, PADDED);
+        assert_eq!(walker.next().unwrap().unwrap(), b"Hello, world 3");
+        assert!(matches!(
+            walker.next(),
+            Some(Err(Error::TruncatedFooter { index: 0, .. }))
+        ));
+        assert_eq!(walker.last_position(), (1, 96));
+    }
+
     #[test]
     fn test_encode_decode_safe_archived_root() {
         let mut writer = AlignedVec::new();
@@ -663,4 +1784,452 @@ mod tests {
 
         assert!(doc4.is_none(), "Doc4 should not exist");
     }
+
+    fn archive_positions(positions: &[u32]) -> AlignedVec {
+        rkyv::to_bytes::<_, 256>(&positions.to_vec()).expect("Serialize positions")
+    }
+
+    #[test]
+    fn test_invalid_position_index() {
+        let mut decoder = Decoder::new(&[]);
+        assert!(matches!(
+            decoder.read_position_index(),
+            Err(Error::InvalidPositionIndex { offset: 0, .. })
+        ));
+
+        let mut writer = AlignedVec::new();
+        let mut encoder = Encoder::<_, DEFAULT_SCRATCH_SPACE>::new(&mut writer);
+        encoder.enable_position_index();
+        for id in [1, 2] {
+            let mut document = Document::default();
+            document.insert("id", Value::U64(id));
+            encoder.encode(&document).expect("Encode document");
+        }
+        let _ = encoder
+            .write_position_index()
+            .expect("Write position index");
+
+        let len = writer.len();
+        writer[len - 1] ^= 0xFF;
+        let mut decoder = Decoder::new(&writer);
+        let err = decoder
+            .read_position_index()
+            .expect_err("A corrupt position index should be rejected");
+        assert!(matches!(err, Error::InvalidPositionIndex { .. }));
+        assert_eq!(err.offset(), Some(len));
+        assert!(
+            decoder.deserialize_at(0).unwrap().is_none(),
+            "A failed read should leave the decoder unchanged"
+        );
+
+        writer[len - 1] ^= 0xFF;
+        let index_end = len - POSITION_INDEX_TRAILER_SIZE;
+        let index_start =
+            u64::from_le_bytes(writer[index_end..index_end + 8].try_into().unwrap());
+        writer[index_start as usize] ^= 0xFF;
+        let mut decoder = Decoder::new(&writer);
+        let err = decoder
+            .read_position_index()
+            .expect_err("A position index failing its checksum should be rejected");
+        assert!(matches!(err, Error::InvalidPositionIndex { .. }));
+        assert_eq!(err.offset(), Some(index_end));
+    }
+
+    #[test]
+    fn test_position_index_is_not_iterated() {
+        for framing in [Framing::Backward, Framing::Bidirectional] {
+            let mut writer = AlignedVec::new();
+            let mut encoder = Encoder::<_, DEFAULT_SCRATCH_SPACE>::new(&mut writer);
+            encoder.set_framing(framing);
+            encoder.enable_position_index();
+            for id in [1, 2, 3] {
+                let mut document = Document::default();
+                document.insert("id", Value::U64(id));
+                encoder.encode(&document).expect("Encode document");
+            }
+            let _ = encoder
+                .write_position_index()
+                .expect("Write position index");
+            assert_eq!(writer[writer.len() - 8..], POSITION_INDEX_MAGIC);
+
+            let mut decoder = Decoder::new(&writer);
+            decoder.set_framing(framing);
+            decoder.enable_checksum_validation();
+            decoder.read_position_index().expect("Read position index");
+            let ids = |docs: Vec<Document>| {
+                docs.iter()
+                    .map(|doc| doc.get("id").cloned().unwrap())
+                    .collect::<Vec<_>>()
+            };
+            let newest_first = decoder
+                .deserializer_iter()
+                .collect::<Result<Vec<_>>>()
+                .expect("Only the documents should be iterated");
+            assert_eq!(
+                ids(newest_first),
+                vec![Value::U64(3), Value::U64(2), Value::U64(1)]
+            );
+            let oldest_first = decoder
+                .deserializer_iter()
+                .rev()
+                .collect::<Result<Vec<_>>>()
+                .expect("Only the documents should be iterated");
+            assert_eq!(
+                ids(oldest_first),
+                vec![Value::U64(1), Value::U64(2), Value::U64(3)]
+            );
+            assert_eq!(decoder.checked_archived_iter().count(), 3);
+            assert_eq!(unsafe { decoder.archived_iter() }.count(), 3);
+            assert_eq!(decoder.build_index().unwrap().len(), 3);
+            let newest = decoder
+                .deserialize_at(2)
+                .unwrap()
+                .expect("Document should exist");
+            assert_eq!(newest.get("id"), Some(&Value::U64(3)));
+        }
+
+        let buf = encode_indexed(&documents(3), RecordLayout::default());
+        let decoder = Decoder::with_position_index(&buf).expect("Read position index");
+        let mut returned = decoder
+            .deserializer_iter()
+            .collect::<Result<Vec<_>>>()
+            .expect("Only the documents should be iterated");
+        returned.reverse();
+        assert_eq!(returned, documents(3));
+    }
+
+    #[test]
+    fn test_known_positions_out_of_bounds() {
+        let mut writer = AlignedVec::new();
+        let mut encoder = Encoder::<_, DEFAULT_SCRATCH_SPACE>::new(&mut writer);
+        let mut document = Document::default();
+        document.insert("id", Value::U64(1));
+        encoder.encode(&document).expect("Encode document");
+        let end = encoder.writer().len() as u32;
+
+        let positions = archive_positions(&[end, 0, 4, end + 16, u32::MAX]);
+        let positions = unsafe { rkyv::archived_root::<Vec<u32>>(&positions) };
+        let decoder = Decoder::using_known_positions(&writer, positions);
+
+        assert_eq!(decoder.deserialize_at(0).unwrap(), Some(document));
+        assert!(matches!(
+            decoder.deserialize_at(1),
+            Err(Error::TruncatedFooter {
+                index: 1,
+                offset: 0
+            })
+        ));
+        assert!(matches!(
+            decoder.checked_archived_at(2),
+            Err(Error::TruncatedFooter {
+                index: 2,
+                offset: 4
+            })
+        ));
+        assert!(matches!(
+            unsafe { decoder.archived_at(3) },
+            Err(Error::OutOfBounds { index: 3, .. })
+        ));
+        assert!(matches!(
+            decoder.deserialize_at(4),
+            Err(Error::OutOfBounds { index: 4, .. })
+        ));
+        assert!(decoder.deserialize_at(5).unwrap().is_none());
+    }
+
+    fn layout(framing: Framing, width: FramingWidth) -> RecordLayout {
+        RecordLayout {
+            framing,
+            width,
+            ..RecordLayout::default()
+        }
+    }
+
+    #[test]
+    fn test_build_index() {
+        for framing in [Framing::Backward, Framing::Bidirectional] {
+            let mut writer = AlignedVec::new();
+            let mut encoder = Encoder::<_, DEFAULT_SCRATCH_SPACE>::new(&mut writer);
+            encoder.set_framing(framing);
+            encoder.enable_position_index();
+            for id in [1, 20, 300] {
+                let mut document = Document::default();
+                document.insert("id", Value::U64(id));
+                document
+                    .insert("name", Value::String(Text::from("a".repeat(id as usize))));
+                encoder.encode(&document).expect("Encode document");
+            }
+            let expected = encoder.positions().to_vec();
+            let _ = encoder.into_writer();
+
+            let mut decoder = Decoder::new(&writer);
+            decoder.set_framing(framing);
+            decoder.enable_checksum_validation();
+            assert_eq!(decoder.build_index().unwrap(), expected);
+
+            // Corrupt the data of the second document.
+            writer[expected[0] as usize + framing.header_size(Alignment::default())] ^=
+                0xFF;
+            let mut decoder = Decoder::new(&writer);
+            decoder.set_framing(framing);
+            assert_eq!(
+                decoder.build_index().unwrap(),
+                expected,
+                "Checksums should only be verified if enabled"
+            );
+            decoder.enable_checksum_validation();
+            assert!(matches!(
+                decoder.build_index(),
+                Err(Error::ChecksumMismatch { index: 1, .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_bidirectional_streaming() {
+        let buf = encode_documents(
+            &[document(1), document(2), document(3)],
+            layout(Framing::Bidirectional, FramingWidth::Narrow),
+        );
+        let first_record = buf.len() / 3;
+        assert_eq!(
+            buf.len() % 3,
+            0,
+            "Documents of the same size should have the same record size"
+        );
+
+        // A reader which has only seen part of the third document.
+        let partial = &buf[..buf.len() - 8];
+        let mut decoder = Decoder::new(partial);
+        decoder.set_framing(Framing::Bidirectional);
+        let mut docs = decoder.deserializer_iter().rev();
+
+        assert_eq!(
+            docs.next().unwrap().unwrap().get("id"),
+            Some(&Value::U64(1))
+        );
+        assert_eq!(
+            docs.next().unwrap().unwrap().get("id"),
+            Some(&Value::U64(2))
+        );
+        assert!(matches!(
+            docs.next(),
+            Some(Err(Error::TruncatedHeader { index: 2, offset })) if offset == first_record * 2
+        ));
+        assert!(docs.next().is_none());
+    }
+
+    #[test]
+    fn test_backward_framing_reverse_iteration() {
+        let buf = encode_documents(
+            &[document(1), document(2), document(3)],
+            RecordLayout::default(),
+        );
+        let decoder = Decoder::new(&buf);
+
+        let ids = decoder
+            .deserializer_iter()
+            .rev()
+            .map(|doc| doc.map(|doc| doc.get("id").cloned().unwrap()))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(ids, vec![Value::U64(1), Value::U64(2), Value::U64(3)]);
+
+        let mut buf = buf;
+        let len = buf.len();
+        buf[len - 8..len - 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let decoder = Decoder::new(&buf);
+        let mut docs = decoder.deserializer_iter().rev();
+        assert!(matches!(
+            docs.next(),
+            Some(Err(Error::TruncatedFooter { index: 0, .. }))
+        ));
+        assert!(
+            docs.next().is_none(),
+            "Nothing before a broken footer can be located"
+        );
+
+        // Breaking the footer of the second document, the documents before it
+        // count as one in either direction.
+        let mut buf = encode_documents(
+            &[document(1), document(2), document(3)],
+            RecordLayout::default(),
+        );
+        let second_end = buf.len() / 3 * 2;
+        buf[second_end - 8..second_end - 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let decoder = Decoder::new(&buf);
+        for reverse in [true, false] {
+            let mut docs = decoder.deserializer_iter();
+            let mut next = || {
+                if reverse {
+                    docs.next_back()
+                } else {
+                    docs.next()
+                }
+            };
+            if !reverse {
+                assert_eq!(next().unwrap().unwrap().get("id"), Some(&Value::U64(3)));
+            }
+            assert!(matches!(
+                next(),
+                Some(Err(Error::TruncatedFooter { index: 0, offset })) if offset == second_end
+            ));
+            if reverse {
+                assert_eq!(next().unwrap().unwrap().get("id"), Some(&Value::U64(3)));
+            }
+            assert!(next().is_none());
+        }
+    }
+
+    fn any_layout() -> impl Strategy<Value = RecordLayout> {
+        let framing = prop_oneof![Just(Framing::Backward), Just(Framing::Bidirectional)];
+        let width = prop_oneof![Just(FramingWidth::Narrow), Just(FramingWidth::Wide)];
+        let checksum = proptest::sample::select(vec![
+            ChecksumAlgorithm::Crc32,
+            #[cfg(feature = "crc32c")]
+            ChecksumAlgorithm::Crc32c,
+            #[cfg(feature = "xxh3")]
+            ChecksumAlgorithm::Xxh3,
+            ChecksumAlgorithm::None,
+        ]);
+        (framing, width, checksum).prop_map(|(framing, width, checksum)| RecordLayout {
+            checksum,
+            ..layout(framing, width)
+        })
+    }
+
+    /// Drains every safe iterator over the buffer in both directions, each record is
+    /// at least the size of the alignment so this bounds how many items can be produced.
+    fn drain_iterators(buf: &[u8], validate_checksum: bool, layout: RecordLayout) {
+        let max_items = buf.len() / layout.alignment.get() + 1;
+        let mut decoder = Decoder::new(buf);
+        decoder.layout = layout;
+        if validate_checksum {
+            decoder.enable_checksum_validation();
+        }
+
+        let walker = || BufferWalker::from_layout(buf, validate_checksum, layout);
+        assert!(walker().count() <= max_items);
+        assert!(walker().rev().count() <= max_items);
+        assert!(decoder.deserializer_iter().count() <= max_items);
+        assert!(decoder.deserializer_iter().rev().count() <= max_items);
+        assert!(decoder.checked_archived_iter().count() <= max_items);
+        assert!(decoder.checked_archived_iter().rev().count() <= max_items);
+        if let Ok(positions) = decoder.build_index() {
+            assert!(positions.len() <= max_items);
+        }
+
+        let mut walker = walker();
+        let mut items = 0;
+        loop {
+            let item = if items % 2 == 0 {
+                walker.next()
+            } else {
+                walker.next_back()
+            };
+            if item.is_none() {
+                break;
+            }
+            items += 1;
+            assert!(items <= max_items * 2);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn prop_random_buffers_never_panic(
+            bytes in proptest::collection::vec(any::<u8>(), 0..512),
+            positions in proptest::collection::vec(any::<u32>(), 0..8),
+            validate_checksum in any::<bool>(),
+            layout in any_layout(),
+        ) {
+            let mut buf = AlignedVec::new();
+            buf.extend_from_slice(&bytes);
+            drain_iterators(&buf, validate_checksum, layout);
+
+            let archived = archive_positions(&positions);
+            let positions = unsafe { rkyv::archived_root::<Vec<u32>>(&archived) };
+            let mut decoder = Decoder::using_known_positions(&buf, positions);
+            decoder.layout = layout;
+            if validate_checksum {
+                decoder.enable_checksum_validation();
+            }
+            for idx in 0..positions.len() + 1 {
+                let _ = decoder.deserialize_at(idx);
+                let _ = decoder.checked_archived_at(idx);
+            }
+        }
+
+        #[test]
+        fn prop_corrupted_buffers_never_panic(
+            ids in proptest::collection::vec(any::<u64>(), 1..8),
+            flips in proptest::collection::vec((any::<usize>(), any::<u8>()), 1..8),
+            truncate in any::<usize>(),
+            validate_checksum in any::<bool>(),
+            layout in any_layout(),
+        ) {
+            let documents = ids.iter().copied().map(document).collect::<Vec<_>>();
+            let mut buf = encode_documents(&documents, layout);
+            for (pos, mask) in flips {
+                let len = buf.len();
+                buf[pos % len] ^= mask;
+            }
+            drain_iterators(&buf, validate_checksum, layout);
+            drain_iterators(&buf[..truncate % (buf.len() + 1)], validate_checksum, layout);
+        }
+
+        #[test]
+        fn prop_encoded_buffers_roundtrip(
+            ids in proptest::collection::vec(any::<u64>(), 0..16),
+            layout in any_layout(),
+            from_front in proptest::collection::vec(any::<bool>(), 16),
+        ) {
+            let documents = ids.iter().copied().map(document).collect::<Vec<_>>();
+            let buf = encode_documents(&documents, layout);
+            let mut decoder = Decoder::new(&buf);
+            decoder.layout = layout;
+            decoder.enable_checksum_validation();
+
+            let id = |doc: Result<Document>| doc.map(|doc| doc.get("id").cloned());
+            let expected = ids.iter().map(|id| Some(Value::U64(*id))).collect::<Vec<_>>();
+
+            let returned = decoder
+                .deserializer_iter()
+                .rev()
+                .map(id)
+                .collect::<Result<Vec<_>>>()
+                .expect("Every document should be readable");
+            prop_assert_eq!(&returned, &expected);
+
+            let positions = rkyv::to_bytes::<_, 256>(&decoder.build_index().expect("Build index"))
+                .expect("Archive positions");
+            let positions = unsafe { rkyv::archived_root::<Vec<u64>>(&positions) };
+            let mut indexed = Decoder::using_known_positions(&buf, positions);
+            indexed.layout = layout;
+            let returned = (0..ids.len())
+                .map(|idx| {
+                    let doc = indexed.deserialize_at(idx)?;
+                    Ok(doc.and_then(|doc| doc.get("id").cloned()))
+                })
+                .collect::<Result<Vec<_>>>()
+                .expect("Every document should be indexed");
+            prop_assert_eq!(&returned, &expected);
+
+            // Mixing directions should produce every document exactly once.
+            let mut iter = decoder.deserializer_iter();
+            let mut front = Vec::new();
+            let mut back = Vec::new();
+            for from_front in from_front.iter().cycle().take(ids.len() + 1) {
+                let doc = if *from_front { iter.next_back() } else { iter.next() };
+                match doc {
+                    Some(doc) if *from_front => front.push(id(doc).unwrap()),
+                    Some(doc) => back.push(id(doc).unwrap()),
+                    None => break,
+                }
+            }
+            back.reverse();
+            front.extend(back);
+            prop_assert_eq!(front, expected);
+        }
+    }
 }
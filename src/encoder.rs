@@ -1,71 +1,482 @@
-use std::hash::Hasher;
-use std::io::{ErrorKind, Write};
-use std::{io, mem};
+use std::convert::Infallible;
+use std::io::{self, Read, Write};
+use std::mem;
 
 use rkyv::ser::Serializer;
 
+use crate::checksum::{ChecksumAlgorithm, ChecksumHasher};
+use crate::core::{ArchivedDocument, Canonical};
+use crate::decoder::{footer_size, Framing, FramingWidth, POSITION_INDEX_MAGIC};
+use crate::error::{Error, Result};
+use crate::schema::Schema;
 use crate::serializer::{
+    BelliniBufferSerializer,
     BelliniSerializer,
     BelliniSerializerError,
-    BelliniWriteSerializer,
+    ScratchStats,
 };
 
 /// The default amount of stack scratch space to allocate.
 pub const DEFAULT_SCRATCH_SPACE: usize = 1 << 10;
 
+/// The smallest alignment documents can be padded to with [Alignment::Padded].
+pub const MIN_ALIGNMENT: usize = 16;
+
+/// The alignment required by every archived document and value.
+const ARCHIVED_ALIGNMENT: usize = mem::align_of::<ArchivedDocument>();
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// The alignment of each encoded document.
+///
+/// Defaults to padding every document to [MIN_ALIGNMENT] bytes.
+pub enum Alignment {
+    /// Documents are only padded to the 8 bytes every archived value must be
+    /// aligned to.
+    ///
+    /// With the default framing width and checksum every document and its
+    /// footer is already a multiple of 8 bytes so nothing is padded, this is
+    /// the layout of buffers encoded before documents could be aligned. It
+    /// must be opted into to read those buffers.
+    None,
+    /// Every document is padded so it starts on a multiple of the given number
+    /// of bytes, relative to the first byte written by the encoder.
+    ///
+    /// The alignment must be a power of two and at least [MIN_ALIGNMENT] bytes.
+    Padded(usize),
+}
+
+impl Default for Alignment {
+    fn default() -> Self {
+        Self::Padded(MIN_ALIGNMENT)
+    }
+}
+
+impl Alignment {
+    #[inline]
+    /// The number of bytes each document is aligned to.
+    pub fn get(self) -> usize {
+        match self {
+            Self::None => ARCHIVED_ALIGNMENT,
+            Self::Padded(alignment) => alignment,
+        }
+    }
+
+    #[inline]
+    /// The number of padding bytes written after a document and its footer
+    /// totalling `len` bytes.
+    pub(crate) fn padding_for(self, len: usize) -> usize {
+        padding_for(len, self.get())
+    }
+
+    #[inline]
+    /// Rounds the length of a document and its footer up to include the
+    /// padding, returning `None` on overflow.
+    pub(crate) fn checked_padded(self, len: usize) -> Option<usize> {
+        len.checked_next_multiple_of(self.get())
+    }
+
+    #[inline]
+    /// ### Panics
+    /// If the alignment is padded but not a power of two or is less
+    /// than [MIN_ALIGNMENT].
+    pub(crate) fn assert_valid(self) {
+        if let Self::Padded(alignment) = self {
+            assert_valid_alignment(alignment);
+        }
+    }
+}
+
 /// A document encoder that writes to a given output writer.
 ///
-/// This encoder allocates 1KB of stack space for serializing.
+/// This encoder allocates 1KB of stack space for serializing, any scratch space
+/// needed beyond that is allocated on the heap, see [Encoder::scratch_stats].
+///
+/// Each document is staged in a reusable buffer and only written out once it
+/// has been fully serialized, so a failed encode never leaves a partial
+/// document in the writer.
+///
+/// Documents are padded to [MIN_ALIGNMENT] bytes by default, or to a given
+/// alignment with [Encoder::with_alignment]. Any padding is not included in
+/// the length or checksum of the document. Buffers in the unpadded layout
+/// used before documents could be aligned are written with [Alignment::None].
+///
+/// The encoder can also record where each document ends, see
+/// [Encoder::enable_position_index], allowing documents to be looked up
+/// directly by their index when decoding.
 pub struct Encoder<W: Write, const N: usize = DEFAULT_SCRATCH_SPACE> {
-    writer: BelliniSerializer<N, BelliniWriteSerializer<ChecksumAndLenWriter<W>>>,
+    serializer: BelliniSerializer<N, BelliniBufferSerializer>,
+    writer: ChecksumAndLenWriter<W>,
+    canonical: bool,
+    schema: Option<Schema>,
+    framing: Framing,
+    positions: Option<Vec<u64>>,
+    documents: usize,
 }
 
 impl<W: Write, const N: usize> Encoder<W, N> {
     #[inline]
-    /// Create a new document encoder.
+    /// Create a new document encoder padding each document
+    /// to [MIN_ALIGNMENT] bytes.
     pub fn new(writer: W) -> Self {
+        Self::with_alignment(writer, Alignment::default())
+    }
+
+    /// Create a new document encoder which aligns each document
+    /// with the given alignment.
+    ///
+    /// The buffer must be decoded with the same alignment, see
+    /// [Decoder::set_alignment](crate::Decoder::set_alignment).
+    ///
+    /// ### Panics
+    /// If the alignment is padded but not a power of two or is less
+    /// than [MIN_ALIGNMENT].
+    pub fn with_alignment(writer: W, alignment: Alignment) -> Self {
         Self {
-            writer: BelliniSerializer::new(BelliniWriteSerializer::new(
-                ChecksumAndLenWriter::new(writer),
-            )),
+            serializer: BelliniSerializer::new(BelliniBufferSerializer::default()),
+            writer: ChecksumAndLenWriter::with_alignment(writer, alignment),
+            canonical: false,
+            schema: None,
+            framing: Framing::default(),
+            positions: None,
+            documents: 0,
         }
     }
 
+    /// Enables the canonical document layout.
+    ///
+    /// Documents are archived with their fields, and any nested object entries,
+    /// sorted by key. This allows the archived document to binary search
+    /// its fields when looking up a key, see
+    /// [CanonicalDocument](crate::CanonicalDocument).
+    ///
+    /// The layout is not recorded in the archived documents or a raw stream,
+    /// the reader must know the documents were encoded with it. Segments and
+    /// block streams record it, see [Decoder::as_canonical](crate::Decoder::as_canonical).
+    pub fn enable_canonical_layout(&mut self) {
+        self.canonical = true;
+    }
+
+    /// Sets how each document is framed, see [Framing].
+    ///
+    /// The buffer must be decoded with the same framing, see
+    /// [Decoder::set_framing](crate::Decoder::set_framing).
+    ///
+    /// ### Panics
+    /// If any documents have already been encoded.
+    pub fn set_framing(&mut self, framing: Framing) {
+        assert_eq!(
+            self.documents, 0,
+            "The framing must be set before encoding any documents"
+        );
+        self.framing = framing;
+    }
+
+    /// Records the end position of every encoded document.
+    ///
+    /// The positions can be taken with [Encoder::positions] or written to the
+    /// end of the buffer with [Encoder::write_position_index], either can be
+    /// used to look up documents by their index when decoding, see
+    /// [Decoder::using_known_positions](crate::Decoder::using_known_positions).
+    ///
+    /// With [FramingWidth::Narrow] the positions are written as `u32`s,
+    /// documents which would end beyond `u32::MAX` are rejected with an
+    /// [Error::PositionOverflow] error.
+    ///
+    /// ### Panics
+    /// If any documents have already been encoded.
+    pub fn enable_position_index(&mut self) {
+        assert_eq!(
+            self.documents, 0,
+            "The position index must be enabled before encoding any documents"
+        );
+        self.positions = Some(Vec::new());
+    }
+
+    /// Sets the width of the lengths framing each document, and of the
+    /// positions in the position index, see [FramingWidth].
+    ///
+    /// The buffer must be decoded with the same width, see
+    /// [Decoder::set_framing_width](crate::Decoder::set_framing_width).
+    ///
+    /// ### Panics
+    /// If any documents have already been encoded.
+    pub fn set_framing_width(&mut self, width: FramingWidth) {
+        assert_eq!(
+            self.documents, 0,
+            "The framing width must be set before encoding any documents"
+        );
+        self.writer.set_width(width);
+    }
+
+    /// Sets the algorithm used to checksum each document, see [ChecksumAlgorithm].
+    ///
+    /// The buffer must be decoded with the same algorithm, see
+    /// [Decoder::set_checksum_algorithm](crate::Decoder::set_checksum_algorithm).
+    /// Only segments record the algorithm, a raw stream gives no sign of it.
+    /// As the checksum size differs, a decoder left on the default
+    /// [ChecksumAlgorithm::Crc32] cannot even locate the documents of a stream
+    /// written with `Xxh3` or `None`.
+    ///
+    /// ### Panics
+    /// If any documents have already been encoded.
+    pub fn set_checksum_algorithm(&mut self, checksum: ChecksumAlgorithm) {
+        assert_eq!(
+            self.documents, 0,
+            "The checksum algorithm must be set before encoding any documents"
+        );
+        self.writer.set_checksum_algorithm(checksum);
+    }
+
+    /// Validates every document against the schema before encoding it.
+    ///
+    /// Documents which do not match the schema are rejected with an
+    /// [Error::Schema] error, nothing is written.
+    pub fn set_schema(&mut self, schema: Schema) {
+        self.schema = Some(schema);
+    }
+
+    /// Limits how many bytes of heap scratch space can be used while
+    /// serializing a single document once the stack space is exhausted.
+    ///
+    /// Documents exceeding the limit are rejected with an
+    /// [Error::ScratchOverflow] error, nothing is written.
+    pub fn set_scratch_heap_limit(&mut self, limit: Option<usize>) {
+        self.serializer.scratch_mut().set_heap_limit(limit);
+    }
+
+    #[inline]
+    /// Returns how often serializing has spilled out of the stack scratch space.
+    ///
+    /// Frequent spills suggest a larger scratch space `N` is worth the stack usage.
+    pub fn scratch_stats(&self) -> ScratchStats {
+        self.serializer.scratch().stats()
+    }
+
     #[inline]
     /// Encode a document and write the output to the writer.
-    pub fn encode(&mut self, document: &crate::Document) -> io::Result<()> {
-        let res = self.writer.serialize_value(document);
+    pub fn encode(&mut self, document: &crate::Document) -> Result<()> {
+        let index = self.documents;
+        if let Some(schema) = self.schema.as_ref() {
+            schema
+                .validate(document)
+                .map_err(|errors| Error::Schema { index, errors })?;
+        }
+
+        self.serializer.reset();
+        self.serializer.inner_mut().clear();
 
-        let writer = self.writer.inner_mut().writer_mut();
+        let res = if self.canonical {
+            self.serializer.serialize_value(&Canonical(document))
+        } else {
+            self.serializer.serialize_value(document)
+        };
 
-        if res.is_err() {
-            writer.reset();
+        let offset = self.writer.position();
+        res.map_err(|e| serialize_error(e, index, offset))?;
+
+        let end = self.check_staged(index)?;
+        if self.positions.is_some() && end > self.writer.width.max_length() {
+            return Err(Error::PositionOverflow { index, offset });
         }
 
-        match res {
-            Ok(_) => {
-                let res = writer.write_footer();
-                writer.reset();
-                res
-            },
-            Err(BelliniSerializerError::SerializerError(e)) => Err(e),
-            Err(other) => Err(io::Error::new(ErrorKind::InvalidData, other.to_string())),
+        self.write_staged()?;
+
+        if let Some(positions) = self.positions.as_mut() {
+            positions.push(end);
         }
+        self.documents += 1;
+        Ok(())
+    }
+
+    #[inline]
+    /// The end position of every encoded document, in the order they were encoded.
+    ///
+    /// This is empty unless [Encoder::enable_position_index] has been called.
+    /// The positions can be archived with [rkyv::to_bytes] for use with
+    /// [Decoder::using_known_positions](crate::Decoder::using_known_positions).
+    pub fn positions(&self) -> &[u64] {
+        self.positions.as_deref().unwrap_or_default()
+    }
+
+    /// Writes the recorded positions to the end of the buffer as an archived
+    /// `Vec<u32>`, or `Vec<u64>` with [FramingWidth::Wide], and returns the
+    /// inner writer.
+    ///
+    /// The index is framed like any other document and followed by a trailer
+    /// ending with [POSITION_INDEX_MAGIC]. The buffer must be decoded with
+    /// [Decoder::with_position_index](crate::Decoder::with_position_index) or
+    /// [Decoder::read_position_index](crate::Decoder::read_position_index),
+    /// which load the index and only decode the documents before it.
+    ///
+    /// ### Panics
+    /// If [Encoder::enable_position_index] has not been called.
+    pub fn write_position_index(mut self) -> Result<W> {
+        let index_offset = self.writer.position() as u64;
+        self.write_index_record()?;
+        let writer = self.writer_mut();
+        writer.write_all(&index_offset.to_le_bytes())?;
+        writer.write_all(&POSITION_INDEX_MAGIC)?;
+        Ok(self.writer.into_inner())
+    }
+
+    /// Writes the recorded positions as a record after the documents.
+    ///
+    /// ### Panics
+    /// If [Encoder::enable_position_index] has not been called.
+    pub(crate) fn write_index_record(&mut self) -> Result<()> {
+        let positions = self
+            .positions
+            .take()
+            .expect("The position index must be enabled before it can be written");
+
+        let index = self.documents;
+        self.serializer.reset();
+        self.serializer.inner_mut().clear();
+        let res = match self.writer.width {
+            // Every position has been checked to fit when it was recorded.
+            FramingWidth::Narrow => self.serializer.serialize_value(
+                &positions.iter().map(|end| *end as u32).collect::<Vec<_>>(),
+            ),
+            FramingWidth::Wide => self.serializer.serialize_value(&positions),
+        };
+        res.map_err(|e| serialize_error(e, index, self.writer.position()))?;
+
+        self.check_staged(index)?;
+        self.write_staged()
+    }
+
+    #[inline]
+    /// The number of bytes written by the encoder.
+    pub(crate) fn position(&self) -> usize {
+        self.writer.position()
+    }
+
+    #[inline]
+    /// The alignment of each encoded document.
+    pub(crate) fn alignment(&self) -> Alignment {
+        self.writer.alignment
+    }
+
+    #[inline]
+    /// How each encoded document is framed.
+    pub(crate) fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    #[inline]
+    /// Gets a mutable reference to the inner writer.
+    ///
+    /// Anything written directly is not included in the document positions.
+    pub(crate) fn writer_mut(&mut self) -> &mut W {
+        &mut self.writer.writer
+    }
+
+    #[inline]
+    /// How wide the lengths framing each document are.
+    pub(crate) fn framing_width(&self) -> FramingWidth {
+        self.writer.width
+    }
+
+    #[cfg(any(feature = "zstd", feature = "lz4"))]
+    #[inline]
+    /// Returns if documents are encoded in the canonical layout.
+    pub(crate) fn is_canonical(&self) -> bool {
+        self.canonical
+    }
+
+    #[inline]
+    /// The algorithm used to checksum each document.
+    pub(crate) fn checksum_algorithm(&self) -> ChecksumAlgorithm {
+        self.writer.checksum_hasher.algorithm()
+    }
+
+    #[inline]
+    /// Checks the staged document can be framed, returning the position
+    /// it would end at once written.
+    fn check_staged(&self, index: usize) -> Result<u64> {
+        let length = self.serializer.inner().as_slice().len();
+        let offset = self.writer.position();
+        if length as u64 > self.writer.width.max_length() {
+            return Err(Error::DocumentTooLarge {
+                index,
+                offset,
+                len: length,
+            });
+        }
+
+        let alignment = self.writer.alignment;
+        let footer_size = footer_size(self.writer.width, self.checksum_algorithm());
+        let end = offset
+            + self.framing.header_size(alignment)
+            + length
+            + alignment.padding_for(length + footer_size)
+            + footer_size;
+        Ok(end as u64)
+    }
+
+    #[inline]
+    /// Writes the staged document along with its header and footer.
+    fn write_staged(&mut self) -> Result<()> {
+        // Errors from the writer itself can still leave partial output.
+        let staged = self.serializer.inner().as_slice();
+        let res = match self.framing {
+            Framing::Backward => Ok(()),
+            Framing::Bidirectional => self.writer.write_header(staged.len()),
+        }
+        .and_then(|_| self.writer.write_all(staged))
+        .and_then(|_| self.writer.write_footer());
+        self.writer.reset();
+        res.map_err(Error::from)
+    }
+
+    #[inline]
+    /// The number of documents which have been encoded.
+    pub fn documents(&self) -> usize {
+        self.documents
     }
 
     #[inline]
     /// Return a reference to the given writer.
     pub fn writer(&self) -> &W {
-        self.writer.inner().writer().inner()
+        self.writer.inner()
     }
 
     #[inline]
     /// Consumer the encoder and return the inner writer.
     pub fn into_writer(self) -> W {
-        self.writer
-            .into_inner_serializer() // Composite serializer
-            .into_inner() // Writer serializer
-            .into_inner() // Inner writer
+        self.writer.into_inner()
+    }
+}
+
+#[cfg(any(feature = "zstd", feature = "lz4"))]
+impl<const N: usize> Encoder<rkyv::AlignedVec, N> {
+    #[inline]
+    /// Clears the encoded documents, so the next document is written
+    /// to the start of the buffer.
+    pub(crate) fn clear(&mut self) {
+        self.writer.writer.clear();
+        self.writer.position = 0;
+    }
+}
+
+/// Locates a failure to serialize the document at the given index.
+fn serialize_error<const N: usize>(
+    e: BelliniSerializerError<N, Infallible>,
+    index: usize,
+    offset: usize,
+) -> Error {
+    match e {
+        BelliniSerializerError::SerializerError(e) => match e {},
+        BelliniSerializerError::ScratchSpaceError(e) => Error::ScratchOverflow {
+            index,
+            offset,
+            source: Box::new(e),
+        },
+        BelliniSerializerError::SharedError(e) => Error::Serialize {
+            index,
+            offset,
+            source: Box::new(e),
+        },
     }
 }
 
@@ -74,25 +485,63 @@ impl<W: Write, const N: usize> Encoder<W, N> {
 pub struct ChecksumAndLenWriter<W> {
     writer: W,
     length: usize,
-    checksum_hasher: crc32fast::Hasher,
+    position: usize,
+    alignment: Alignment,
+    width: FramingWidth,
+    checksum_hasher: ChecksumHasher,
 }
 
 impl<W: Write> ChecksumAndLenWriter<W> {
-    /// Creates a new checksum writer.
+    #[cfg(feature = "utils")]
+    /// Creates a new checksum writer padding each document
+    /// to [MIN_ALIGNMENT] bytes.
     pub fn new(writer: W) -> Self {
+        Self::with_alignment(writer, Alignment::default())
+    }
+
+    /// Creates a new checksum writer padding each document
+    /// with the given alignment.
+    ///
+    /// ### Panics
+    /// If the alignment is padded but not a power of two or is less
+    /// than [MIN_ALIGNMENT].
+    pub fn with_alignment(writer: W, alignment: Alignment) -> Self {
+        alignment.assert_valid();
+
         Self {
             writer,
             length: 0,
-            checksum_hasher: crc32fast::Hasher::new(),
+            position: 0,
+            alignment,
+            width: FramingWidth::default(),
+            checksum_hasher: ChecksumAlgorithm::default().hasher(),
         }
     }
 
+    #[inline]
+    /// Sets the width of the lengths in each header and footer.
+    pub fn set_width(&mut self, width: FramingWidth) {
+        self.width = width;
+    }
+
+    #[inline]
+    /// Sets the algorithm used to checksum each document.
+    pub fn set_checksum_algorithm(&mut self, checksum: ChecksumAlgorithm) {
+        self.checksum_hasher = checksum.hasher();
+    }
+
     #[inline]
     /// Get a reference to the inner writer.
     pub fn inner(&self) -> &W {
         &self.writer
     }
 
+    #[inline]
+    /// The number of bytes written to the inner writer, including footers.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
     #[inline]
     /// Reset the checksum state.
     pub fn reset(&mut self) {
@@ -100,30 +549,97 @@ impl<W: Write> ChecksumAndLenWriter<W> {
         self.checksum_hasher.reset();
     }
 
+    #[inline]
+    /// Writes the header used by [Framing::Bidirectional] for a document of
+    /// the given length.
+    ///
+    /// The header is written in the format of:
+    /// | length(4 or 8 bytes) | padding |
+    ///
+    /// The zeroed padding makes the header the size of the alignment.
+    pub fn write_header(&mut self, length: usize) -> io::Result<()> {
+        self.check_length(length)?;
+        self.write_length(length)?;
+        let header_size = self.alignment.get();
+        let padding = header_size - self.width.length_size();
+        io::copy(&mut io::repeat(0).take(padding as u64), &mut self.writer)?;
+        self.position += header_size;
+        Ok(())
+    }
+
     #[inline]
     /// Appends the checksum and len to the end of the writer.
     ///
     /// The footer is written in the format of:
-    /// | length(4 bytes) | checksum(4 bytes) |
+    /// | padding | length(4 or 8 bytes) | checksum(0, 4 or 8 bytes) |
+    ///
+    /// The zeroed padding makes the document and its footer a multiple of
+    /// the alignment, it is not included in the length or checksum.
+    ///
+    /// Lengths which do not fit in the width are rejected rather than truncated.
     pub fn write_footer(&mut self) -> io::Result<()> {
-        let checksum = mem::take(&mut self.checksum_hasher).finalize();
-        self.writer.write_all(&(self.length as u32).to_le_bytes())?;
-        self.writer.write_all(&checksum.to_le_bytes())?;
+        self.check_length(self.length)?;
+        let algorithm = self.checksum_hasher.algorithm();
+        let footer_size = footer_size(self.width, algorithm);
+        let checksum = self.checksum_hasher.finish().to_le_bytes();
+        let padding = self.alignment.padding_for(self.length + footer_size);
+        io::copy(&mut io::repeat(0).take(padding as u64), &mut self.writer)?;
+        self.position += padding;
+        self.write_length(self.length)?;
+        self.writer.write_all(&checksum[..algorithm.size()])?;
+        self.position += footer_size;
         self.reset();
         Ok(())
     }
 
+    #[inline]
+    /// Checks the length fits in the writer's width.
+    fn check_length(&self, length: usize) -> io::Result<()> {
+        if length as u64 > self.width.max_length() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "length {length} does not fit in the {:?} framing width",
+                    self.width
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    /// Writes a length at the writer's width.
+    fn write_length(&mut self, length: usize) -> io::Result<()> {
+        let length = (length as u64).to_le_bytes();
+        self.writer.write_all(&length[..self.width.length_size()])
+    }
+
     pub fn into_inner(self) -> W {
         self.writer
     }
 }
 
+#[inline]
+/// The number of padding bytes required to round `len` up to the alignment.
+pub(crate) fn padding_for(len: usize, alignment: usize) -> usize {
+    len.next_multiple_of(alignment) - len
+}
+
+#[inline]
+pub(crate) fn assert_valid_alignment(alignment: usize) {
+    assert!(
+        alignment.is_power_of_two() && alignment >= MIN_ALIGNMENT,
+        "Alignment must be a power of two and at least {MIN_ALIGNMENT} bytes, got {alignment}",
+    );
+}
+
 impl<W: Write> Write for ChecksumAndLenWriter<W> {
     #[inline]
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let n = self.writer.write(buf)?;
-        self.checksum_hasher.write(buf);
+        self.checksum_hasher.update(&buf[..n]);
         self.length += n;
+        self.position += n;
         Ok(n)
     }
 
@@ -138,7 +654,7 @@ mod tests {
     use rkyv::AlignedVec;
 
     use super::*;
-    use crate::{Document, Text, Value};
+    use crate::{Document, FieldSchema, Text, Value, ValueKind, FOOTER_SIZE};
 
     #[test]
     fn test_encoder() {
@@ -152,7 +668,7 @@ mod tests {
         encoder.encode(&document).expect("Encode document");
         let _ = encoder.into_writer();
 
-        assert_eq!(writer.len(), 80, "Written byte lengths should match");
+        assert_eq!(writer.len(), 96, "Written byte lengths should match");
 
         let checksum =
             u32::from_le_bytes(writer[writer.len() - 4..].try_into().unwrap());
@@ -168,4 +684,444 @@ mod tests {
             "Documents deserialized should match"
         );
     }
+
+    #[test]
+    fn test_encoder_canonical() {
+        let mut writer = AlignedVec::new();
+        let mut encoder = Encoder::<_, DEFAULT_SCRATCH_SPACE>::new(&mut writer);
+        encoder.enable_canonical_layout();
+
+        let mut document = Document::default();
+        document.insert("name", Value::String(Text::from("Hello, world!")));
+        document.insert(
+            "nested",
+            Value::ArrayDynamic(vec![Value::Object(vec![
+                (Text::from("b"), Value::U64(2)),
+                (Text::from("a"), Value::U64(1)),
+            ])]),
+        );
+        document.insert("bobby", Value::F64(123.1231241));
+
+        encoder.encode(&document).expect("Encode document");
+        let _ = encoder.into_writer();
+
+        let length =
+            u32::from_le_bytes(writer[writer.len() - 8..][..4].try_into().unwrap());
+        let rkyved_data = &writer[..length as usize];
+        let archived = rkyv::check_archived_root::<Document>(rkyved_data)
+            .expect("Canonical document should be valid");
+        let canonical = crate::CanonicalDocument::new(archived)
+            .expect("Document should be archived in the canonical layout");
+        assert_eq!(
+            canonical.get("nested").unwrap().as_array().unwrap().len(),
+            1
+        );
+
+        let returned_doc: Document =
+            rkyv::from_bytes(rkyved_data).expect("Deserialize document");
+        let mut expected = Document::default();
+        expected.insert("bobby", Value::F64(123.1231241));
+        expected.insert("name", Value::String(Text::from("Hello, world!")));
+        expected.insert(
+            "nested",
+            Value::ArrayDynamic(vec![Value::Object(vec![
+                (Text::from("a"), Value::U64(1)),
+                (Text::from("b"), Value::U64(2)),
+            ])]),
+        );
+        assert_eq!(
+            expected, returned_doc,
+            "Canonical archive should hold the fields in key order"
+        );
+        assert_ne!(document, returned_doc);
+    }
+
+    #[test]
+    fn test_encoder_schema() {
+        let mut schema = Schema::default();
+        schema.insert("name", FieldSchema::new(ValueKind::String));
+
+        let mut writer = AlignedVec::new();
+        let mut encoder = Encoder::<_, DEFAULT_SCRATCH_SPACE>::new(&mut writer);
+        encoder.set_schema(schema);
+
+        let mut document = Document::default();
+        document.insert("name", Value::String(Text::from("Hello, world!")));
+        encoder.encode(&document).expect("Encode valid document");
+        let valid_len = encoder.writer().len();
+
+        let mut document = Document::default();
+        document.insert("name", Value::U64(1));
+        let err = encoder
+            .encode(&document)
+            .expect_err("Invalid document should be rejected");
+        assert!(matches!(err, Error::Schema { index: 1, .. }));
+        assert_eq!(
+            encoder.writer().len(),
+            valid_len,
+            "Rejected documents should not be written"
+        );
+    }
+
+    #[test]
+    fn test_encoder_failure_leaves_writer_unchanged() {
+        let mut writer = AlignedVec::new();
+        let mut encoder = Encoder::<_, 64>::new(&mut writer);
+        encoder.set_scratch_heap_limit(Some(256));
+
+        let mut small = Document::default();
+        small.insert("name", Value::String(Text::from("Hello, world!")));
+        encoder.encode(&small).expect("Encode document");
+        let written = encoder.writer().to_vec();
+
+        let mut large = Document::default();
+        for i in 0..64 {
+            large.insert(format!("field-{i}"), Value::U64(i));
+        }
+        let err = encoder
+            .encode(&large)
+            .expect_err("Heap scratch limit should be exceeded");
+        assert!(matches!(err, Error::ScratchOverflow { index: 1, .. }));
+        assert_eq!(err.offset(), Some(written.len()));
+        assert_eq!(
+            encoder.writer().as_slice(),
+            written.as_slice(),
+            "Failed encode should not write anything"
+        );
+
+        encoder
+            .encode(&small)
+            .expect("Encode document after failure");
+        let _ = encoder.into_writer();
+
+        let decoder = crate::Decoder::new(&writer);
+        let documents = decoder
+            .deserializer_iter()
+            .collect::<Result<Vec<_>>>()
+            .expect("Every document should be readable");
+        assert_eq!(documents, vec![small.clone(), small]);
+    }
+
+    #[test]
+    fn test_encoder_alignment() {
+        let mut writer = AlignedVec::new();
+        let mut encoder = Encoder::<_, DEFAULT_SCRATCH_SPACE>::with_alignment(
+            &mut writer,
+            Alignment::Padded(64),
+        );
+
+        let mut documents = Vec::new();
+        for i in 0..8 {
+            let mut document = Document::default();
+            document.insert("name", Value::String(Text::from("a".repeat(i * 3))));
+            encoder.encode(&document).expect("Encode document");
+            assert_eq!(
+                encoder.writer().len() % 64,
+                0,
+                "Each document should be padded to the alignment"
+            );
+            documents.push(document);
+        }
+        let _ = encoder.into_writer();
+
+        // `AlignedVec` only guarantees 16 byte alignment.
+        let mut storage = vec![0u8; writer.len() + 64];
+        let offset = storage.as_ptr().align_offset(64);
+        let buffer = &mut storage[offset..offset + writer.len()];
+        buffer.copy_from_slice(&writer);
+
+        let mut decoder = crate::Decoder::new(buffer);
+        decoder.enable_checksum_validation();
+        decoder.set_alignment(Alignment::Padded(64));
+        let mut returned = decoder
+            .deserializer_iter()
+            .collect::<Result<Vec<_>>>()
+            .expect("Every document should be readable");
+        returned.reverse();
+        assert_eq!(returned, documents);
+    }
+
+    #[test]
+    fn test_encoder_default_alignment() {
+        let mut writer = AlignedVec::new();
+        let mut encoder = Encoder::<_, DEFAULT_SCRATCH_SPACE>::new(&mut writer);
+
+        let mut documents = Vec::new();
+        for i in 0..8 {
+            let mut document = Document::default();
+            document.insert("name", Value::String(Text::from("a".repeat(i * 3))));
+            encoder.encode(&document).expect("Encode document");
+            assert_eq!(
+                encoder.writer().len() % MIN_ALIGNMENT,
+                0,
+                "Each document should be padded to the minimum alignment"
+            );
+            documents.push(document);
+        }
+        let _ = encoder.into_writer();
+
+        let mut decoder = crate::Decoder::new(&writer);
+        decoder.enable_checksum_validation();
+        let mut returned = decoder
+            .deserializer_iter()
+            .collect::<Result<Vec<_>>>()
+            .expect("Every document should be readable");
+        returned.reverse();
+        assert_eq!(returned, documents);
+    }
+
+    #[test]
+    fn test_encoder_padding() {
+        let mut writer = AlignedVec::new();
+        let mut encoder = Encoder::<_, DEFAULT_SCRATCH_SPACE>::with_alignment(
+            &mut writer,
+            Alignment::Padded(64),
+        );
+
+        let mut document = Document::default();
+        document.insert("name", Value::String(Text::from("Hello, world!")));
+        encoder.encode(&document).expect("Encode document");
+        let _ = encoder.into_writer();
+        assert_eq!(writer.len() % 64, 0, "Document should be padded");
+
+        let end = writer.len();
+        let length =
+            u32::from_le_bytes(writer[end - 8..end - 4].try_into().unwrap()) as usize;
+        assert!(
+            length + FOOTER_SIZE < end,
+            "Length should not include the padding"
+        );
+        assert!(
+            writer[length..end - FOOTER_SIZE].iter().all(|b| *b == 0),
+            "Padding should be zeroed"
+        );
+
+        let checksum = u32::from_le_bytes(writer[end - 4..].try_into().unwrap());
+        assert_eq!(
+            checksum,
+            crc32fast::hash(&writer[..length]),
+            "Checksum should not include the padding"
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_encoder_rejects_small_alignment() {
+        let _ = Encoder::<_, DEFAULT_SCRATCH_SPACE>::with_alignment(
+            Vec::new(),
+            Alignment::Padded(8),
+        );
+    }
+
+    #[test]
+    fn test_encoder_position_index() {
+        let mut writer = AlignedVec::new();
+        let mut encoder = Encoder::<_, DEFAULT_SCRATCH_SPACE>::new(&mut writer);
+        encoder.set_framing(Framing::Bidirectional);
+        encoder.enable_position_index();
+
+        let mut documents = Vec::new();
+        for i in 0..5 {
+            let mut document = Document::default();
+            document.insert("name", Value::String(Text::from("a".repeat(i * 7))));
+            encoder.encode(&document).expect("Encode document");
+            assert_eq!(
+                encoder.positions().last().copied(),
+                Some(encoder.writer().len() as u64),
+                "Positions should be the end of each document"
+            );
+            documents.push(document);
+        }
+
+        let positions = rkyv::to_bytes::<_, 256>(&encoder.positions().to_vec())
+            .expect("Archive positions");
+        let documents_len = encoder.writer().len();
+        let _ = encoder
+            .write_position_index()
+            .expect("Write position index");
+
+        let positions = unsafe { rkyv::archived_root::<Vec<u64>>(&positions) };
+        let mut decoder =
+            crate::Decoder::using_known_positions(&writer[..documents_len], positions);
+        decoder.set_framing(Framing::Bidirectional);
+        for (idx, document) in documents.iter().enumerate() {
+            assert_eq!(
+                decoder.deserialize_at(idx).unwrap().as_ref(),
+                Some(document)
+            );
+        }
+
+        let mut decoder = crate::Decoder::new(&writer);
+        decoder.set_framing(Framing::Bidirectional);
+        decoder.read_position_index().expect("Read position index");
+        for (idx, document) in documents.iter().enumerate() {
+            assert_eq!(
+                decoder.deserialize_at(idx).unwrap().as_ref(),
+                Some(document)
+            );
+        }
+        assert!(decoder.deserialize_at(documents.len()).unwrap().is_none());
+
+        let returned = decoder
+            .deserializer_iter()
+            .rev()
+            .collect::<Result<Vec<_>>>()
+            .expect("The position index should not be iterated");
+        assert_eq!(returned, documents);
+    }
+
+    #[test]
+    fn test_encoder_wide_framing() {
+        for framing in [Framing::Backward, Framing::Bidirectional] {
+            let mut writer = AlignedVec::new();
+            let mut encoder = Encoder::<_, DEFAULT_SCRATCH_SPACE>::new(&mut writer);
+            encoder.set_framing(framing);
+            encoder.set_framing_width(FramingWidth::Wide);
+            encoder.enable_position_index();
+
+            let mut document = Document::default();
+            document.insert("name", Value::String(Text::from("Hello, world!")));
+            encoder.encode(&document).expect("Encode document");
+            let end = encoder.writer().len();
+            assert_eq!(encoder.positions(), &[end as u64]);
+
+            let written = encoder.writer();
+            let length =
+                u64::from_le_bytes(written[end - 12..end - 4].try_into().unwrap());
+            let checksum = u32::from_le_bytes(written[end - 4..].try_into().unwrap());
+            let header_size = framing.header_size(Alignment::default());
+            let data = &written[header_size..header_size + length as usize];
+            assert_eq!(checksum, crc32fast::hash(data), "Checksums should match");
+            if framing == Framing::Bidirectional {
+                let header = u64::from_le_bytes(written[..8].try_into().unwrap());
+                assert_eq!(header, length, "Header should hold the wide length");
+            }
+
+            let _ = encoder
+                .write_position_index()
+                .expect("Write position index");
+
+            let mut decoder = crate::Decoder::new(&writer);
+            decoder.set_framing(framing);
+            decoder.set_framing_width(FramingWidth::Wide);
+            decoder.read_position_index().expect("Read position index");
+            decoder.enable_checksum_validation();
+            assert_eq!(decoder.deserialize_at(0).unwrap(), Some(document.clone()));
+            assert_eq!(
+                decoder.deserializer_iter().next_back().unwrap().unwrap(),
+                document
+            );
+        }
+    }
+
+    #[test]
+    fn test_encoder_checksum_algorithms() {
+        for algorithm in [
+            ChecksumAlgorithm::Crc32,
+            #[cfg(feature = "crc32c")]
+            ChecksumAlgorithm::Crc32c,
+            #[cfg(feature = "xxh3")]
+            ChecksumAlgorithm::Xxh3,
+            ChecksumAlgorithm::None,
+        ] {
+            let mut writer = AlignedVec::new();
+            let mut encoder = Encoder::<_, DEFAULT_SCRATCH_SPACE>::new(&mut writer);
+            encoder.set_checksum_algorithm(algorithm);
+
+            let mut document = Document::default();
+            document.insert("name", Value::String(Text::from("Hello, world!")));
+            encoder.encode(&document).expect("Encode document");
+
+            let written = encoder.writer();
+            let end = written.len();
+            let footer_size = 4 + algorithm.size();
+            let length = u32::from_le_bytes(
+                written[end - footer_size..end - algorithm.size()]
+                    .try_into()
+                    .unwrap(),
+            );
+            let mut checksum = [0; 8];
+            checksum[..algorithm.size()]
+                .copy_from_slice(&written[end - algorithm.size()..]);
+            let data = &written[..length as usize];
+            assert_eq!(
+                u64::from_le_bytes(checksum),
+                algorithm.checksum(data),
+                "{algorithm:?} checksums should match"
+            );
+
+            let mut decoder = crate::Decoder::new(&writer);
+            decoder.set_checksum_algorithm(algorithm);
+            decoder.enable_checksum_validation();
+            assert_eq!(
+                decoder.deserializer_iter().next().unwrap().unwrap(),
+                document
+            );
+
+            if algorithm != ChecksumAlgorithm::None {
+                let mut corrupt = writer.clone();
+                corrupt[0] ^= 0xFF;
+                let mut decoder = crate::Decoder::new(&corrupt);
+                decoder.set_checksum_algorithm(algorithm);
+                decoder.enable_checksum_validation();
+                assert!(matches!(
+                    decoder.deserializer_iter().next(),
+                    Some(Err(Error::ChecksumMismatch { .. }))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_narrow_width_rejects_large_lengths() {
+        let mut writer =
+            ChecksumAndLenWriter::with_alignment(Vec::new(), Alignment::None);
+        let too_large = u32::MAX as usize + 1;
+        let err = writer
+            .write_header(too_large)
+            .expect_err("Length should not fit in a u32");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(writer.inner().is_empty(), "Nothing should be written");
+
+        writer.set_width(FramingWidth::Wide);
+        writer
+            .write_header(too_large)
+            .expect("Length fits in a u64");
+        assert_eq!(&writer.inner()[..8], &(too_large as u64).to_le_bytes());
+    }
+
+    #[test]
+    fn test_encoder_scratch_spills_to_heap() {
+        let mut writer = AlignedVec::new();
+        let mut encoder = Encoder::<_, 64>::new(&mut writer);
+
+        let mut small = Document::default();
+        small.insert("name", Value::String(Text::from("Hello, world!")));
+        encoder.encode(&small).expect("Encode document");
+        assert_eq!(
+            encoder.scratch_stats(),
+            ScratchStats::default(),
+            "Small documents should fit on the stack"
+        );
+
+        let mut large = Document::default();
+        for i in 0..64 {
+            large.insert(format!("field-{i}"), Value::U64(i));
+        }
+        encoder
+            .encode(&large)
+            .expect("Encode document using the heap");
+        let stats = encoder.scratch_stats();
+        assert!(stats.spills > 0, "Large documents should spill");
+        assert!(stats.largest_spill > 64);
+        let _ = encoder.into_writer();
+
+        let decoder = crate::Decoder::new(&writer);
+        let documents = decoder
+            .deserializer_iter()
+            .collect::<Result<Vec<_>>>()
+            .expect("Every document should be readable");
+        assert_eq!(documents, vec![large, small]);
+    }
 }
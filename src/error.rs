@@ -0,0 +1,437 @@
+//! Errors produced while encoding and decoding documents
+//!
+//! Every error locates the document it occurred at so corrupt documents can
+//! be skipped or quarantined without losing the rest of the buffer.
+
+use std::error::Error as StdError;
+use std::{fmt, io};
+
+#[cfg(any(feature = "zstd", feature = "lz4"))]
+use crate::BlockError;
+use crate::{SegmentError, ValidationErrors};
+
+/// A boxed error which caused a document to be rejected.
+pub type BoxedError = Box<dyn StdError + Send + Sync + 'static>;
+
+/// A specialized `Result` type for encoding and decoding documents.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+#[non_exhaustive]
+/// An error produced while encoding or decoding documents.
+///
+/// The `index` of a document is always its insertion index, the number of
+/// documents encoded before it. The `*_at` methods of the
+/// [Decoder](crate::Decoder) report the index they were requested at, and the
+/// iterators count from the oldest document whichever end they iterate from.
+///
+/// Documents before a record which cannot be located are counted as a single
+/// document, as the iterators cannot tell how many there were.
+///
+/// Errors can be converted into an [io::Error] where [Error::Io] is unwrapped
+/// and everything else becomes an [io::ErrorKind::InvalidData] error.
+///
+/// Some variants are only available with their feature enabled, so matches
+/// outside of this crate must handle errors they do not know of.
+pub enum Error {
+    /// An error from the underlying writer.
+    Io(io::Error),
+    /// The buffer is not a segment which can be decoded by this build.
+    Segment(SegmentError),
+    #[cfg(any(feature = "zstd", feature = "lz4"))]
+    /// The buffer is not a block stream which can be decoded by this build,
+    /// or one of its blocks is corrupt.
+    Block(BlockError),
+    /// The document did not match the encoder's schema, nothing was written.
+    Schema {
+        /// The index of the document.
+        index: usize,
+        /// Every violation of the schema.
+        errors: ValidationErrors,
+    },
+    /// The document needed more scratch space than allowed, nothing was written.
+    ScratchOverflow {
+        /// The index of the document.
+        index: usize,
+        /// The byte offset the document would have been written at.
+        offset: usize,
+        /// The scratch space error.
+        source: BoxedError,
+    },
+    /// The document could not be serialized, nothing was written.
+    Serialize {
+        /// The index of the document.
+        index: usize,
+        /// The byte offset the document would have been written at.
+        offset: usize,
+        /// The serializer error.
+        source: BoxedError,
+    },
+    /// The document is too large for the encoder's framing width,
+    /// nothing was written.
+    DocumentTooLarge {
+        /// The index of the document.
+        index: usize,
+        /// The byte offset the document would have been written at.
+        offset: usize,
+        /// The length of the archived document.
+        len: usize,
+    },
+    /// The document ends beyond the range of a `u32` position index.
+    ///
+    /// When encoding, nothing was written.
+    PositionOverflow {
+        /// The index of the document.
+        index: usize,
+        /// The byte offset the document would have been written at.
+        offset: usize,
+    },
+    /// The position index at the end of the buffer could not be read.
+    InvalidPositionIndex {
+        /// The byte offset the position index ends at.
+        offset: usize,
+        /// The error reading the position index.
+        source: BoxedError,
+    },
+    /// A position lies beyond the end of the buffer.
+    OutOfBounds {
+        /// The index of the document.
+        index: usize,
+        /// The byte offset the document was expected to end at.
+        offset: usize,
+        /// The length of the buffer.
+        len: usize,
+    },
+    /// A footer is cut short or describes more data than is in the buffer.
+    TruncatedFooter {
+        /// The index of the document.
+        index: usize,
+        /// The byte offset the footer ends at.
+        offset: usize,
+    },
+    /// A header is cut short or describes more data than is in the buffer.
+    ///
+    /// Headers are only read from buffers encoded with
+    /// [Framing::Bidirectional](crate::Framing::Bidirectional) when iterating
+    /// oldest first.
+    TruncatedHeader {
+        /// The index of the document.
+        index: usize,
+        /// The byte offset the header starts at.
+        offset: usize,
+    },
+    /// The header and footer of a record disagree, or the record overlaps
+    /// documents which have already been read.
+    FramingMismatch {
+        /// The index of the document.
+        index: usize,
+        /// The byte offset the record starts at.
+        offset: usize,
+    },
+    /// The document data is not aligned to the decoder's alignment.
+    Misaligned {
+        /// The index of the document.
+        index: usize,
+        /// The byte offset of the document data.
+        offset: usize,
+        /// The alignment the decoder expected.
+        alignment: usize,
+    },
+    /// The checksum of the document data did not match the one in its footer.
+    ChecksumMismatch {
+        /// The index of the document.
+        index: usize,
+        /// The byte offset of the document data.
+        offset: usize,
+        /// The checksum stored in the footer.
+        expected: u64,
+        /// The checksum of the data.
+        actual: u64,
+    },
+    /// The document data failed validation of its archived layout.
+    InvalidArchive {
+        /// The index of the document.
+        index: usize,
+        /// The byte offset of the document data.
+        offset: usize,
+        /// The validation error.
+        source: BoxedError,
+    },
+    /// The archived document could not be deserialized.
+    Deserialize {
+        /// The index of the document.
+        index: usize,
+        /// The byte offset of the document data.
+        offset: usize,
+        /// The deserializer error.
+        source: BoxedError,
+    },
+}
+
+impl Error {
+    /// The index of the document the error occurred at, if any.
+    pub fn index(&self) -> Option<usize> {
+        match self {
+            Self::Io(_) | Self::Segment(_) | Self::InvalidPositionIndex { .. } => None,
+            #[cfg(any(feature = "zstd", feature = "lz4"))]
+            Self::Block(_) => None,
+            Self::Schema { index, .. }
+            | Self::ScratchOverflow { index, .. }
+            | Self::Serialize { index, .. }
+            | Self::DocumentTooLarge { index, .. }
+            | Self::PositionOverflow { index, .. }
+            | Self::OutOfBounds { index, .. }
+            | Self::TruncatedFooter { index, .. }
+            | Self::TruncatedHeader { index, .. }
+            | Self::FramingMismatch { index, .. }
+            | Self::Misaligned { index, .. }
+            | Self::ChecksumMismatch { index, .. }
+            | Self::InvalidArchive { index, .. }
+            | Self::Deserialize { index, .. } => Some(*index),
+        }
+    }
+
+    /// Replaces the index of the document the error occurred at, if any.
+    pub(crate) fn with_index(mut self, document_index: usize) -> Self {
+        match &mut self {
+            Self::Io(_) | Self::Segment(_) | Self::InvalidPositionIndex { .. } => {},
+            #[cfg(any(feature = "zstd", feature = "lz4"))]
+            Self::Block(_) => {},
+            Self::Schema { index, .. }
+            | Self::ScratchOverflow { index, .. }
+            | Self::Serialize { index, .. }
+            | Self::DocumentTooLarge { index, .. }
+            | Self::PositionOverflow { index, .. }
+            | Self::OutOfBounds { index, .. }
+            | Self::TruncatedFooter { index, .. }
+            | Self::TruncatedHeader { index, .. }
+            | Self::FramingMismatch { index, .. }
+            | Self::Misaligned { index, .. }
+            | Self::ChecksumMismatch { index, .. }
+            | Self::InvalidArchive { index, .. }
+            | Self::Deserialize { index, .. } => *index = document_index,
+        }
+        self
+    }
+
+    /// The byte offset the error occurred at, if any.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Self::Io(_) | Self::Segment(_) | Self::Schema { .. } => None,
+            #[cfg(any(feature = "zstd", feature = "lz4"))]
+            Self::Block(_) => None,
+            Self::ScratchOverflow { offset, .. }
+            | Self::Serialize { offset, .. }
+            | Self::DocumentTooLarge { offset, .. }
+            | Self::PositionOverflow { offset, .. }
+            | Self::InvalidPositionIndex { offset, .. }
+            | Self::OutOfBounds { offset, .. }
+            | Self::TruncatedFooter { offset, .. }
+            | Self::TruncatedHeader { offset, .. }
+            | Self::FramingMismatch { offset, .. }
+            | Self::Misaligned { offset, .. }
+            | Self::ChecksumMismatch { offset, .. }
+            | Self::InvalidArchive { offset, .. }
+            | Self::Deserialize { offset, .. } => Some(*offset),
+        }
+    }
+
+    /// Returns if the document itself is corrupt rather than the framing
+    /// of the buffer around it.
+    ///
+    /// Iterators skip over corrupt documents, so iteration can continue
+    /// after these errors.
+    pub fn is_corrupt_document(&self) -> bool {
+        matches!(
+            self,
+            Self::Misaligned { .. }
+                | Self::ChecksumMismatch { .. }
+                | Self::InvalidArchive { .. }
+                | Self::Deserialize { .. }
+        )
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {e}"),
+            Self::Segment(e) => write!(f, "invalid segment: {e}"),
+            #[cfg(any(feature = "zstd", feature = "lz4"))]
+            Self::Block(e) => write!(f, "invalid block stream: {e}"),
+            Self::Schema { index, errors } => {
+                write!(f, "document {index} does not match the schema: {errors}")
+            },
+            Self::ScratchOverflow {
+                index,
+                offset,
+                source,
+            } => write!(
+                f,
+                "document {index} at offset {offset} exceeded the scratch space: {source}"
+            ),
+            Self::Serialize {
+                index,
+                offset,
+                source,
+            } => write!(
+                f,
+                "document {index} at offset {offset} could not be serialized: {source}"
+            ),
+            Self::DocumentTooLarge { index, offset, len } => write!(
+                f,
+                "document {index} at offset {offset} of length {len} is too large for the framing width"
+            ),
+            Self::PositionOverflow { index, offset } => write!(
+                f,
+                "document {index} at offset {offset} would end beyond the range of the position index"
+            ),
+            Self::InvalidPositionIndex { offset, source } => write!(
+                f,
+                "position index ending at offset {offset} is invalid: {source}"
+            ),
+            Self::OutOfBounds { index, offset, len } => write!(
+                f,
+                "document {index} ends at offset {offset} beyond the buffer length {len}"
+            ),
+            Self::TruncatedFooter { index, offset } => write!(
+                f,
+                "document {index} has a truncated footer ending at offset {offset}"
+            ),
+            Self::TruncatedHeader { index, offset } => write!(
+                f,
+                "document {index} has a truncated header starting at offset {offset}"
+            ),
+            Self::FramingMismatch { index, offset } => write!(
+                f,
+                "document {index} has a mismatched header or footer at offset {offset}"
+            ),
+            Self::Misaligned {
+                index,
+                offset,
+                alignment,
+            } => write!(
+                f,
+                "document {index} at offset {offset} is not aligned to {alignment} bytes"
+            ),
+            Self::ChecksumMismatch {
+                index,
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "document {index} at offset {offset} has checksum {actual:#010x}, expected {expected:#010x}"
+            ),
+            Self::InvalidArchive {
+                index,
+                offset,
+                source,
+            } => write!(f, "document {index} at offset {offset} is invalid: {source}"),
+            Self::Deserialize {
+                index,
+                offset,
+                source,
+            } => write!(
+                f,
+                "document {index} at offset {offset} could not be deserialized: {source}"
+            ),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Segment(e) => Some(e),
+            #[cfg(any(feature = "zstd", feature = "lz4"))]
+            Self::Block(e) => Some(e),
+            Self::Schema { errors, .. } => Some(errors),
+            Self::ScratchOverflow { source, .. }
+            | Self::Serialize { source, .. }
+            | Self::InvalidPositionIndex { source, .. }
+            | Self::InvalidArchive { source, .. }
+            | Self::Deserialize { source, .. } => Some(source.as_ref()),
+            Self::DocumentTooLarge { .. }
+            | Self::PositionOverflow { .. }
+            | Self::OutOfBounds { .. }
+            | Self::TruncatedFooter { .. }
+            | Self::TruncatedHeader { .. }
+            | Self::FramingMismatch { .. }
+            | Self::Misaligned { .. }
+            | Self::ChecksumMismatch { .. } => None,
+        }
+    }
+}
+
+#[cfg(any(feature = "validation", test))]
+#[derive(Debug)]
+/// The message of an error which cannot be sent between threads,
+/// like those produced by bytecheck.
+struct ErrorMessage(String);
+
+#[cfg(any(feature = "validation", test))]
+impl fmt::Display for ErrorMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(any(feature = "validation", test))]
+impl StdError for ErrorMessage {}
+
+#[cfg(any(feature = "validation", test))]
+/// Boxes the message of an error which is not `Send` and `Sync`.
+pub(crate) fn boxed_message(e: impl fmt::Display) -> BoxedError {
+    Box::new(ErrorMessage(e.to_string()))
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<SegmentError> for Error {
+    fn from(e: SegmentError) -> Self {
+        Self::Segment(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            other => io::Error::new(io::ErrorKind::InvalidData, other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_conversion() {
+        let err = Error::from(io::Error::new(io::ErrorKind::WriteZero, "full"));
+        assert!(matches!(err, Error::Io(_)));
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::WriteZero);
+
+        let err = io::Error::from(Error::ChecksumMismatch {
+            index: 1,
+            offset: 32,
+            expected: 0,
+            actual: 1,
+        });
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let inner = err
+            .into_inner()
+            .expect("Error should be wrapped")
+            .downcast::<Error>()
+            .expect("Error should be a bellini error");
+        assert_eq!(inner.index(), Some(1));
+        assert_eq!(inner.offset(), Some(32));
+        assert!(inner.is_corrupt_document());
+    }
+}
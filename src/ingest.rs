@@ -0,0 +1,257 @@
+//! Configurable ingestion of documents through serde
+//!
+//! Formats like JSON have no date type, so by default timestamps are ingested
+//! as plain strings and integers. An [IngestConfig] can be used as a
+//! `DeserializeSeed` to turn them into `Value::Date` and `Value::ArrayDate`.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde::de::{DeserializeSeed, MapAccess, Visitor};
+use serde::Deserializer;
+
+use crate::core::{Document, Value};
+use crate::serde_compat::{visit_entries, ValueVisitor};
+
+#[derive(Debug, Clone, Default)]
+/// Controls how values are produced while deserializing documents.
+///
+/// ```
+/// use serde::de::DeserializeSeed;
+/// use bellini::{IngestConfig, Value};
+///
+/// let mut config = IngestConfig::default();
+/// config.enable_date_detection();
+/// config.add_epoch_millis_field("seen");
+///
+/// let mut json = serde_json::Deserializer::from_str(
+///     r#"{"created": "2023-06-15T12:00:00Z", "seen": 1686830400000}"#,
+/// );
+/// let document = config.document_seed().deserialize(&mut json).unwrap();
+/// assert_eq!(document.get("created"), Some(&Value::Date(1_686_830_400_000_000)));
+/// assert_eq!(document.get("seen"), Some(&Value::Date(1_686_830_400_000_000)));
+/// ```
+pub struct IngestConfig {
+    detect_dates: bool,
+    epoch_millis_fields: BTreeSet<String>,
+}
+
+impl IngestConfig {
+    /// The config used by the plain `Deserialize` implementations which
+    /// never produces dates.
+    pub(crate) const DEFAULT: Self = Self {
+        detect_dates: false,
+        epoch_millis_fields: BTreeSet::new(),
+    };
+
+    /// Parse any RFC3339 or ISO-8601 timestamp strings into dates.
+    ///
+    /// Plain `YYYY-MM-DD` dates are taken as midnight UTC.
+    pub fn enable_date_detection(&mut self) {
+        self.detect_dates = true;
+    }
+
+    /// Treat integers stored under the given key as milliseconds since
+    /// `UNIX_EPOCH` and convert them into dates.
+    ///
+    /// The key is matched at any level of nesting, arrays of integers under
+    /// the key become `Value::ArrayDate`.
+    pub fn add_epoch_millis_field(&mut self, key: impl Into<String>) {
+        self.epoch_millis_fields.insert(key.into());
+    }
+
+    #[inline]
+    /// Returns if date strings are being detected.
+    pub fn detects_dates(&self) -> bool {
+        self.detect_dates
+    }
+
+    #[inline]
+    /// Returns if the values of the given key are epoch milliseconds.
+    pub fn is_epoch_millis_field(&self, key: &str) -> bool {
+        self.epoch_millis_fields.contains(key)
+    }
+
+    #[inline]
+    /// A `DeserializeSeed` producing a [Document] using this config.
+    pub fn document_seed(&self) -> DocumentSeed<'_> {
+        DocumentSeed { config: self }
+    }
+
+    #[inline]
+    /// A `DeserializeSeed` producing a [Value] using this config.
+    pub fn value_seed(&self) -> ValueSeed<'_> {
+        ValueSeed { config: self }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+/// Deserializes a [Document] using an [IngestConfig].
+pub struct DocumentSeed<'a> {
+    config: &'a IngestConfig,
+}
+
+impl<'a, 'de> DeserializeSeed<'de> for DocumentSeed<'a> {
+    type Value = Document;
+
+    #[inline]
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'a, 'de> Visitor<'de> for DocumentSeed<'a> {
+    type Value = Document;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a JSON object")
+    }
+
+    #[inline]
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        DeserializeSeed::deserialize(self, deserializer)
+    }
+
+    #[inline]
+    fn visit_map<V>(self, visitor: V) -> Result<Self::Value, V::Error>
+    where
+        V: MapAccess<'de>,
+    {
+        visit_entries(self.config, visitor).map(Document::from)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+/// Deserializes a [Value] using an [IngestConfig].
+pub struct ValueSeed<'a> {
+    config: &'a IngestConfig,
+}
+
+impl<'a, 'de> DeserializeSeed<'de> for ValueSeed<'a> {
+    type Value = Value;
+
+    #[inline]
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ValueVisitor::new(self.config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Text;
+
+    fn ingest(config: &IngestConfig, json: &str) -> Document {
+        let mut deserializer = serde_json::Deserializer::from_str(json);
+        config
+            .document_seed()
+            .deserialize(&mut deserializer)
+            .expect("Deserialize document")
+    }
+
+    #[test]
+    fn test_default_config() {
+        let json = r#"{"created": "2023-06-15T12:00:00Z", "seen": [1, 2]}"#;
+        let document = ingest(&IngestConfig::default(), json);
+        assert_eq!(
+            document.get("created"),
+            Some(&Value::String(Text::from("2023-06-15T12:00:00Z")))
+        );
+        assert_eq!(document.get("seen"), Some(&Value::ArrayU64(vec![1, 2])));
+
+        let expected: Document = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            document, expected,
+            "Default config should match Deserialize"
+        );
+    }
+
+    #[test]
+    fn test_date_detection() {
+        let mut config = IngestConfig::default();
+        config.enable_date_detection();
+
+        let document = ingest(
+            &config,
+            r#"{
+                "created": "2023-06-15T12:00:00Z",
+                "day": "2000-02-29",
+                "name": "2023-06-15 is not a date",
+                "seen": ["1970-01-01T00:00:00Z", "1970-01-01T00:00:01+00:00"],
+                "mixed": ["1970-01-01T00:00:00Z", "hello"],
+                "nested": {"at": "1970-01-01T00:00:00.5Z"}
+            }"#,
+        );
+        assert_eq!(
+            document.get("created"),
+            Some(&Value::Date(1_686_830_400_000_000))
+        );
+        assert_eq!(document.get("day"), Some(&Value::Date(951_782_400_000_000)));
+        assert_eq!(
+            document.get("name"),
+            Some(&Value::String(Text::from("2023-06-15 is not a date")))
+        );
+        assert_eq!(
+            document.get("seen"),
+            Some(&Value::ArrayDate(vec![0, 1_000_000]))
+        );
+        assert_eq!(
+            document.get("mixed"),
+            Some(&Value::ArrayDynamic(vec![
+                Value::Date(0),
+                Value::String(Text::from("hello"))
+            ]))
+        );
+        assert_eq!(
+            document.get("nested").and_then(|v| v.get("at")),
+            Some(&Value::Date(500_000))
+        );
+    }
+
+    #[test]
+    fn test_epoch_millis_fields() {
+        let mut config = IngestConfig::default();
+        config.add_epoch_millis_field("seen");
+
+        let document = ingest(
+            &config,
+            r#"{
+                "seen": 1686830400000,
+                "count": 1686830400000,
+                "nested": {"seen": [-1, 2]},
+                "huge": {"seen": 18446744073709551615}
+            }"#,
+        );
+        assert_eq!(
+            document.get("seen"),
+            Some(&Value::Date(1_686_830_400_000_000))
+        );
+        assert_eq!(document.get("count"), Some(&Value::U64(1_686_830_400_000)));
+        assert_eq!(
+            document.get("nested").and_then(|v| v.get("seen")),
+            Some(&Value::ArrayDate(vec![-1000, 2000]))
+        );
+        assert_eq!(
+            document.get("huge").and_then(|v| v.get("seen")),
+            Some(&Value::U64(u64::MAX)),
+            "Offsets which overflow should be left as integers"
+        );
+
+        let mut deserializer = serde_json::Deserializer::from_str("[1, 2]");
+        let value = config.value_seed().deserialize(&mut deserializer).unwrap();
+        assert_eq!(
+            value,
+            Value::ArrayU64(vec![1, 2]),
+            "Only values under the configured keys are dates"
+        );
+    }
+}
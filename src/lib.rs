@@ -1,19 +1,110 @@
+#[cfg(feature = "serde")]
+mod array_builder;
+#[cfg(any(feature = "zstd", feature = "lz4"))]
+mod block;
+mod checksum;
 mod core;
+mod date;
 mod decoder;
 mod encoder;
+mod error;
+#[cfg(feature = "serde")]
+mod ingest;
+mod lookup;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod path;
+mod realign;
+mod schema;
+mod segment;
 mod serializer;
+#[cfg(test)]
+mod test_utils;
 
 #[cfg(feature = "serde")]
 mod serde_compat;
+#[cfg(feature = "serde")]
+mod serde_de;
+#[cfg(feature = "serde")]
+mod serde_ser;
 
+#[cfg(any(feature = "zstd", feature = "lz4"))]
+pub use block::{
+    BlockCompression,
+    BlockDecoder,
+    BlockDictionary,
+    BlockEncoder,
+    BlockEntry,
+    BlockError,
+    BLOCK_INDEX_ENTRY_SIZE,
+    BLOCK_MAGIC,
+    BLOCK_TRAILER_SIZE,
+    DEFAULT_BLOCK_DOCUMENTS,
+    DEFAULT_BLOCK_SIZE,
+};
+#[cfg(feature = "crc32c")]
+pub use checksum::Crc32c;
+#[cfg(feature = "xxh3")]
+pub use checksum::Xxh3;
+pub use checksum::{Checksum, ChecksumAlgorithm, Crc32, NoChecksum};
 #[cfg(feature = "utils")]
 pub use decoder::BufferWalker;
-pub use decoder::{ArchivedIterator, Archiver, Decoder, UnsafeArchiver, FOOTER_SIZE};
+pub use decoder::{
+    ArchivedIterator,
+    Archiver,
+    Decoder,
+    Framing,
+    FramingWidth,
+    PositionTable,
+    UnsafeArchiver,
+    FOOTER_SIZE,
+    POSITION_INDEX_MAGIC,
+    POSITION_INDEX_TRAILER_SIZE,
+    WIDE_FOOTER_SIZE,
+};
 #[cfg(feature = "validation")]
 pub use decoder::{CheckedArchiver, DeserializerIterator};
 #[cfg(feature = "utils")]
 pub use encoder::ChecksumAndLenWriter;
-pub use encoder::{Encoder, DEFAULT_SCRATCH_SPACE};
+pub use encoder::{Alignment, Encoder, DEFAULT_SCRATCH_SPACE, MIN_ALIGNMENT};
+pub use error::{BoxedError, Error, Result};
+#[cfg(feature = "serde")]
+pub use ingest::{DocumentSeed, IngestConfig, ValueSeed};
+pub use lookup::{CanonicalDocument, CanonicalValue};
+#[cfg(feature = "mmap")]
+pub use mmap::MmapDecoder;
+pub use path::{ArchivedValueRef, Path, PathError};
+pub use realign::{RealignStats, RealignedDocument, RealigningDecoder};
+pub use schema::{
+    Coercion,
+    CoercionPolicy,
+    FieldSchema,
+    InferredField,
+    InferredSchema,
+    NumericRange,
+    Schema,
+    ValidationError,
+    ValidationErrorKind,
+    ValidationErrors,
+};
+pub use segment::{
+    SegmentEncoder,
+    SegmentError,
+    SegmentFlags,
+    SegmentHeader,
+    FORMAT_VERSION,
+    LAYOUT_VERSION,
+    SEGMENT_HEADER_SIZE,
+    SEGMENT_MAGIC,
+    SEGMENT_TRAILER_SIZE,
+};
+#[cfg(feature = "serde")]
+pub use serde_compat::{DateFormat, SerdeError, WithDateFormat};
+#[cfg(feature = "serde")]
+pub use serde_de::{from_archived, from_archived_value};
+#[cfg(feature = "serde")]
+pub use serde_ser::{to_document, to_value, ValueSerializer};
+pub use serializer::ScratchStats;
 
 pub use self::core::{
     ArchivedBytes,
@@ -24,4 +115,5 @@ pub use self::core::{
     Document,
     Text,
     Value,
+    ValueKind,
 };
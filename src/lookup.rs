@@ -0,0 +1,625 @@
+//! Field lookups and typed accessors
+//!
+//! These are shared between the owned and archived forms of the core types,
+//! the archived lookups never allocate or deserialize and always hand back
+//! references into the archive buffer.
+
+use std::ops::Deref;
+
+use crate::core::{
+    ArchivedBytes,
+    ArchivedDocument,
+    ArchivedText,
+    ArchivedValue,
+    Bytes,
+    Document,
+    Text,
+    Value,
+};
+
+macro_rules! impl_value_accessors {
+    ($value:ident, $text:ty, $bytes:ty) => {
+        impl $value {
+            #[inline]
+            /// Returns if the value is null.
+            pub fn is_null(&self) -> bool {
+                matches!(self, Self::Null)
+            }
+
+            #[inline]
+            /// Returns the inner value if it is a bool.
+            pub fn as_bool(&self) -> Option<bool> {
+                match self {
+                    Self::Bool(v) => Some(*v),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// Returns the inner value if it is a string.
+            pub fn as_str(&self) -> Option<&str> {
+                match self {
+                    Self::String(v) => Some(v.as_ref()),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// Returns the inner value if it is a bytes value.
+            pub fn as_bytes(&self) -> Option<&[u8]> {
+                match self {
+                    Self::Bytes(v) => Some(v.as_ref()),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// Returns the inner value if it is a u64.
+            pub fn as_u64(&self) -> Option<u64> {
+                match self {
+                    Self::U64(v) => Some(*v),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// Returns the inner value if it is a i64.
+            pub fn as_i64(&self) -> Option<i64> {
+                match self {
+                    Self::I64(v) => Some(*v),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// Returns the inner value if it is a f64.
+            pub fn as_f64(&self) -> Option<f64> {
+                match self {
+                    Self::F64(v) => Some(*v),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// Returns the inner microsecond offset if it is a date.
+            pub fn as_date(&self) -> Option<i64> {
+                match self {
+                    Self::Date(v) => Some(*v),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// Returns the inner values if it is an array of bools.
+            pub fn as_bool_array(&self) -> Option<&[bool]> {
+                match self {
+                    Self::ArrayBool(v) => Some(v.as_slice()),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// Returns the inner values if it is an array of strings.
+            pub fn as_str_array(&self) -> Option<&[$text]> {
+                match self {
+                    Self::ArrayString(v) => Some(v.as_slice()),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// Returns the inner values if it is an array of bytes values.
+            pub fn as_bytes_array(&self) -> Option<&[$bytes]> {
+                match self {
+                    Self::ArrayBytes(v) => Some(v.as_slice()),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// Returns the inner values if it is an array of u64s.
+            pub fn as_u64_array(&self) -> Option<&[u64]> {
+                match self {
+                    Self::ArrayU64(v) => Some(v.as_slice()),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// Returns the inner values if it is an array of i64s.
+            pub fn as_i64_array(&self) -> Option<&[i64]> {
+                match self {
+                    Self::ArrayI64(v) => Some(v.as_slice()),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// Returns the inner values if it is an array of f64s.
+            pub fn as_f64_array(&self) -> Option<&[f64]> {
+                match self {
+                    Self::ArrayF64(v) => Some(v.as_slice()),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// Returns the inner microsecond offsets if it is an array of dates.
+            pub fn as_date_array(&self) -> Option<&[i64]> {
+                match self {
+                    Self::ArrayDate(v) => Some(v.as_slice()),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// Returns the inner values if it is an array of dynamic values.
+            pub fn as_array(&self) -> Option<&[$value]> {
+                match self {
+                    Self::ArrayDynamic(v) => Some(v.as_slice()),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// Returns the inner entries if it is an object.
+            pub fn as_object(&self) -> Option<&[($text, $value)]> {
+                match self {
+                    Self::Object(v) => Some(v.as_slice()),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            /// The object entries or an empty slice if the value is not an object.
+            fn entries(&self) -> &[($text, $value)] {
+                self.as_object().unwrap_or_default()
+            }
+        }
+    };
+}
+
+impl_value_accessors!(Value, Text, Bytes);
+impl_value_accessors!(ArchivedValue, ArchivedText, ArchivedBytes);
+
+macro_rules! impl_field_lookup {
+    ($t:ty, $value:ty, $text:ty, |$this:ident| $entries:expr) => {
+        impl $t {
+            #[inline]
+            /// Get the value of the first entry with the given key.
+            pub fn get(&self, key: &str) -> Option<&$value> {
+                let $this = self;
+                let (entries, sorted) = $entries;
+                find_entry(entries, key, sorted)
+            }
+
+            #[inline]
+            /// Get the values of every entry with the given key in the order
+            /// they were inserted.
+            pub fn get_all<'a>(
+                &'a self,
+                key: &'a str,
+            ) -> impl Iterator<Item = &'a $value> + 'a {
+                let $this = self;
+                let (entries, sorted) = $entries;
+                candidates(entries, key, sorted)
+                    .iter()
+                    .filter(move |(k, _)| k.as_ref() == key)
+                    .map(|(_, v)| v)
+            }
+
+            #[inline]
+            /// Returns if an entry with the given key exists.
+            pub fn contains_key(&self, key: &str) -> bool {
+                self.get(key).is_some()
+            }
+        }
+
+        impl_typed_getters!($t, $value, {
+            get_bool => as_bool -> bool,
+            get_str => as_str -> &str,
+            get_bytes => as_bytes -> &[u8],
+            get_u64 => as_u64 -> u64,
+            get_i64 => as_i64 -> i64,
+            get_f64 => as_f64 -> f64,
+            get_date => as_date -> i64,
+            get_bool_array => as_bool_array -> &[bool],
+            get_str_array => as_str_array -> &[$text],
+            get_u64_array => as_u64_array -> &[u64],
+            get_i64_array => as_i64_array -> &[i64],
+            get_f64_array => as_f64_array -> &[f64],
+            get_date_array => as_date_array -> &[i64],
+            get_array => as_array -> &[$value],
+            get_object => as_object -> &[($text, $value)],
+        });
+    };
+}
+
+macro_rules! impl_typed_getters {
+    ($t:ty, $value:ty, { $($getter:ident => $accessor:ident -> $ret:ty),* $(,)? }) => {
+        impl_typed_getters!($t, $value, |value| value, {
+            $($getter => $accessor -> $ret),*
+        });
+    };
+    ($t:ty, $value:ty, |$v:ident| $project:expr, {
+        $($getter:ident => $accessor:ident -> $ret:ty),* $(,)?
+    }) => {
+        impl $t {
+            $(
+                #[inline]
+                #[doc = concat!(
+                    "Get the value of the first entry with the given key if it is present ",
+                    "and has the type expected by [`", stringify!($accessor), "`](",
+                    stringify!($value), "::", stringify!($accessor), ")."
+                )]
+                pub fn $getter(&self, key: &str) -> Option<$ret> {
+                    self.get(key).and_then(|$v| <$value>::$accessor($project))
+                }
+            )*
+        }
+    };
+}
+
+impl_field_lookup!(Document, Value, Text, |doc| (
+    doc.fields(),
+    doc.is_canonical()
+));
+impl_field_lookup!(ArchivedDocument, ArchivedValue, ArchivedText, |doc| {
+    (doc.fields(), false)
+});
+impl_field_lookup!(Value, Value, Text, |value| (value.entries(), false));
+impl_field_lookup!(ArchivedValue, ArchivedValue, ArchivedText, |value| {
+    (value.entries(), false)
+});
+
+#[derive(Debug, Clone, Copy)]
+/// An archived document in the canonical sorted-key layout.
+///
+/// Whether a document is canonical is not part of its archived representation,
+/// it is a property of how the document was encoded, see
+/// [Encoder::enable_canonical_layout](crate::Encoder::enable_canonical_layout).
+/// Segments and block streams record it with
+/// [SegmentFlags::CANONICAL](crate::SegmentFlags::CANONICAL), and their decoders
+/// produce views with [Decoder::as_canonical](crate::Decoder::as_canonical).
+/// Raw streams do not record it, documents from a raw stream known to be
+/// canonical are viewed with [CanonicalDocument::new_unchecked].
+///
+/// Lookups binary search the fields, and the entries of any object reached
+/// through them, rather than scanning them.
+pub struct CanonicalDocument<'a>(&'a ArchivedDocument);
+
+impl<'a> CanonicalDocument<'a> {
+    /// Views the document in the canonical layout, checking every object
+    /// within it is sorted by key.
+    ///
+    /// This reads the whole document, costing more than the lookups it speeds
+    /// up. Use [Decoder::as_canonical](crate::Decoder::as_canonical) or
+    /// [CanonicalDocument::new_unchecked] when the document is known to have
+    /// been encoded in the canonical layout.
+    pub fn new(document: &'a ArchivedDocument) -> Option<Self> {
+        is_sorted(document.fields()).then_some(Self(document))
+    }
+
+    #[inline]
+    /// Views a document known to be in the canonical layout without checking it.
+    ///
+    /// Lookups on a document which is not sorted may miss entries which exist.
+    pub fn new_unchecked(document: &'a ArchivedDocument) -> Self {
+        Self(document)
+    }
+
+    #[inline]
+    /// The archived document.
+    pub fn document(&self) -> &'a ArchivedDocument {
+        self.0
+    }
+
+    #[inline]
+    /// Get the value of the first entry with the given key.
+    pub fn get(&self, key: &str) -> Option<CanonicalValue<'a>> {
+        find_entry(self.0.fields(), key, true).map(CanonicalValue)
+    }
+
+    #[inline]
+    /// Get the values of every entry with the given key in the order
+    /// they were inserted.
+    pub fn get_all<'k>(
+        &self,
+        key: &'k str,
+    ) -> impl Iterator<Item = CanonicalValue<'a>> + 'k
+    where
+        'a: 'k,
+    {
+        find_all(self.0.fields(), key).map(CanonicalValue)
+    }
+
+    #[inline]
+    /// Returns if an entry with the given key exists.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// An archived value within a [CanonicalDocument].
+///
+/// Lookups binary search the entries if the value is an object.
+pub struct CanonicalValue<'a>(&'a ArchivedValue);
+
+impl<'a> CanonicalValue<'a> {
+    #[inline]
+    /// The archived value.
+    pub fn value(&self) -> &'a ArchivedValue {
+        self.0
+    }
+
+    #[inline]
+    /// Get the value of the first entry with the given key.
+    pub fn get(&self, key: &str) -> Option<CanonicalValue<'a>> {
+        find_entry(self.0.entries(), key, true).map(CanonicalValue)
+    }
+
+    #[inline]
+    /// Get the values of every entry with the given key in the order
+    /// they were inserted.
+    pub fn get_all<'k>(
+        &self,
+        key: &'k str,
+    ) -> impl Iterator<Item = CanonicalValue<'a>> + 'k
+    where
+        'a: 'k,
+    {
+        find_all(self.0.entries(), key).map(CanonicalValue)
+    }
+
+    #[inline]
+    /// Returns if an entry with the given key exists.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+}
+
+impl Deref for CanonicalValue<'_> {
+    type Target = ArchivedValue;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+macro_rules! impl_canonical_getters {
+    ($($t:ty),*) => {
+        $(
+            impl_typed_getters!($t, ArchivedValue, |value| value.value(), {
+                get_bool => as_bool -> bool,
+                get_str => as_str -> &str,
+                get_bytes => as_bytes -> &[u8],
+                get_u64 => as_u64 -> u64,
+                get_i64 => as_i64 -> i64,
+                get_f64 => as_f64 -> f64,
+                get_date => as_date -> i64,
+                get_bool_array => as_bool_array -> &[bool],
+                get_str_array => as_str_array -> &[ArchivedText],
+                get_u64_array => as_u64_array -> &[u64],
+                get_i64_array => as_i64_array -> &[i64],
+                get_f64_array => as_f64_array -> &[f64],
+                get_date_array => as_date_array -> &[i64],
+                get_array => as_array -> &[ArchivedValue],
+                get_object => as_object -> &[(ArchivedText, ArchivedValue)],
+            });
+        )*
+    };
+}
+
+impl_canonical_getters!(CanonicalDocument<'_>, CanonicalValue<'_>);
+
+/// Returns if the entries, and those of every object within them, are sorted by key.
+fn is_sorted(entries: &[(ArchivedText, ArchivedValue)]) -> bool {
+    entries
+        .windows(2)
+        .all(|pair| pair[0].0.as_bytes() <= pair[1].0.as_bytes())
+        && entries.iter().all(|(_, value)| is_value_sorted(value))
+}
+
+fn is_value_sorted(value: &ArchivedValue) -> bool {
+    match value {
+        ArchivedValue::Object(entries) => is_sorted(entries),
+        ArchivedValue::ArrayDynamic(values) => values.iter().all(is_value_sorted),
+        _ => true,
+    }
+}
+
+#[inline]
+/// The values of every entry with the given key in sorted entries.
+fn find_all<'a: 'k, 'k, K: AsRef<str>, V>(
+    entries: &'a [(K, V)],
+    key: &'k str,
+) -> impl Iterator<Item = &'a V> + 'k {
+    candidates(entries, key, true)
+        .iter()
+        .filter(move |(k, _)| k.as_ref() == key)
+        .map(|(_, v)| v)
+}
+
+#[inline]
+/// Finds the value of the first entry with the given key.
+pub(crate) fn find_entry<'a, K: AsRef<str>, V>(
+    entries: &'a [(K, V)],
+    key: &str,
+    sorted: bool,
+) -> Option<&'a V> {
+    candidates(entries, key, sorted)
+        .iter()
+        .find(|(k, _)| k.as_ref() == key)
+        .map(|(_, v)| v)
+}
+
+#[inline]
+/// Narrows the entries down to the run which may contain the key.
+///
+/// Sorted entries are binary searched, otherwise every entry is a candidate.
+fn candidates<'a, K: AsRef<str>, V>(
+    entries: &'a [(K, V)],
+    key: &str,
+    sorted: bool,
+) -> &'a [(K, V)] {
+    if !sorted {
+        return entries;
+    }
+
+    let key = key.as_bytes();
+    let start = entries.partition_point(|(k, _)| k.as_ref().as_bytes() < key);
+    let len = entries[start..].partition_point(|(k, _)| k.as_ref().as_bytes() == key);
+    &entries[start..start + len]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_document() -> Document {
+        let mut document = Document::default();
+        document.insert("id", Value::U64(1));
+        document.insert("name", Value::String(Text::from("bellini")));
+        document.insert("tags", Value::ArrayString(vec![Text::from("a")]));
+        document.insert("tags", Value::ArrayString(vec![Text::from("b")]));
+        document.insert("scores", Value::ArrayF64(vec![1.5, 2.5]));
+        document.insert(
+            "user",
+            Value::Object(vec![
+                (Text::from("age"), Value::I64(-3)),
+                (Text::from("active"), Value::Bool(true)),
+            ]),
+        );
+        document
+    }
+
+    #[test]
+    fn test_owned_lookup() {
+        let document = test_document();
+
+        assert_eq!(document.get_u64("id"), Some(1));
+        assert_eq!(document.get_str("name"), Some("bellini"));
+        assert_eq!(
+            document.get_f64_array("scores"),
+            Some([1.5, 2.5].as_slice())
+        );
+        assert_eq!(
+            document.get_i64("id"),
+            None,
+            "Type mismatch should return None"
+        );
+        assert!(document.contains_key("user"));
+        assert!(!document.contains_key("missing"));
+
+        let tags = document
+            .get_all("tags")
+            .map(|v| v.as_str_array().unwrap()[0].as_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(tags, ["a", "b"], "All entries should be returned in order");
+
+        let user = document.get("user").expect("Object should exist");
+        assert_eq!(user.get_i64("age"), Some(-3));
+        assert_eq!(user.get_bool("active"), Some(true));
+        assert!(document.get("name").unwrap().get("age").is_none());
+    }
+
+    #[test]
+    fn test_archived_lookup() {
+        let document = test_document();
+        let bytes = rkyv::to_bytes::<_, 256>(&document).expect("Serialize document");
+        let archived = rkyv::check_archived_root::<Document>(&bytes)
+            .expect("Archived document should be valid");
+
+        assert_eq!(archived.get_u64("id"), Some(1));
+        assert_eq!(archived.get_str("name"), Some("bellini"));
+        assert_eq!(
+            archived.get_f64_array("scores"),
+            Some([1.5, 2.5].as_slice())
+        );
+        assert!(archived.contains_key("tags"));
+        assert_eq!(archived.get_all("tags").count(), 2);
+
+        let user = archived.get("user").expect("Object should exist");
+        assert_eq!(user.get_i64("age"), Some(-3));
+        assert_eq!(user.get_bool("active"), Some(true));
+        assert!(user.get_object("age").is_none());
+    }
+
+    #[test]
+    fn test_canonical_lookup() {
+        let mut document = test_document();
+        document.canonicalize();
+        assert!(document.is_canonical());
+
+        let keys = document
+            .fields()
+            .iter()
+            .map(|(k, _)| k.as_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, ["id", "name", "scores", "tags", "tags", "user"]);
+        assert_eq!(document.get_u64("id"), Some(1));
+        assert_eq!(document.get_all("tags").count(), 2);
+        assert!(!document.contains_key("missing"));
+
+        let mut sorted = Document::default();
+        for (key, value) in document.fields() {
+            sorted.insert(key.clone(), value.clone());
+        }
+        assert!(!sorted.is_canonical());
+        assert_eq!(sorted, document, "The layout should not affect equality");
+
+        let bytes = rkyv::to_bytes::<_, 256>(&document).expect("Serialize document");
+        let archived = rkyv::check_archived_root::<Document>(&bytes)
+            .expect("Archived document should be valid");
+        let canonical =
+            CanonicalDocument::new(archived).expect("Document should be canonical");
+
+        let tags = canonical
+            .get_all("tags")
+            .map(|v| v.value().as_str_array().unwrap()[0].as_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(tags, ["a", "b"], "Sort should keep duplicate keys in order");
+        assert_eq!(canonical.get_str("name"), Some("bellini"));
+        assert!(canonical.get("aaa").is_none());
+        assert!(canonical.get("zzz").is_none());
+
+        let user = canonical.get("user").expect("Object should exist");
+        assert_eq!(user.get_i64("age"), Some(-3));
+        assert_eq!(user.get_bool("active"), Some(true));
+        assert!(!user.contains_key("missing"));
+
+        document.insert("aaa", Value::Null);
+        assert!(
+            !document.is_canonical(),
+            "Inserting should reset the layout"
+        );
+        assert!(document.contains_key("aaa"));
+    }
+
+    #[test]
+    fn test_canonical_rejects_unsorted() {
+        let document = test_document();
+        let bytes = rkyv::to_bytes::<_, 256>(&document).expect("Serialize document");
+        let archived = rkyv::check_archived_root::<Document>(&bytes)
+            .expect("Archived document should be valid");
+        assert!(CanonicalDocument::new(archived).is_none());
+
+        let mut document = Document::default();
+        document.insert("a", Value::U64(1));
+        document.insert(
+            "b",
+            Value::Object(vec![
+                (Text::from("z"), Value::Null),
+                (Text::from("y"), Value::Null),
+            ]),
+        );
+        let bytes = rkyv::to_bytes::<_, 256>(&document).expect("Serialize document");
+        let archived = rkyv::check_archived_root::<Document>(&bytes)
+            .expect("Archived document should be valid");
+        assert!(
+            CanonicalDocument::new(archived).is_none(),
+            "Nested objects should be checked"
+        );
+    }
+}
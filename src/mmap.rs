@@ -0,0 +1,254 @@
+//! Memory mapped segment files
+//!
+//! A mapping always starts on a page boundary, so a segment file is mapped with
+//! every document aligned as long as the segment's alignment is no larger than
+//! the page size. Segments which cannot be aligned are rejected when opened
+//! rather than failing on each document.
+
+use std::fs::File;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::decoder::{open_segment, SegmentParts};
+use crate::{
+    ArchivedDocument,
+    ArchivedIterator,
+    CanonicalDocument,
+    CheckedArchiver,
+    Decoder,
+    DeserializerIterator,
+    Document,
+    Result,
+    UnsafeArchiver,
+};
+
+/// A decoder for segment files written by a [SegmentEncoder](crate::SegmentEncoder),
+/// reading the documents directly from a memory mapping of the file.
+///
+/// The header, checksum and position index are checked once when the file is
+/// mapped, see [Decoder::open]. Documents can then be read with the same API as
+/// the [Decoder], or with a [Decoder] borrowed from the mapping.
+pub struct MmapDecoder {
+    mmap: Mmap,
+    parts: SegmentParts,
+    validate_checksum: bool,
+}
+
+impl MmapDecoder {
+    /// Opens and maps the segment file at the given path.
+    ///
+    /// ### Safety
+    /// See [MmapDecoder::map].
+    pub unsafe fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        Self::map(&file)
+    }
+
+    /// Maps the given segment file.
+    ///
+    /// Verifying the checksum reads the whole file.
+    ///
+    /// ### Safety
+    /// The file must not be modified, including by other processes, while it is
+    /// mapped. Any change to the mapped bytes after they have been checked is
+    /// immediately UB.
+    pub unsafe fn map(file: &File) -> Result<Self> {
+        let mmap = Mmap::map(file)?;
+        let parts = open_segment(&mmap)?;
+
+        Ok(Self {
+            mmap,
+            parts,
+            validate_checksum: false,
+        })
+    }
+
+    #[inline]
+    /// Enables validation of the document checksum.
+    pub fn enable_checksum_validation(&mut self) {
+        self.validate_checksum = true;
+    }
+
+    #[inline]
+    /// Returns if the documents were encoded in the canonical layout, see
+    /// [Decoder::as_canonical].
+    pub fn is_canonical(&self) -> bool {
+        self.parts.canonical
+    }
+
+    #[inline]
+    /// A decoder over the documents of the mapped segment.
+    pub fn decoder(&self) -> Decoder<'_> {
+        // SAFETY:
+        //  The parts were read from the mapping, which is never modified.
+        let mut decoder =
+            unsafe { Decoder::from_segment_parts(&self.mmap, &self.parts) };
+        if self.validate_checksum {
+            decoder.enable_checksum_validation();
+        }
+        decoder
+    }
+
+    #[inline]
+    /// Deserialize the document at the given idx position, see
+    /// [Decoder::deserialize_at].
+    pub fn deserialize_at(&self, idx: usize) -> Result<Option<Document>> {
+        self.decoder().deserialize_at(idx)
+    }
+
+    #[inline]
+    /// Gets the archived value located at the given idx position.
+    ///
+    /// ### Safety
+    /// See [Decoder::archived_at].
+    pub unsafe fn archived_at(
+        &self,
+        idx: usize,
+    ) -> Result<Option<&rkyv::Archived<Document>>> {
+        self.decoder().archived_at(idx)
+    }
+
+    #[inline]
+    /// Gets the archived value located at the given idx position,
+    /// see [Decoder::checked_archived_at].
+    pub fn checked_archived_at(
+        &self,
+        idx: usize,
+    ) -> Result<Option<&rkyv::Archived<Document>>> {
+        self.decoder().checked_archived_at(idx)
+    }
+
+    #[inline]
+    /// Views a document from the mapped segment in the canonical layout,
+    /// see [Decoder::as_canonical].
+    pub fn as_canonical<'a>(
+        &'a self,
+        document: &'a ArchivedDocument,
+    ) -> Option<CanonicalDocument<'a>> {
+        self.decoder().as_canonical(document)
+    }
+
+    #[inline]
+    /// Create a new iterator for deserializing all docs within the segment.
+    pub fn deserializer_iter(&self) -> DeserializerIterator<'_> {
+        self.decoder().deserializer_iter()
+    }
+
+    #[inline]
+    /// An unsafe archive iterator.
+    ///
+    /// ### Safety
+    /// See [Decoder::archived_iter].
+    pub unsafe fn archived_iter(&self) -> ArchivedIterator<'_, UnsafeArchiver> {
+        self.decoder().archived_iter()
+    }
+
+    #[inline]
+    /// A archive iterator, see [Decoder::checked_archived_iter].
+    pub fn checked_archived_iter(&self) -> ArchivedIterator<'_, CheckedArchiver> {
+        self.decoder().checked_archived_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::decoder::RecordLayout;
+    use crate::test_utils::{documents, encode_segment};
+    use crate::{Alignment, Error, SegmentError};
+
+    /// A segment file which is removed once dropped.
+    struct SegmentFile(PathBuf);
+
+    impl SegmentFile {
+        fn write(name: &str, buf: &[u8]) -> Self {
+            let path = env::temp_dir().join(format!("bellini-{}-{name}", process::id()));
+            fs::write(&path, buf).expect("Write segment file");
+            Self(path)
+        }
+    }
+
+    impl Drop for SegmentFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_mmap_decoder() {
+        for alignment in [16, 64, 4096] {
+            let documents = documents(10);
+            let file = SegmentFile::write(
+                &format!("mmap-{alignment}"),
+                &encode_segment(
+                    &documents,
+                    RecordLayout {
+                        alignment: Alignment::Padded(alignment),
+                        ..RecordLayout::default()
+                    },
+                ),
+            );
+
+            let mut decoder =
+                unsafe { MmapDecoder::open(&file.0) }.expect("Map segment");
+            decoder.enable_checksum_validation();
+
+            for (idx, document) in documents.iter().enumerate() {
+                assert_eq!(
+                    decoder.deserialize_at(idx).unwrap().as_ref(),
+                    Some(document)
+                );
+                let archived = decoder
+                    .checked_archived_at(idx)
+                    .unwrap()
+                    .expect("Document should exist");
+                assert_eq!(
+                    archived.get("id").and_then(|id| id.as_u64()),
+                    Some(idx as u64)
+                );
+            }
+            assert!(decoder.deserialize_at(10).unwrap().is_none());
+
+            // Misaligned documents would be rejected with an error.
+            let archived = unsafe { decoder.archived_iter() }
+                .rev()
+                .collect::<Result<Vec<_>>>()
+                .expect("Every document should be aligned");
+            assert_eq!(archived.len(), 10);
+
+            let mut returned = decoder
+                .deserializer_iter()
+                .collect::<Result<Vec<_>>>()
+                .expect("Every document should be readable");
+            returned.reverse();
+            assert_eq!(returned, documents);
+        }
+    }
+
+    #[test]
+    fn test_mmap_decoder_rejects_corrupt_files() {
+        let mut buf = encode_segment(&documents(3), RecordLayout::default());
+        let len = buf.len();
+        buf[len - 1] = b'X';
+        let file = SegmentFile::write("corrupt", &buf);
+        assert!(matches!(
+            unsafe { MmapDecoder::open(&file.0) },
+            Err(Error::Segment(SegmentError::BadMagic))
+        ));
+
+        let file = SegmentFile::write("empty", &[]);
+        assert!(matches!(
+            unsafe { MmapDecoder::open(&file.0) },
+            Err(Error::Segment(SegmentError::Truncated { len: 0 }))
+        ));
+
+        assert!(matches!(
+            unsafe { MmapDecoder::open(env::temp_dir().join("bellini-missing")) },
+            Err(Error::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,626 @@
+//! Path queries over archived documents
+//!
+//! Paths can be given either as a JSON pointer (`/user/addresses/0/city`) or in
+//! the dotted form (`user.addresses[0].city`), both are resolved directly against
+//! the archived data without deserializing any of it.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::core::{ArchivedBytes, ArchivedDocument, ArchivedText, ArchivedValue};
+use crate::lookup::{find_entry, CanonicalDocument};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A parsed path which can be resolved against many documents.
+///
+/// Keys containing `.` or `[` can only be expressed using the JSON pointer form.
+pub struct Path {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// A key, or an index when applied to an array.
+    Token(String),
+    /// An explicit `[n]` array index.
+    Index(usize),
+}
+
+impl Path {
+    /// Parses a path in either the JSON pointer or dotted form.
+    ///
+    /// Paths starting with `/` are treated as JSON pointers, the empty path
+    /// refers to the document itself.
+    pub fn parse(path: &str) -> Result<Self, PathError> {
+        let segments = if path.is_empty() {
+            Vec::new()
+        } else if let Some(pointer) = path.strip_prefix('/') {
+            pointer
+                .split('/')
+                .map(|token| unescape_token(path, token).map(Segment::Token))
+                .collect::<Result<_, _>>()?
+        } else {
+            parse_dotted(path)?
+        };
+
+        Ok(Self { segments })
+    }
+
+    /// Resolves the path against the given document.
+    pub fn resolve<'a>(
+        &self,
+        document: &'a ArchivedDocument,
+    ) -> Result<ArchivedValueRef<'a>, PathError> {
+        self.walk(ArchivedValueRef::Document(document), false)
+    }
+
+    /// Resolves the path against a document in the canonical layout,
+    /// binary searching each object along the path.
+    pub fn resolve_canonical<'a>(
+        &self,
+        document: CanonicalDocument<'a>,
+    ) -> Result<ArchivedValueRef<'a>, PathError> {
+        self.walk(ArchivedValueRef::Document(document.document()), true)
+    }
+
+    /// Resolves the path against the given value.
+    pub fn resolve_value<'a>(
+        &self,
+        value: &'a ArchivedValue,
+    ) -> Result<ArchivedValueRef<'a>, PathError> {
+        self.walk(ArchivedValueRef::Value(value), false)
+    }
+
+    fn walk<'a>(
+        &self,
+        mut current: ArchivedValueRef<'a>,
+        sorted: bool,
+    ) -> Result<ArchivedValueRef<'a>, PathError> {
+        for pos in 0..self.segments.len() {
+            current = match current {
+                ArchivedValueRef::Document(document) => {
+                    self.lookup_key(pos, document.fields(), sorted, "object")?
+                },
+                ArchivedValueRef::Value(ArchivedValue::Object(entries)) => {
+                    self.lookup_key(pos, entries, sorted, "object")?
+                },
+                ArchivedValueRef::Value(value) => self.lookup_index(pos, value)?,
+                other => {
+                    return Err(PathError::TypeMismatch {
+                        path: self.pointer_to(pos),
+                        expected: "object or array",
+                        found: other.as_type(),
+                    })
+                },
+            };
+        }
+
+        Ok(current)
+    }
+
+    fn lookup_key<'a>(
+        &self,
+        pos: usize,
+        entries: &'a [(ArchivedText, ArchivedValue)],
+        sorted: bool,
+        found: &'static str,
+    ) -> Result<ArchivedValueRef<'a>, PathError> {
+        let key = match &self.segments[pos] {
+            Segment::Token(key) => key,
+            Segment::Index(_) => {
+                return Err(PathError::TypeMismatch {
+                    path: self.pointer_to(pos),
+                    expected: "array",
+                    found,
+                })
+            },
+        };
+
+        find_entry(entries, key, sorted)
+            .map(ArchivedValueRef::Value)
+            .ok_or_else(|| PathError::NotFound {
+                path: self.pointer_to(pos),
+            })
+    }
+
+    fn lookup_index<'a>(
+        &self,
+        pos: usize,
+        value: &'a ArchivedValue,
+    ) -> Result<ArchivedValueRef<'a>, PathError> {
+        let len = value.array_len().ok_or_else(|| PathError::TypeMismatch {
+            path: self.pointer_to(pos),
+            expected: "object or array",
+            found: value.as_type(),
+        })?;
+
+        let index = match &self.segments[pos] {
+            Segment::Index(index) => *index,
+            Segment::Token(token) => {
+                parse_index(token).ok_or_else(|| PathError::InvalidIndex {
+                    path: self.pointer_to(pos),
+                    segment: token.clone(),
+                })?
+            },
+        };
+
+        value
+            .array_element(index)
+            .ok_or_else(|| PathError::IndexOutOfRange {
+                path: self.pointer_to(pos),
+                index,
+                len,
+            })
+    }
+
+    /// Renders the path up to and including the given segment as a JSON pointer.
+    fn pointer_to(&self, pos: usize) -> String {
+        let mut pointer = String::new();
+        for segment in &self.segments[..=pos] {
+            pointer.push('/');
+            match segment {
+                Segment::Token(token) => {
+                    pointer.push_str(&token.replace('~', "~0").replace('/', "~1"))
+                },
+                Segment::Index(index) => pointer.push_str(&index.to_string()),
+            }
+        }
+        pointer
+    }
+}
+
+impl FromStr for Path {
+    type Err = PathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.segments.is_empty() {
+            return Ok(());
+        }
+        write!(f, "{}", self.pointer_to(self.segments.len() - 1))
+    }
+}
+
+fn unescape_token(path: &str, token: &str) -> Result<String, PathError> {
+    let mut unescaped = String::with_capacity(token.len());
+    let mut chars = token.chars();
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('0') => unescaped.push('~'),
+            Some('1') => unescaped.push('/'),
+            _ => {
+                return Err(PathError::Invalid {
+                    path: path.to_owned(),
+                    reason: "`~` must be followed by `0` or `1`",
+                })
+            },
+        }
+    }
+    Ok(unescaped)
+}
+
+fn parse_dotted(path: &str) -> Result<Vec<Segment>, PathError> {
+    let invalid = |reason| PathError::Invalid {
+        path: path.to_owned(),
+        reason,
+    };
+
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let (key, mut indexes) = match part.find('[') {
+            Some(start) => part.split_at(start),
+            None => (part, ""),
+        };
+
+        if key.is_empty() {
+            return Err(invalid("keys cannot be empty"));
+        }
+        segments.push(Segment::Token(key.to_owned()));
+
+        while !indexes.is_empty() {
+            let end = indexes.find(']').ok_or_else(|| invalid("unclosed `[`"))?;
+            let index = parse_index(&indexes[1..end])
+                .ok_or_else(|| invalid("indexes must be a non-negative integer"))?;
+            segments.push(Segment::Index(index));
+
+            indexes = &indexes[end + 1..];
+            if !indexes.is_empty() && !indexes.starts_with('[') {
+                return Err(invalid("expected `.` or `[` after `]`"));
+            }
+        }
+    }
+
+    Ok(segments)
+}
+
+fn parse_index(token: &str) -> Option<usize> {
+    let is_canonical = token == "0" || !token.starts_with('0');
+    if token.is_empty() || !is_canonical || !token.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    token.parse().ok()
+}
+
+#[derive(Debug, Clone, Copy)]
+/// A borrowed view of whatever sits at a path within an archived document.
+///
+/// Elements of the typed arrays are not stored as an [ArchivedValue], so
+/// they are returned as their own variants.
+pub enum ArchivedValueRef<'a> {
+    /// The document itself, produced by the empty path.
+    Document(&'a ArchivedDocument),
+    /// A value stored in an object or dynamic array.
+    Value(&'a ArchivedValue),
+    /// An element of a bool array.
+    Bool(bool),
+    /// An element of a string array.
+    String(&'a ArchivedText),
+    /// An element of a bytes array.
+    Bytes(&'a ArchivedBytes),
+    /// An element of a u64 array.
+    U64(u64),
+    /// An element of a i64 array.
+    I64(i64),
+    /// An element of a f64 array.
+    F64(f64),
+    /// An element of a date array.
+    Date(i64),
+}
+
+impl<'a> ArchivedValueRef<'a> {
+    /// Get the string representation of the type being viewed.
+    pub fn as_type(&self) -> &'static str {
+        match *self {
+            ArchivedValueRef::Document(_) => "object",
+            ArchivedValueRef::Value(value) => value.as_type(),
+            ArchivedValueRef::Bool(_) => "bool",
+            ArchivedValueRef::String(_) => "string",
+            ArchivedValueRef::Bytes(_) => "bytes",
+            ArchivedValueRef::U64(_) => "u64",
+            ArchivedValueRef::I64(_) => "i64",
+            ArchivedValueRef::F64(_) => "f64",
+            ArchivedValueRef::Date(_) => "datetime",
+        }
+    }
+
+    #[inline]
+    /// Returns the archived value if the view is of a value.
+    pub fn as_value(&self) -> Option<&'a ArchivedValue> {
+        match *self {
+            ArchivedValueRef::Value(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    /// Returns the string if the view is of a string value or element.
+    pub fn as_str(&self) -> Option<&'a str> {
+        match *self {
+            ArchivedValueRef::Value(value) => value.as_str(),
+            ArchivedValueRef::String(v) => Some(v.as_ref()),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    /// Returns the u64 if the view is of a u64 value or element.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            ArchivedValueRef::Value(value) => value.as_u64(),
+            ArchivedValueRef::U64(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    /// Returns the i64 if the view is of a i64 value or element.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            ArchivedValueRef::Value(value) => value.as_i64(),
+            ArchivedValueRef::I64(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    /// Returns the f64 if the view is of a f64 value or element.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            ArchivedValueRef::Value(value) => value.as_f64(),
+            ArchivedValueRef::F64(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl ArchivedDocument {
+    #[inline]
+    /// Resolve a JSON pointer or dotted path against the document.
+    pub fn get_path(&self, path: &str) -> Result<ArchivedValueRef<'_>, PathError> {
+        Path::parse(path)?.resolve(self)
+    }
+}
+
+impl<'a> CanonicalDocument<'a> {
+    #[inline]
+    /// Resolve a JSON pointer or dotted path against the document,
+    /// binary searching each object along the path.
+    pub fn get_path(&self, path: &str) -> Result<ArchivedValueRef<'a>, PathError> {
+        Path::parse(path)?.resolve_canonical(*self)
+    }
+}
+
+impl ArchivedValue {
+    #[inline]
+    /// Resolve a JSON pointer or dotted path against the value.
+    pub fn get_path(&self, path: &str) -> Result<ArchivedValueRef<'_>, PathError> {
+        Path::parse(path)?.resolve_value(self)
+    }
+
+    #[inline]
+    /// The number of elements if the value is an array.
+    pub(crate) fn array_len(&self) -> Option<usize> {
+        match self {
+            ArchivedValue::ArrayBool(values) => Some(values.len()),
+            ArchivedValue::ArrayString(values) => Some(values.len()),
+            ArchivedValue::ArrayBytes(values) => Some(values.len()),
+            ArchivedValue::ArrayU64(values) => Some(values.len()),
+            ArchivedValue::ArrayI64(values) => Some(values.len()),
+            ArchivedValue::ArrayF64(values) => Some(values.len()),
+            ArchivedValue::ArrayDate(values) => Some(values.len()),
+            ArchivedValue::ArrayDynamic(values) => Some(values.len()),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    /// A view of the element at the given index if the value is an array.
+    pub(crate) fn array_element(&self, index: usize) -> Option<ArchivedValueRef<'_>> {
+        let element = match self {
+            ArchivedValue::ArrayBool(values) => {
+                ArchivedValueRef::Bool(*values.get(index)?)
+            },
+            ArchivedValue::ArrayString(values) => {
+                ArchivedValueRef::String(values.get(index)?)
+            },
+            ArchivedValue::ArrayBytes(values) => {
+                ArchivedValueRef::Bytes(values.get(index)?)
+            },
+            ArchivedValue::ArrayU64(values) => {
+                ArchivedValueRef::U64(*values.get(index)?)
+            },
+            ArchivedValue::ArrayI64(values) => {
+                ArchivedValueRef::I64(*values.get(index)?)
+            },
+            ArchivedValue::ArrayF64(values) => {
+                ArchivedValueRef::F64(*values.get(index)?)
+            },
+            ArchivedValue::ArrayDate(values) => {
+                ArchivedValueRef::Date(*values.get(index)?)
+            },
+            ArchivedValue::ArrayDynamic(values) => {
+                ArchivedValueRef::Value(values.get(index)?)
+            },
+            _ => return None,
+        };
+        Some(element)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An error produced while parsing or resolving a path.
+///
+/// Each error carries the JSON pointer of the segment that failed.
+pub enum PathError {
+    /// The path could not be parsed.
+    Invalid { path: String, reason: &'static str },
+    /// No entry exists with the key.
+    NotFound { path: String },
+    /// The segment could not be used as an array index.
+    InvalidIndex { path: String, segment: String },
+    /// The index was outside the bounds of the array.
+    IndexOutOfRange {
+        path: String,
+        index: usize,
+        len: usize,
+    },
+    /// The segment tried to descend into a value of the wrong type.
+    TypeMismatch {
+        path: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid { path, reason } => {
+                write!(f, "invalid path {path:?}: {reason}")
+            },
+            Self::NotFound { path } => write!(f, "no value exists at {path:?}"),
+            Self::InvalidIndex { path, segment } => {
+                write!(f, "cannot resolve {path:?}: {segment:?} is not an array index")
+            },
+            Self::IndexOutOfRange { path, index, len } => write!(
+                f,
+                "cannot resolve {path:?}: index {index} is out of range for array of length {len}"
+            ),
+            Self::TypeMismatch {
+                path,
+                expected,
+                found,
+            } => write!(f, "cannot resolve {path:?}: expected {expected}, found {found}"),
+        }
+    }
+}
+
+impl Error for PathError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Document, Text, Value};
+
+    fn test_document() -> Document {
+        let mut document = Document::default();
+        document.insert(
+            "user",
+            Value::Object(vec![
+                (Text::from("name"), Value::String(Text::from("bobby"))),
+                (
+                    Text::from("addresses"),
+                    Value::ArrayDynamic(vec![Value::Object(vec![(
+                        Text::from("city"),
+                        Value::String(Text::from("London")),
+                    )])]),
+                ),
+                (Text::from("scores"), Value::ArrayU64(vec![3, 4])),
+                (Text::from("a/b~c"), Value::Bool(true)),
+            ]),
+        );
+        document.insert("tags", Value::ArrayString(vec![Text::from("rust")]));
+        document
+    }
+
+    #[test]
+    fn test_parse_paths() {
+        assert_eq!(
+            Path::parse("/user/addresses/0/city").unwrap(),
+            Path::parse("user.addresses[0].city")
+                .unwrap()
+                .to_string()
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(Path::parse("/a~1b~0c").unwrap().to_string(), "/a~1b~0c");
+        assert_eq!(Path::parse("").unwrap().to_string(), "");
+        assert_eq!(Path::parse("a[0][1]").unwrap().to_string(), "/a/0/1");
+
+        assert!(matches!(
+            Path::parse("/a~2"),
+            Err(PathError::Invalid { .. })
+        ));
+        assert!(matches!(
+            Path::parse("a..b"),
+            Err(PathError::Invalid { .. })
+        ));
+        assert!(matches!(Path::parse("a[0"), Err(PathError::Invalid { .. })));
+        assert!(matches!(
+            Path::parse("a[x]"),
+            Err(PathError::Invalid { .. })
+        ));
+        assert!(matches!(
+            Path::parse("a[0]b"),
+            Err(PathError::Invalid { .. })
+        ));
+    }
+
+    #[test]
+    fn test_resolve_paths() {
+        for canonical in [false, true] {
+            let mut document = test_document();
+            if canonical {
+                document.canonicalize();
+            }
+
+            let bytes = rkyv::to_bytes::<_, 256>(&document).expect("Serialize document");
+            let archived = rkyv::check_archived_root::<Document>(&bytes)
+                .expect("Archived document should be valid");
+
+            if canonical {
+                let canonical = CanonicalDocument::new(archived)
+                    .expect("Document should be in the canonical layout");
+                let city = canonical.get_path("/user/addresses/0/city").unwrap();
+                assert_eq!(city.as_str(), Some("London"));
+                assert!(matches!(
+                    canonical.get_path("/user/missing"),
+                    Err(PathError::NotFound { .. })
+                ));
+            }
+
+            let city = archived.get_path("/user/addresses/0/city").unwrap();
+            assert_eq!(city.as_str(), Some("London"));
+            let city = archived.get_path("user.addresses[0].city").unwrap();
+            assert_eq!(city.as_str(), Some("London"));
+
+            let score = archived.get_path("user.scores[1]").unwrap();
+            assert_eq!(score.as_u64(), Some(4));
+            let tag = archived.get_path("/tags/0").unwrap();
+            assert_eq!(tag.as_str(), Some("rust"));
+            let escaped = archived.get_path("/user/a~1b~0c").unwrap();
+            assert_eq!(escaped.as_type(), "bool");
+            let root = archived.get_path("").unwrap();
+            assert_eq!(root.as_type(), "object");
+
+            let user = archived.get("user").unwrap();
+            let name = user.get_path("name").unwrap();
+            assert_eq!(name.as_str(), Some("bobby"));
+        }
+    }
+
+    #[test]
+    fn test_resolve_errors() {
+        let document = test_document();
+        let bytes = rkyv::to_bytes::<_, 256>(&document).expect("Serialize document");
+        let archived = rkyv::check_archived_root::<Document>(&bytes)
+            .expect("Archived document should be valid");
+
+        assert_eq!(
+            archived.get_path("user.name.first").unwrap_err(),
+            PathError::TypeMismatch {
+                path: "/user/name/first".into(),
+                expected: "object or array",
+                found: "string",
+            },
+        );
+        assert_eq!(
+            archived.get_path("/user/scores/2").unwrap_err(),
+            PathError::IndexOutOfRange {
+                path: "/user/scores/2".into(),
+                index: 2,
+                len: 2,
+            },
+        );
+        assert_eq!(
+            archived.get_path("/user/scores/0/x").unwrap_err(),
+            PathError::TypeMismatch {
+                path: "/user/scores/0/x".into(),
+                expected: "object or array",
+                found: "u64",
+            },
+        );
+        assert_eq!(
+            archived.get_path("/user/scores/first").unwrap_err(),
+            PathError::InvalidIndex {
+                path: "/user/scores/first".into(),
+                segment: "first".into(),
+            },
+        );
+        assert_eq!(
+            archived.get_path("user[0]").unwrap_err(),
+            PathError::TypeMismatch {
+                path: "/user/0".into(),
+                expected: "array",
+                found: "object",
+            },
+        );
+        assert_eq!(
+            archived.get_path("/missing").unwrap_err(),
+            PathError::NotFound {
+                path: "/missing".into()
+            },
+        );
+    }
+}
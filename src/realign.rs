@@ -0,0 +1,333 @@
+//! Decoding documents from buffers which may not be aligned
+//!
+//! A [Decoder] rejects every document of a buffer which does not start on a
+//! multiple of its alignment, e.g. a slice out of a network buffer. A
+//! [RealigningDecoder] instead copies each misaligned document into a reusable
+//! aligned scratch buffer before archiving it.
+
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+use rkyv::AlignedVec;
+
+use crate::decoder::BufferWalker;
+#[cfg(any(feature = "validation", test))]
+use crate::decoder::CheckedArchiver;
+use crate::{
+    ArchivedDocument,
+    Archiver,
+    BoxedError,
+    Decoder,
+    Error,
+    Result,
+    UnsafeArchiver,
+};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+/// Statistics on how often documents had to be copied to be aligned.
+pub struct RealignStats {
+    /// The number of documents which were copied into the scratch buffer.
+    pub copies: u64,
+    /// The total number of bytes copied.
+    pub copied_bytes: u64,
+}
+
+#[derive(Debug)]
+/// An archived document produced by a [RealigningDecoder].
+///
+/// The document either borrows the decoder's buffer directly, or the scratch
+/// buffer it was copied into, so it must be dropped before the next document
+/// is produced.
+pub struct RealignedDocument<'r> {
+    archived: &'r ArchivedDocument,
+    copied: bool,
+}
+
+impl<'r> RealignedDocument<'r> {
+    #[inline]
+    /// Returns if the document was copied into the scratch buffer.
+    pub fn was_copied(&self) -> bool {
+        self.copied
+    }
+}
+
+impl<'r> Deref for RealignedDocument<'r> {
+    type Target = ArchivedDocument;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self.archived
+    }
+}
+
+/// A decoder which copies misaligned documents into aligned storage on demand.
+///
+/// Documents are copied when they are not aligned to [AlignedVec::ALIGNMENT],
+/// which satisfies the alignment of every archived value. Aligned documents are
+/// archived in place, so realigning costs nothing for aligned buffers.
+///
+/// As each document may borrow the scratch buffer, documents are produced one
+/// at a time rather than through an iterator.
+pub struct RealigningDecoder<'a, A: Archiver> {
+    decoder: Decoder<'a>,
+    walker: BufferWalker<'a>,
+    scratch: AlignedVec,
+    stats: RealignStats,
+    _phantom: PhantomData<A>,
+}
+
+impl<'a> Decoder<'a> {
+    /// A realigning decoder which performs no validation of the documents.
+    ///
+    /// ### Safety
+    /// You **must** ensure the provided buffer has the correct layout,
+    /// otherwise this is immediately UB.
+    pub unsafe fn realigning(self) -> RealigningDecoder<'a, UnsafeArchiver> {
+        RealigningDecoder::new(self)
+    }
+
+    #[cfg(any(feature = "validation", test))]
+    /// A realigning decoder which validates each document, providing a safe
+    /// API rather than becoming UB on an invalid buffer being provided.
+    pub fn checked_realigning(self) -> RealigningDecoder<'a, CheckedArchiver> {
+        RealigningDecoder::new(self)
+    }
+}
+
+impl<'a, A: Archiver> RealigningDecoder<'a, A> {
+    fn new(decoder: Decoder<'a>) -> Self {
+        Self {
+            walker: decoder.unaligned_walker(),
+            decoder,
+            scratch: AlignedVec::new(),
+            stats: RealignStats::default(),
+            _phantom: PhantomData,
+        }
+    }
+
+    #[inline]
+    /// Returns how often documents have been copied to be aligned.
+    pub fn stats(&self) -> RealignStats {
+        self.stats
+    }
+
+    /// Gets the archived value located at the given idx position,
+    /// see [Decoder::archived_at].
+    pub fn archived_at(&mut self, idx: usize) -> Result<Option<RealignedDocument<'_>>> {
+        let (data, offset) = match self.decoder.unaligned_doc_slice_at(idx)? {
+            Some(slice) => slice,
+            None => return Ok(None),
+        };
+        Self::realign(&mut self.scratch, &mut self.stats, data)
+            .map(Some)
+            .map_err(|source| Error::InvalidArchive {
+                index: idx,
+                offset,
+                source,
+            })
+    }
+
+    /// Produces the next document, newest first, like iterating the
+    /// [ArchivedIterator](crate::ArchivedIterator).
+    pub fn next_document(&mut self) -> Option<Result<RealignedDocument<'_>>> {
+        let data = self.walker.next()?;
+        Some(data.and_then(|data| {
+            Self::realign(&mut self.scratch, &mut self.stats, data)
+                .map_err(|source| invalid_archive(&mut self.walker, source))
+        }))
+    }
+
+    /// Produces the next document from the other end, oldest first, like
+    /// iterating the [ArchivedIterator](crate::ArchivedIterator) in reverse.
+    pub fn next_back_document(&mut self) -> Option<Result<RealignedDocument<'_>>> {
+        let data = self.walker.next_back()?;
+        Some(data.and_then(|data| {
+            Self::realign(&mut self.scratch, &mut self.stats, data)
+                .map_err(|source| invalid_archive(&mut self.walker, source))
+        }))
+    }
+
+    /// Copies the document data into the scratch buffer if it is misaligned,
+    /// then archives it.
+    fn realign<'r>(
+        scratch: &'r mut AlignedVec,
+        stats: &mut RealignStats,
+        data: &'r [u8],
+    ) -> Result<RealignedDocument<'r>, BoxedError> {
+        let copied = !(data.as_ptr() as usize).is_multiple_of(AlignedVec::ALIGNMENT);
+        let data = if copied {
+            scratch.clear();
+            scratch.extend_from_slice(data);
+            stats.copies += 1;
+            stats.copied_bytes += data.len() as u64;
+            scratch.as_slice()
+        } else {
+            data
+        };
+
+        let archived = A::get_archived(data)?;
+        Ok(RealignedDocument { archived, copied })
+    }
+}
+
+/// An invalid archive error for the last document the walker produced.
+fn invalid_archive(walker: &mut BufferWalker<'_>, source: BoxedError) -> Error {
+    let (index, offset) = walker.last_position();
+    Error::InvalidArchive {
+        index,
+        offset,
+        source,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::RecordLayout;
+    use crate::test_utils::{
+        documents,
+        encode_documents,
+        encode_indexed,
+        encode_with_positions,
+    };
+    use crate::Framing;
+
+    fn framed(framing: Framing) -> RecordLayout {
+        RecordLayout {
+            framing,
+            ..RecordLayout::default()
+        }
+    }
+
+    /// Copies the buffer so it starts one byte past an aligned address.
+    fn misaligned(buf: &[u8]) -> AlignedVec {
+        let mut misaligned = AlignedVec::new();
+        misaligned.push(0);
+        misaligned.extend_from_slice(buf);
+        misaligned
+    }
+
+    fn id(document: &ArchivedDocument) -> Option<u64> {
+        document.get("id").and_then(|id| id.as_u64())
+    }
+
+    #[test]
+    fn test_realigning_misaligned_buffer() {
+        for framing in [Framing::Backward, Framing::Bidirectional] {
+            let buf = encode_documents(&documents(5), framed(framing));
+            let misaligned = misaligned(&buf);
+            let mut decoder = Decoder::new(&misaligned[1..]);
+            decoder.set_framing(framing);
+            decoder.enable_checksum_validation();
+
+            assert!(
+                matches!(
+                    decoder.checked_archived_iter().next(),
+                    Some(Err(Error::Misaligned { .. }))
+                ),
+                "The plain decoder should reject misaligned documents"
+            );
+
+            let mut realigning = decoder.checked_realigning();
+            for expected in (0..5).rev() {
+                let document = realigning
+                    .next_document()
+                    .expect("Document should exist")
+                    .expect("Document should be realigned");
+                assert!(document.was_copied());
+                assert_eq!(id(&document), Some(expected));
+            }
+            assert!(realigning.next_document().is_none());
+
+            let stats = realigning.stats();
+            assert_eq!(stats.copies, 5);
+            assert!(stats.copied_bytes > 0);
+        }
+    }
+
+    #[test]
+    fn test_realigning_reverse_and_lookup() {
+        let (buf, positions) =
+            encode_with_positions(&documents(5), framed(Framing::Bidirectional));
+        let positions = rkyv::to_bytes::<_, 256>(&positions).unwrap();
+        let positions = rkyv::check_archived_root::<Vec<u64>>(&positions).unwrap();
+        let misaligned = misaligned(&buf);
+
+        let mut decoder = Decoder::using_known_positions(&misaligned[1..], positions);
+        decoder.set_framing(Framing::Bidirectional);
+        let mut realigning = decoder.checked_realigning();
+
+        for expected in 0..5 {
+            let document = realigning.next_back_document().unwrap().unwrap();
+            assert_eq!(id(&document), Some(expected));
+        }
+        assert!(realigning.next_back_document().is_none());
+
+        let document = realigning
+            .archived_at(3)
+            .unwrap()
+            .expect("Document should exist");
+        assert_eq!(id(&document), Some(3));
+        assert!(realigning.archived_at(5).unwrap().is_none());
+        assert_eq!(realigning.stats().copies, 6);
+    }
+
+    #[test]
+    fn test_realigning_aligned_buffer_is_not_copied() {
+        let buf = encode_documents(&documents(3), RecordLayout::default());
+        let mut realigning = unsafe { Decoder::new(&buf).realigning() };
+        while let Some(document) = realigning.next_document() {
+            assert!(!document.expect("Document should be readable").was_copied());
+        }
+        assert_eq!(realigning.stats(), RealignStats::default());
+    }
+
+    #[test]
+    fn test_realigning_indexed_buffer() {
+        let buf = encode_indexed(&documents(3), framed(Framing::Bidirectional));
+
+        let mut decoder = Decoder::new(&buf);
+        decoder.set_framing(Framing::Bidirectional);
+        decoder.enable_checksum_validation();
+        decoder.read_position_index().expect("Read position index");
+        let mut realigning = decoder.checked_realigning();
+        for expected in (0..3).rev() {
+            let document = realigning.next_document().unwrap().unwrap();
+            assert_eq!(id(&document), Some(expected));
+        }
+        assert!(
+            realigning.next_document().is_none(),
+            "The position index should not be produced as a document"
+        );
+
+        let mut decoder = Decoder::new(&buf);
+        decoder.set_framing(Framing::Bidirectional);
+        decoder.read_position_index().expect("Read position index");
+        let mut realigning = decoder.checked_realigning();
+        for expected in 0..3 {
+            let document = realigning.next_back_document().unwrap().unwrap();
+            assert_eq!(id(&document), Some(expected));
+        }
+        assert!(realigning.next_back_document().is_none());
+        assert_eq!(realigning.stats(), RealignStats::default());
+    }
+
+    #[test]
+    fn test_realigning_corrupt_document() {
+        let (buf, positions) =
+            encode_with_positions(&documents(3), RecordLayout::default());
+        let mut misaligned = misaligned(&buf);
+        // Corrupt the data of the newest document, keeping its footer.
+        let start = positions[1] as usize + 1;
+        let end = positions[2] as usize + 1 - crate::FOOTER_SIZE;
+        misaligned[start..end].fill(0xFF);
+
+        let mut realigning = Decoder::new(&misaligned[1..]).checked_realigning();
+        assert!(matches!(
+            realigning.next_document(),
+            Some(Err(Error::InvalidArchive { index: 2, .. }))
+        ));
+        let document = realigning.next_document().unwrap().unwrap();
+        assert_eq!(id(&document), Some(1));
+    }
+}
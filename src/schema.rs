@@ -0,0 +1,678 @@
+//! Schemas describing the expected shape of documents
+//!
+//! A schema declares the kinds of value each field may hold, if the field
+//! must be present and if it may be null. Objects can be described by nested
+//! schemas, both owned and archived documents can be validated.
+
+use std::error::Error;
+use std::fmt;
+
+mod coerce;
+mod infer;
+
+pub use self::coerce::{Coercion, CoercionPolicy};
+pub use self::infer::{InferredField, InferredSchema, NumericRange};
+use crate::core::{
+    ArchivedDocument,
+    ArchivedText,
+    ArchivedValue,
+    Document,
+    Text,
+    Value,
+    ValueKind,
+};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// The expected fields of a document or object.
+///
+/// Fields not declared in the schema are accepted unless
+/// [Schema::deny_unknown_fields] is set.
+///
+/// ```
+/// use bellini::{Document, FieldSchema, Schema, Value, ValueKind};
+///
+/// let mut schema = Schema::default();
+/// schema.insert("name", FieldSchema::new(ValueKind::String));
+/// schema.insert("tags", FieldSchema::new(ValueKind::ArrayString).optional());
+///
+/// let mut document = Document::default();
+/// document.insert("name", Value::U64(1));
+///
+/// let errors = schema.validate(&document).unwrap_err();
+/// assert_eq!(errors.to_string(), "\"/name\": expected string, found u64");
+/// ```
+pub struct Schema {
+    fields: Vec<(String, FieldSchema)>,
+    deny_unknown_fields: bool,
+}
+
+impl Schema {
+    /// Declares a field, replacing any existing declaration for the key.
+    pub fn insert(&mut self, key: impl Into<String>, field: FieldSchema) {
+        let key = key.into();
+        match self.fields.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, existing)) => *existing = field,
+            None => self.fields.push((key, field)),
+        }
+    }
+
+    #[inline]
+    /// Get the declaration of a field.
+    pub fn get(&self, key: &str) -> Option<&FieldSchema> {
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, f)| f)
+    }
+
+    #[inline]
+    /// Get the declared fields in the order they were inserted.
+    pub fn fields(&self) -> &[(String, FieldSchema)] {
+        &self.fields
+    }
+
+    /// Reject any field which is not declared in the schema.
+    pub fn deny_unknown_fields(&mut self) {
+        self.deny_unknown_fields = true;
+    }
+
+    /// Validates the document against the schema.
+    ///
+    /// All violations are collected rather than stopping at the first one.
+    pub fn validate(&self, document: &Document) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        self.validate_entries(document.fields(), &mut String::new(), &mut errors);
+        ValidationErrors::from_vec(errors)
+    }
+
+    /// Validates the archived document against the schema.
+    ///
+    /// All violations are collected rather than stopping at the first one.
+    pub fn validate_archived(
+        &self,
+        document: &ArchivedDocument,
+    ) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        self.validate_entries(document.fields(), &mut String::new(), &mut errors);
+        ValidationErrors::from_vec(errors)
+    }
+
+    fn validate_entries<K, V>(
+        &self,
+        entries: &[(K, V)],
+        path: &mut String,
+        errors: &mut Vec<ValidationError>,
+    ) where
+        K: AsRef<str>,
+        V: SchemaValue,
+    {
+        let order = KeyOrder::new(entries);
+        let mut known = vec![false; entries.len()];
+
+        for (key, field) in &self.fields {
+            let mut matches = order.matches(entries, key).peekable();
+            let parent_len = push_token(path, key);
+
+            if matches.peek().is_none() && field.required {
+                errors.push(ValidationError {
+                    path: path.clone(),
+                    kind: ValidationErrorKind::Missing,
+                });
+            }
+
+            for index in matches {
+                known[index] = true;
+                field.validate_value(&entries[index].1, path, errors);
+            }
+
+            path.truncate(parent_len);
+        }
+
+        if !self.deny_unknown_fields {
+            return;
+        }
+
+        for ((key, _), known) in entries.iter().zip(known) {
+            if !known {
+                let parent_len = push_token(path, key.as_ref());
+                errors.push(ValidationError {
+                    path: path.clone(),
+                    kind: ValidationErrorKind::UnknownField,
+                });
+                path.truncate(parent_len);
+            }
+        }
+    }
+}
+
+/// The entries of a document or object ordered by key so each declared field
+/// can be binary searched.
+///
+/// Entries in the canonical layout are already sorted, otherwise the indices
+/// of the entries are sorted. The sort is stable so entries sharing a key
+/// keep their insertion order.
+enum KeyOrder {
+    Sorted,
+    Indices(Vec<usize>),
+}
+
+impl KeyOrder {
+    fn new<K: AsRef<str>, V>(entries: &[(K, V)]) -> Self {
+        let key = |index: usize| entries[index].0.as_ref().as_bytes();
+        if (1..entries.len()).all(|index| key(index - 1) <= key(index)) {
+            return Self::Sorted;
+        }
+
+        let mut indices = (0..entries.len()).collect::<Vec<_>>();
+        indices.sort_by(|a, b| key(*a).cmp(key(*b)));
+        Self::Indices(indices)
+    }
+
+    /// The indices of every entry with the given key in insertion order.
+    fn matches<'a, K: AsRef<str>, V>(
+        &'a self,
+        entries: &'a [(K, V)],
+        key: &'a str,
+    ) -> impl Iterator<Item = usize> + 'a {
+        let key = key.as_bytes();
+        let entry_key = move |index: usize| entries[index].0.as_ref().as_bytes();
+        let position = move |i: usize| match self {
+            Self::Sorted => i,
+            Self::Indices(indices) => indices[i],
+        };
+
+        let start = match self {
+            Self::Sorted => {
+                entries.partition_point(|(k, _)| k.as_ref().as_bytes() < key)
+            },
+            Self::Indices(indices) => indices.partition_point(|i| entry_key(*i) < key),
+        };
+        (start..entries.len())
+            .map(position)
+            .take_while(move |index| entry_key(*index) == key)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The declaration of a single field.
+///
+/// Fields are required and not nullable by default.
+pub struct FieldSchema {
+    kinds: Vec<ValueKind>,
+    nullable: bool,
+    required: bool,
+    object: Option<Schema>,
+}
+
+impl FieldSchema {
+    /// A field holding the given kind of value.
+    pub fn new(kind: ValueKind) -> Self {
+        Self::one_of([kind])
+    }
+
+    /// A field holding any one of the given kinds of value.
+    pub fn one_of(kinds: impl IntoIterator<Item = ValueKind>) -> Self {
+        Self {
+            kinds: kinds.into_iter().collect(),
+            nullable: false,
+            required: true,
+            object: None,
+        }
+    }
+
+    /// A field holding an object matching the given schema.
+    ///
+    /// The schema is also applied to the elements of a dynamic array, each
+    /// element must be an object, or `null` if the field is nullable.
+    pub fn object(schema: Schema) -> Self {
+        Self {
+            object: Some(schema),
+            ..Self::new(ValueKind::Object)
+        }
+    }
+
+    /// Allow the field to hold an explicit `null`.
+    pub fn nullable(mut self) -> Self {
+        self.nullable = true;
+        self
+    }
+
+    /// Allow the field to be missing from the document.
+    pub fn optional(mut self) -> Self {
+        self.required = false;
+        self
+    }
+
+    /// Also allow the given kind of value.
+    pub fn or(mut self, kind: ValueKind) -> Self {
+        if !self.kinds.contains(&kind) {
+            self.kinds.push(kind);
+        }
+        self
+    }
+
+    #[inline]
+    /// The kinds of value the field may hold.
+    pub fn kinds(&self) -> &[ValueKind] {
+        &self.kinds
+    }
+
+    #[inline]
+    /// Returns if the field may hold an explicit `null`.
+    pub fn is_nullable(&self) -> bool {
+        self.nullable
+    }
+
+    #[inline]
+    /// Returns if the field must be present.
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+
+    #[inline]
+    /// The schema nested objects must match.
+    pub fn object_schema(&self) -> Option<&Schema> {
+        self.object.as_ref()
+    }
+
+    fn validate_value<V: SchemaValue>(
+        &self,
+        value: &V,
+        path: &mut String,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        let kind = value.kind();
+        if kind == ValueKind::Null {
+            if !self.nullable {
+                errors.push(ValidationError {
+                    path: path.clone(),
+                    kind: ValidationErrorKind::UnexpectedNull,
+                });
+            }
+            return;
+        }
+
+        if !self.kinds.contains(&kind) {
+            errors.push(ValidationError {
+                path: path.clone(),
+                kind: ValidationErrorKind::TypeMismatch {
+                    expected: self.kinds.clone(),
+                    found: kind,
+                },
+            });
+            return;
+        }
+
+        let schema = match &self.object {
+            None => return,
+            Some(schema) => schema,
+        };
+
+        if let Some(entries) = value.entries() {
+            schema.validate_entries(entries, path, errors);
+        }
+
+        for (index, element) in value.elements().iter().enumerate() {
+            let parent_len = path.len();
+            path.push('/');
+            path.push_str(&index.to_string());
+
+            match element.entries() {
+                Some(entries) => schema.validate_entries(entries, path, errors),
+                None if element.kind() == ValueKind::Null => {
+                    if !self.nullable {
+                        errors.push(ValidationError {
+                            path: path.clone(),
+                            kind: ValidationErrorKind::UnexpectedNull,
+                        });
+                    }
+                },
+                None => errors.push(ValidationError {
+                    path: path.clone(),
+                    kind: ValidationErrorKind::TypeMismatch {
+                        expected: vec![ValueKind::Object],
+                        found: element.kind(),
+                    },
+                }),
+            }
+
+            path.truncate(parent_len);
+        }
+    }
+}
+
+/// The parts of a value inspected by a schema, implemented for both the
+/// owned and archived values.
+trait SchemaValue: Sized {
+    type Key: AsRef<str>;
+
+    fn kind(&self) -> ValueKind;
+
+    fn entries(&self) -> Option<&[(Self::Key, Self)]>;
+
+    fn elements(&self) -> &[Self];
+}
+
+macro_rules! impl_schema_value {
+    ($value:ident, $text:ty) => {
+        impl SchemaValue for $value {
+            type Key = $text;
+
+            #[inline]
+            fn kind(&self) -> ValueKind {
+                $value::kind(self)
+            }
+
+            #[inline]
+            fn entries(&self) -> Option<&[($text, $value)]> {
+                match self {
+                    $value::Object(entries) => Some(entries),
+                    _ => None,
+                }
+            }
+
+            #[inline]
+            fn elements(&self) -> &[$value] {
+                match self {
+                    $value::ArrayDynamic(values) => values,
+                    _ => &[],
+                }
+            }
+        }
+    };
+}
+
+impl_schema_value!(Value, Text);
+impl_schema_value!(ArchivedValue, ArchivedText);
+
+/// Appends a JSON pointer token to the path returning the previous length.
+fn push_token(path: &mut String, token: &str) -> usize {
+    let parent_len = path.len();
+    path.push('/');
+    path.push_str(&token.replace('~', "~0").replace('/', "~1"));
+    parent_len
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A single violation of a schema.
+pub struct ValidationError {
+    /// The JSON pointer of the offending field.
+    pub path: String,
+    /// What was wrong with the field.
+    pub kind: ValidationErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The reason a field failed validation.
+pub enum ValidationErrorKind {
+    /// A required field was not present.
+    Missing,
+    /// The field was `null` but is not nullable.
+    UnexpectedNull,
+    /// The field held a kind of value not declared in the schema.
+    TypeMismatch {
+        expected: Vec<ValueKind>,
+        found: ValueKind,
+    },
+    /// The field is not declared and the schema denies unknown fields.
+    UnknownField,
+    /// The value could be converted to a declared kind but the coercion
+    /// policy does not allow it.
+    CoercionRejected { from: ValueKind, to: ValueKind },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = &self.path;
+        match &self.kind {
+            ValidationErrorKind::Missing => {
+                write!(f, "{path:?}: required field is missing")
+            },
+            ValidationErrorKind::UnexpectedNull => {
+                write!(f, "{path:?}: field is not nullable")
+            },
+            ValidationErrorKind::TypeMismatch { expected, found } => {
+                write!(f, "{path:?}: expected ")?;
+                for (i, kind) in expected.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, " or ")?;
+                    }
+                    write!(f, "{kind}")?;
+                }
+                write!(f, ", found {found}")
+            },
+            ValidationErrorKind::UnknownField => write!(f, "{path:?}: unknown field"),
+            ValidationErrorKind::CoercionRejected { from, to } => {
+                write!(f, "{path:?}: coercing {from} to {to} is not allowed")
+            },
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Every violation found while validating a document.
+///
+/// This is never empty.
+pub struct ValidationErrors(Vec<ValidationError>);
+
+impl ValidationErrors {
+    fn from_vec(errors: Vec<ValidationError>) -> Result<(), Self> {
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Self(errors))
+        }
+    }
+
+    #[inline]
+    /// Get the individual violations.
+    pub fn errors(&self) -> &[ValidationError] {
+        &self.0
+    }
+
+    #[inline]
+    /// Consume the errors returning the individual violations.
+    pub fn into_errors(self) -> Vec<ValidationError> {
+        self.0
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i != 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ValidationErrors {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_schema() -> Schema {
+        let mut address = Schema::default();
+        address.insert("city", FieldSchema::new(ValueKind::String));
+        address.insert("zip", FieldSchema::new(ValueKind::U64).optional());
+        address.deny_unknown_fields();
+
+        let mut schema = Schema::default();
+        schema.insert("name", FieldSchema::new(ValueKind::String));
+        schema.insert(
+            "age",
+            FieldSchema::new(ValueKind::U64)
+                .or(ValueKind::I64)
+                .nullable(),
+        );
+        schema.insert("tags", FieldSchema::new(ValueKind::ArrayString).optional());
+        schema.insert("address", FieldSchema::object(address.clone()));
+        schema.insert(
+            "previous",
+            FieldSchema::object(address)
+                .or(ValueKind::ArrayDynamic)
+                .optional(),
+        );
+        schema
+    }
+
+    fn object(entries: Vec<(&str, Value)>) -> Value {
+        Value::Object(
+            entries
+                .into_iter()
+                .map(|(k, v)| (Text::from(k), v))
+                .collect(),
+        )
+    }
+
+    fn validate_both(schema: &Schema, document: &Document) -> Vec<ValidationError> {
+        let bytes = rkyv::to_bytes::<_, 256>(document).expect("Serialize document");
+        let archived = rkyv::check_archived_root::<Document>(&bytes)
+            .expect("Archived document should be valid");
+
+        let owned = schema.validate(document).err();
+        let archived = schema.validate_archived(archived).err();
+        assert_eq!(
+            owned, archived,
+            "Owned and archived validation should match"
+        );
+        owned.map(ValidationErrors::into_errors).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_valid_document() {
+        let mut document = Document::default();
+        document.insert("name", Value::String(Text::from("bellini")));
+        document.insert("age", Value::Null);
+        document.insert("extra", Value::Bool(true));
+        document.insert(
+            "address",
+            object(vec![("city", Value::String(Text::from("london")))]),
+        );
+        document.insert(
+            "previous",
+            Value::ArrayDynamic(vec![object(vec![
+                ("city", Value::String(Text::from("paris"))),
+                ("zip", Value::U64(1)),
+            ])]),
+        );
+
+        assert_eq!(validate_both(&test_schema(), &document), vec![]);
+    }
+
+    #[test]
+    fn test_invalid_document() {
+        let mut document = Document::default();
+        document.insert("age", Value::F64(1.5));
+        document.insert("tags", Value::Null);
+        document.insert(
+            "address",
+            object(vec![
+                ("city", Value::U64(1)),
+                ("street", Value::String(Text::from("high street"))),
+            ]),
+        );
+        document.insert(
+            "previous",
+            Value::ArrayDynamic(vec![Value::U64(1), object(vec![])]),
+        );
+
+        let errors = validate_both(&test_schema(), &document);
+        let paths = errors
+            .iter()
+            .map(|e| (e.path.as_str(), &e.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                ("/name", &ValidationErrorKind::Missing),
+                (
+                    "/age",
+                    &ValidationErrorKind::TypeMismatch {
+                        expected: vec![ValueKind::U64, ValueKind::I64],
+                        found: ValueKind::F64,
+                    }
+                ),
+                ("/tags", &ValidationErrorKind::UnexpectedNull),
+                (
+                    "/address/city",
+                    &ValidationErrorKind::TypeMismatch {
+                        expected: vec![ValueKind::String],
+                        found: ValueKind::U64,
+                    }
+                ),
+                ("/address/street", &ValidationErrorKind::UnknownField),
+                (
+                    "/previous/0",
+                    &ValidationErrorKind::TypeMismatch {
+                        expected: vec![ValueKind::Object],
+                        found: ValueKind::U64,
+                    }
+                ),
+                ("/previous/1/city", &ValidationErrorKind::Missing),
+            ]
+        );
+        assert_eq!(
+            errors[1].to_string(),
+            "\"/age\": expected u64 or i64, found f64"
+        );
+    }
+
+    #[test]
+    fn test_unsorted_and_duplicate_keys() {
+        let mut schema = Schema::default();
+        schema.insert("b", FieldSchema::new(ValueKind::U64));
+        schema.insert("a", FieldSchema::new(ValueKind::String));
+        schema.deny_unknown_fields();
+
+        let mut document = Document::default();
+        document.insert("c", Value::Null);
+        document.insert("b", Value::U64(1));
+        document.insert("a", Value::U64(2));
+        document.insert("b", Value::Bool(true));
+
+        let expected = vec![
+            ValidationError {
+                path: "/b".into(),
+                kind: ValidationErrorKind::TypeMismatch {
+                    expected: vec![ValueKind::U64],
+                    found: ValueKind::Bool,
+                },
+            },
+            ValidationError {
+                path: "/a".into(),
+                kind: ValidationErrorKind::TypeMismatch {
+                    expected: vec![ValueKind::String],
+                    found: ValueKind::U64,
+                },
+            },
+            ValidationError {
+                path: "/c".into(),
+                kind: ValidationErrorKind::UnknownField,
+            },
+        ];
+        assert_eq!(validate_both(&schema, &document), expected);
+
+        document.canonicalize();
+        assert_eq!(
+            validate_both(&schema, &document),
+            expected,
+            "Sorted entries should report the same errors"
+        );
+    }
+
+    #[test]
+    fn test_value_kind_names() {
+        for kind in ValueKind::ALL {
+            assert_eq!(ValueKind::from_type(kind.as_type()), Some(kind));
+        }
+        assert_eq!(ValueKind::from_type("array<nope>"), None);
+        assert_eq!(Value::ArrayDate(vec![]).kind(), ValueKind::ArrayDate);
+        assert_eq!(ValueKind::ArrayDate.element(), Some(ValueKind::Date));
+        assert!(ValueKind::ArrayDynamic.is_array());
+        assert!(!ValueKind::Object.is_array());
+    }
+}
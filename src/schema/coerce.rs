@@ -0,0 +1,593 @@
+use super::{
+    push_token,
+    FieldSchema,
+    KeyOrder,
+    Schema,
+    ValidationError,
+    ValidationErrorKind,
+    ValidationErrors,
+};
+use crate::core::{Document, Text, Value, ValueKind};
+use crate::date::{parse_rfc3339, Rfc3339};
+
+/// The largest integer magnitude which converts to a `f64` without loss.
+const MAX_SAFE_INTEGER: u64 = 1 << 53;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+/// Controls whether values are converted to the kind declared by a schema.
+pub enum CoercionPolicy {
+    #[default]
+    /// Values which would need converting are rejected.
+    Strict,
+    /// Values are converted where possible and each conversion is reported.
+    Lenient,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A value which was converted to the kind declared by a schema.
+pub struct Coercion {
+    /// The JSON pointer of the converted field.
+    pub path: String,
+    /// The kind of value which was provided.
+    pub from: ValueKind,
+    /// The kind of value it was converted to.
+    pub to: ValueKind,
+}
+
+impl Schema {
+    /// Converts any values in the document to the kind declared for their
+    /// field, then validates the document.
+    ///
+    /// The declared kinds of a field are tried in order and the first
+    /// successful conversion is used, the supported conversions are:
+    ///
+    /// - Strings holding a number, `true`/`false` or an RFC3339 timestamp.
+    /// - Numbers, bools and dates to strings, dates are formatted as RFC3339.
+    /// - Between numeric kinds when no precision is lost, i.e. `1.0` to `1`.
+    /// - A single value to a one element array of the declared element kind.
+    /// - Arrays whose elements can all be converted to the element kind.
+    ///
+    /// Under [CoercionPolicy::Strict] nothing is converted and each value that
+    /// would need converting produces a [ValidationErrorKind::CoercionRejected]
+    /// error, under [CoercionPolicy::Lenient] the applied conversions are
+    /// returned.
+    ///
+    /// ```
+    /// use bellini::{CoercionPolicy, Document, FieldSchema, Schema, Value, ValueKind};
+    ///
+    /// let mut schema = Schema::default();
+    /// schema.insert("count", FieldSchema::new(ValueKind::U64));
+    /// schema.insert("tags", FieldSchema::new(ValueKind::ArrayString));
+    ///
+    /// let mut document = Document::default();
+    /// document.insert("count", Value::from("123"));
+    /// document.insert("tags", Value::from("a"));
+    ///
+    /// let coercions = schema.coerce(&mut document, CoercionPolicy::Lenient).unwrap();
+    /// assert_eq!(coercions.len(), 2);
+    /// assert_eq!(document.get("count"), Some(&Value::U64(123)));
+    /// assert_eq!(document.get("tags"), Some(&Value::ArrayString(vec!["a".into()])));
+    /// ```
+    pub fn coerce(
+        &self,
+        document: &mut Document,
+        policy: CoercionPolicy,
+    ) -> Result<Vec<Coercion>, ValidationErrors> {
+        let mut coercions = Vec::new();
+        let mut errors = Vec::new();
+        let mut pending = Vec::new();
+        let mut walk = Walk::default();
+        self.coerce_entries(
+            document.fields(),
+            &mut walk,
+            &mut pending,
+            &mut errors,
+            policy,
+        );
+
+        if !pending.is_empty() {
+            // Values are replaced in place so the keys keep their order.
+            let canonical = document.is_canonical();
+            apply(document.fields_mut(), &mut pending, &mut coercions);
+            if canonical {
+                document.canonicalize();
+            }
+        }
+
+        if let Err(validation) = self.validate(document) {
+            // Values rejected by the policy would also fail validation,
+            // only the rejection is kept.
+            let rejected = errors.len();
+            for error in validation.into_errors() {
+                let duplicate = errors[..rejected].iter().any(|e| e.path == error.path);
+                if !duplicate {
+                    errors.push(error);
+                }
+            }
+        }
+
+        ValidationErrors::from_vec(errors).map(|_| coercions)
+    }
+
+    /// Walks the entries collecting the values which need converting.
+    fn coerce_entries(
+        &self,
+        entries: &[(Text, Value)],
+        walk: &mut Walk,
+        pending: &mut Vec<Pending>,
+        errors: &mut Vec<ValidationError>,
+        policy: CoercionPolicy,
+    ) {
+        let order = KeyOrder::new(entries);
+        for (key, field) in &self.fields {
+            for index in order.matches(entries, key) {
+                let parent_len = push_token(&mut walk.path, key);
+                walk.positions.push(index);
+                field.coerce_value(&entries[index].1, walk, pending, errors, policy);
+                walk.positions.pop();
+                walk.path.truncate(parent_len);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+/// The location of the value being visited.
+struct Walk {
+    /// The JSON pointer reported for the value.
+    path: String,
+    /// The index of the entry or array element at each level, this tells
+    /// entries sharing a key apart.
+    positions: Vec<usize>,
+}
+
+impl FieldSchema {
+    fn coerce_value(
+        &self,
+        value: &Value,
+        walk: &mut Walk,
+        pending: &mut Vec<Pending>,
+        errors: &mut Vec<ValidationError>,
+        policy: CoercionPolicy,
+    ) {
+        let kind = value.kind();
+        if kind == ValueKind::Null {
+            return;
+        }
+
+        if self.kinds.contains(&kind) {
+            if let Some(schema) = &self.object {
+                self.coerce_nested(schema, value, walk, pending, errors, policy);
+            }
+            return;
+        }
+
+        let coerced = self
+            .kinds
+            .iter()
+            .find_map(|to| coerce(value, *to).map(|value| (*to, value)));
+        let (to, coerced) = match coerced {
+            // Left for validation to report the mismatch.
+            None => return,
+            Some(coerced) => coerced,
+        };
+
+        match policy {
+            CoercionPolicy::Strict => errors.push(ValidationError {
+                path: walk.path.clone(),
+                kind: ValidationErrorKind::CoercionRejected { from: kind, to },
+            }),
+            CoercionPolicy::Lenient => pending.push(Pending {
+                path: walk.path.clone(),
+                positions: walk.positions.clone(),
+                from: kind,
+                to,
+                value: coerced,
+            }),
+        }
+    }
+
+    fn coerce_nested(
+        &self,
+        schema: &Schema,
+        value: &Value,
+        walk: &mut Walk,
+        pending: &mut Vec<Pending>,
+        errors: &mut Vec<ValidationError>,
+        policy: CoercionPolicy,
+    ) {
+        match value {
+            Value::Object(entries) => {
+                schema.coerce_entries(entries, walk, pending, errors, policy)
+            },
+            Value::ArrayDynamic(values) => {
+                for (index, value) in values.iter().enumerate() {
+                    if let Value::Object(entries) = value {
+                        let parent_len = walk.path.len();
+                        walk.path.push('/');
+                        walk.path.push_str(&index.to_string());
+                        walk.positions.push(index);
+                        schema.coerce_entries(entries, walk, pending, errors, policy);
+                        walk.positions.pop();
+                        walk.path.truncate(parent_len);
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+/// A converted value waiting to replace the original.
+struct Pending {
+    path: String,
+    positions: Vec<usize>,
+    from: ValueKind,
+    to: ValueKind,
+    value: Value,
+}
+
+/// Replaces the values at the pending positions, the pending values are in
+/// the order their fields were visited by the schema.
+fn apply(
+    fields: &mut [(Text, Value)],
+    pending: &mut Vec<Pending>,
+    coercions: &mut Vec<Coercion>,
+) {
+    for p in pending.drain(..) {
+        if let Some(slot) = resolve_mut(fields, &p.positions) {
+            *slot = p.value;
+            coercions.push(Coercion {
+                path: p.path,
+                from: p.from,
+                to: p.to,
+            });
+        }
+    }
+}
+
+/// Finds the value at the positions recorded while walking the schema.
+///
+/// Each position is the index of an entry within an object, or of an element
+/// within a dynamic array, so entries sharing a key each resolve to
+/// themselves.
+fn resolve_mut<'a>(
+    fields: &'a mut [(Text, Value)],
+    positions: &[usize],
+) -> Option<&'a mut Value> {
+    let (first, rest) = positions.split_first()?;
+    let mut value = &mut fields.get_mut(*first)?.1;
+    for index in rest {
+        value = match value {
+            Value::Object(entries) => &mut entries.get_mut(*index)?.1,
+            Value::ArrayDynamic(values) => values.get_mut(*index)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// Converts a value to the given kind if it can be done without losing
+/// information.
+fn coerce(value: &Value, to: ValueKind) -> Option<Value> {
+    if let Some(element) = to.element() {
+        let elements = match array_elements(value) {
+            Some(elements) => elements,
+            None => vec![value.clone()],
+        };
+        return typed_array(element, elements);
+    }
+
+    if to == ValueKind::ArrayDynamic {
+        return match array_elements(value) {
+            Some(elements) => Some(Value::ArrayDynamic(elements)),
+            None => Some(Value::ArrayDynamic(vec![value.clone()])),
+        };
+    }
+
+    coerce_scalar(value, to)
+}
+
+fn coerce_scalar(value: &Value, to: ValueKind) -> Option<Value> {
+    if value.kind() == to {
+        return Some(value.clone());
+    }
+
+    let coerced = match (value, to) {
+        (Value::String(v), ValueKind::U64) => Value::U64(v.trim().parse().ok()?),
+        (Value::String(v), ValueKind::I64) => Value::I64(v.trim().parse().ok()?),
+        (Value::String(v), ValueKind::F64) => Value::F64(v.trim().parse().ok()?),
+        (Value::String(v), ValueKind::Bool) => Value::Bool(v.trim().parse().ok()?),
+        (Value::String(v), ValueKind::Date) => Value::Date(parse_rfc3339(v.trim())?),
+        (Value::Bool(v), ValueKind::String) => Value::String(Text::from(v.to_string())),
+        (Value::U64(v), ValueKind::String) => Value::String(Text::from(v.to_string())),
+        (Value::I64(v), ValueKind::String) => Value::String(Text::from(v.to_string())),
+        (Value::F64(v), ValueKind::String) => Value::String(Text::from(v.to_string())),
+        (Value::Date(v), ValueKind::String) => {
+            let date = Rfc3339(*v);
+            if !date.is_representable() {
+                return None;
+            }
+            Value::String(Text::from(date.to_string()))
+        },
+        (Value::U64(v), ValueKind::I64) => Value::I64(i64::try_from(*v).ok()?),
+        (Value::I64(v), ValueKind::U64) => Value::U64(u64::try_from(*v).ok()?),
+        (Value::U64(v), ValueKind::F64) if *v <= MAX_SAFE_INTEGER => {
+            Value::F64(*v as f64)
+        },
+        (Value::I64(v), ValueKind::F64) if v.unsigned_abs() <= MAX_SAFE_INTEGER => {
+            Value::F64(*v as f64)
+        },
+        (Value::F64(v), ValueKind::U64) if is_integral(*v, 0.0, u64::MAX as f64) => {
+            Value::U64(*v as u64)
+        },
+        (Value::F64(v), ValueKind::I64)
+            if is_integral(*v, i64::MIN as f64, i64::MAX as f64) =>
+        {
+            Value::I64(*v as i64)
+        },
+        _ => return None,
+    };
+
+    Some(coerced)
+}
+
+/// Returns if the float is a whole number within `min..max`.
+///
+/// The upper bound is exclusive as `u64::MAX` and `i64::MAX` round up to the
+/// next power of two when converted to a float.
+fn is_integral(v: f64, min: f64, max: f64) -> bool {
+    v.fract() == 0.0 && v >= min && v < max
+}
+
+/// Copies the elements of any of the array variants into values.
+fn array_elements(value: &Value) -> Option<Vec<Value>> {
+    fn collect<T: Copy>(values: &[T], map: impl Fn(T) -> Value) -> Option<Vec<Value>> {
+        Some(values.iter().copied().map(map).collect())
+    }
+
+    match value {
+        Value::ArrayBool(values) => collect(values, Value::Bool),
+        Value::ArrayString(values) => {
+            Some(values.iter().cloned().map(Value::String).collect())
+        },
+        Value::ArrayBytes(values) => {
+            Some(values.iter().cloned().map(Value::Bytes).collect())
+        },
+        Value::ArrayU64(values) => collect(values, Value::U64),
+        Value::ArrayI64(values) => collect(values, Value::I64),
+        Value::ArrayF64(values) => collect(values, Value::F64),
+        Value::ArrayDate(values) => collect(values, Value::Date),
+        Value::ArrayDynamic(values) => Some(values.clone()),
+        _ => None,
+    }
+}
+
+/// Builds a typed array of the given element kind, converting each element.
+fn typed_array(element: ValueKind, values: Vec<Value>) -> Option<Value> {
+    macro_rules! collect {
+        ($variant:ident, $array:ident) => {
+            values
+                .iter()
+                .map(|value| match coerce_scalar(value, element)? {
+                    Value::$variant(v) => Some(v),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()
+                .map(Value::$array)
+        };
+    }
+
+    match element {
+        ValueKind::Bool => collect!(Bool, ArrayBool),
+        ValueKind::String => collect!(String, ArrayString),
+        ValueKind::Bytes => collect!(Bytes, ArrayBytes),
+        ValueKind::U64 => collect!(U64, ArrayU64),
+        ValueKind::I64 => collect!(I64, ArrayI64),
+        ValueKind::F64 => collect!(F64, ArrayF64),
+        ValueKind::Date => collect!(Date, ArrayDate),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_schema() -> Schema {
+        let mut address = Schema::default();
+        address.insert("zip", FieldSchema::new(ValueKind::U64));
+
+        let mut schema = Schema::default();
+        schema.insert("count", FieldSchema::new(ValueKind::U64));
+        schema.insert("offset", FieldSchema::new(ValueKind::I64));
+        schema.insert("score", FieldSchema::new(ValueKind::F64).optional());
+        schema.insert("created", FieldSchema::new(ValueKind::Date));
+        schema.insert("label", FieldSchema::new(ValueKind::String).nullable());
+        schema.insert("tags", FieldSchema::new(ValueKind::ArrayU64));
+        schema.insert("seen", FieldSchema::new(ValueKind::ArrayDate).optional());
+        schema.insert(
+            "address",
+            FieldSchema::object(address).or(ValueKind::ArrayDynamic),
+        );
+        schema
+    }
+
+    fn sloppy_document() -> Document {
+        let mut document = Document::default();
+        document.insert("count", Value::from(" 123 "));
+        document.insert("offset", Value::F64(-1.0));
+        document.insert("created", Value::from("2023-06-15T12:00:00Z"));
+        document.insert("label", Value::U64(7));
+        document.insert("tags", Value::U64(1));
+        document.insert(
+            "seen",
+            Value::ArrayString(vec![Text::from("1970-01-01T00:00:00Z")]),
+        );
+        document.insert(
+            "address",
+            Value::ArrayDynamic(vec![Value::Object(vec![(
+                Text::from("zip"),
+                Value::from("42"),
+            )])]),
+        );
+        document
+    }
+
+    #[test]
+    fn test_lenient_coercion() {
+        let mut document = sloppy_document();
+        let coercions = test_schema()
+            .coerce(&mut document, CoercionPolicy::Lenient)
+            .expect("Coerce document");
+
+        let applied = coercions
+            .iter()
+            .map(|c| (c.path.as_str(), c.from, c.to))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            applied,
+            vec![
+                ("/count", ValueKind::String, ValueKind::U64),
+                ("/offset", ValueKind::F64, ValueKind::I64),
+                ("/created", ValueKind::String, ValueKind::Date),
+                ("/label", ValueKind::U64, ValueKind::String),
+                ("/tags", ValueKind::U64, ValueKind::ArrayU64),
+                ("/seen", ValueKind::ArrayString, ValueKind::ArrayDate),
+                ("/address/0/zip", ValueKind::String, ValueKind::U64),
+            ]
+        );
+
+        assert_eq!(document.get("count"), Some(&Value::U64(123)));
+        assert_eq!(document.get("offset"), Some(&Value::I64(-1)));
+        assert_eq!(
+            document.get("created"),
+            Some(&Value::Date(1_686_830_400_000_000))
+        );
+        assert_eq!(document.get("label"), Some(&Value::from("7")));
+        assert_eq!(document.get("tags"), Some(&Value::ArrayU64(vec![1])));
+        assert_eq!(document.get("seen"), Some(&Value::ArrayDate(vec![0])));
+        match document.get("address") {
+            Some(Value::ArrayDynamic(values)) => {
+                assert_eq!(values[0].get("zip"), Some(&Value::U64(42)))
+            },
+            other => panic!("unexpected address {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_strict_coercion() {
+        let mut document = sloppy_document();
+        document.insert("score", Value::from("not a number"));
+        let original = document.clone();
+
+        let errors = test_schema()
+            .coerce(&mut document, CoercionPolicy::Strict)
+            .expect_err("Strict policy should reject coercions")
+            .into_errors();
+        assert_eq!(errors.len(), 8);
+        assert_eq!(
+            errors[0],
+            ValidationError {
+                path: "/count".to_string(),
+                kind: ValidationErrorKind::CoercionRejected {
+                    from: ValueKind::String,
+                    to: ValueKind::U64,
+                },
+            }
+        );
+        assert_eq!(
+            errors[7].kind,
+            ValidationErrorKind::TypeMismatch {
+                expected: vec![ValueKind::F64],
+                found: ValueKind::String,
+            },
+            "Values which cannot be coerced should fail validation"
+        );
+        assert_eq!(document, original, "Nothing should change");
+    }
+
+    #[test]
+    fn test_coerce_duplicate_keys() {
+        let mut schema = Schema::default();
+        schema.insert("count", FieldSchema::new(ValueKind::U64));
+
+        let mut document = Document::default();
+        document.insert("count", Value::from("many"));
+        document.insert("count", Value::from("2"));
+
+        let errors = schema
+            .coerce(&mut document, CoercionPolicy::Lenient)
+            .expect_err("Only the second count can be coerced")
+            .into_errors();
+        assert_eq!(
+            errors,
+            vec![ValidationError {
+                path: "/count".to_string(),
+                kind: ValidationErrorKind::TypeMismatch {
+                    expected: vec![ValueKind::U64],
+                    found: ValueKind::String,
+                },
+            }]
+        );
+
+        let counts = document.get_all("count").cloned().collect::<Vec<_>>();
+        assert_eq!(
+            counts,
+            vec![Value::from("many"), Value::U64(2)],
+            "Each entry sharing a key should be coerced in place"
+        );
+    }
+
+    #[test]
+    fn test_coerce_keeps_canonical_layout() {
+        let mut document = sloppy_document();
+        document.canonicalize();
+        test_schema()
+            .coerce(&mut document, CoercionPolicy::Lenient)
+            .expect("Coerce document");
+        assert!(document.is_canonical());
+        assert_eq!(document.get("count"), Some(&Value::U64(123)));
+    }
+
+    #[test]
+    fn test_coerce_scalars() {
+        assert_eq!(
+            coerce_scalar(&Value::F64(1.5), ValueKind::I64),
+            None,
+            "Fractions should not be truncated"
+        );
+        assert_eq!(
+            coerce_scalar(&Value::F64(2f64.powi(64)), ValueKind::U64),
+            None
+        );
+        assert_eq!(coerce_scalar(&Value::I64(-1), ValueKind::U64), None);
+        assert_eq!(
+            coerce_scalar(&Value::U64((1 << 53) + 1), ValueKind::F64),
+            None,
+            "Integers which lose precision should not be converted"
+        );
+        assert_eq!(
+            coerce_scalar(&Value::from("true"), ValueKind::Bool),
+            Some(Value::Bool(true))
+        );
+        assert_eq!(
+            coerce_scalar(&Value::Date(0), ValueKind::String),
+            Some(Value::from("1970-01-01T00:00:00Z"))
+        );
+        assert_eq!(
+            coerce(
+                &Value::ArrayDynamic(vec![Value::U64(1), Value::from("2")]),
+                ValueKind::ArrayI64
+            ),
+            Some(Value::ArrayI64(vec![1, 2]))
+        );
+        assert_eq!(
+            coerce(
+                &Value::ArrayDynamic(vec![Value::U64(1), Value::Null]),
+                ValueKind::ArrayI64
+            ),
+            None
+        );
+    }
+}
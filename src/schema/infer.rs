@@ -0,0 +1,572 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::{FieldSchema, Schema};
+use crate::core::{ArchivedDocument, ArchivedValue, Document, Value, ValueKind};
+use crate::Result;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// A schema inferred by observing a corpus of documents.
+///
+/// Nested objects are tracked as nested inferred schemas, objects held within
+/// a dynamic array are all observed by the schema of the array field.
+///
+/// ```
+/// use bellini::{Document, InferredSchema, Value, ValueKind};
+///
+/// let mut a = Document::default();
+/// a.insert("name", Value::from("a"));
+/// a.insert("age", Value::U64(12));
+/// let mut b = Document::default();
+/// b.insert("name", Value::from("b"));
+///
+/// let inferred = InferredSchema::from_documents([&a, &b]);
+/// assert_eq!(inferred.objects(), 2);
+/// assert_eq!(inferred.presence("age"), Some(0.5));
+///
+/// let schema = inferred.to_schema();
+/// assert_eq!(schema.get("name").unwrap().kinds(), &[ValueKind::String]);
+/// assert!(!schema.get("age").unwrap().is_required());
+/// ```
+pub struct InferredSchema {
+    objects: u64,
+    fields: Vec<(String, InferredField)>,
+    /// The position of each field, so wide objects are observed without
+    /// scanning the fields for every key.
+    index: HashMap<String, usize>,
+}
+
+impl InferredSchema {
+    /// Infers a schema from the given documents.
+    pub fn from_documents<'a>(
+        documents: impl IntoIterator<Item = &'a Document>,
+    ) -> Self {
+        let mut inferred = Self::default();
+        for document in documents {
+            inferred.observe(document);
+        }
+        inferred
+    }
+
+    /// Infers a schema from archived documents, i.e. those produced by an
+    /// [ArchivedIterator](crate::ArchivedIterator).
+    ///
+    /// The first error produced by the iterator is returned.
+    pub fn from_archived<'a>(
+        documents: impl IntoIterator<Item = Result<&'a ArchivedDocument>>,
+    ) -> Result<Self> {
+        let mut inferred = Self::default();
+        for document in documents {
+            inferred.observe_archived(document?);
+        }
+        Ok(inferred)
+    }
+
+    /// Adds a single document to the schema.
+    pub fn observe(&mut self, document: &Document) {
+        self.observe_entries(document.fields());
+    }
+
+    /// Adds a single archived document to the schema.
+    pub fn observe_archived(&mut self, document: &ArchivedDocument) {
+        self.observe_entries(document.fields());
+    }
+
+    #[inline]
+    /// The number of documents or objects which have been observed.
+    pub fn objects(&self) -> u64 {
+        self.objects
+    }
+
+    #[inline]
+    /// Get the observed fields in the order they were first seen.
+    pub fn fields(&self) -> &[(String, InferredField)] {
+        &self.fields
+    }
+
+    #[inline]
+    /// Get the observations for a field.
+    pub fn get(&self, key: &str) -> Option<&InferredField> {
+        self.index.get(key).map(|pos| &self.fields[*pos].1)
+    }
+
+    /// The ratio of observed objects which contained the field.
+    pub fn presence(&self, key: &str) -> Option<f64> {
+        let field = self.get(key)?;
+        Some(field.present as f64 / self.objects as f64)
+    }
+
+    /// Builds a schema accepting every observed document.
+    ///
+    /// Fields which held `null` are nullable and fields missing from any
+    /// observed object are optional. Only the kinds of value observed are
+    /// declared, a field which only held objects within dynamic arrays is not
+    /// declared as an object. The objects of a dynamic array which also held
+    /// other kinds of element are not described by the schema.
+    ///
+    /// A schema shares the nullability of a field with the elements of its
+    /// dynamic arrays, so a field whose arrays of objects held `null` elements
+    /// is nullable and also accepts a `null` in place of the array, even if
+    /// one was never observed.
+    pub fn to_schema(&self) -> Schema {
+        let mut schema = Schema::default();
+        for (key, field) in &self.fields {
+            let kinds = field
+                .kinds
+                .keys()
+                .copied()
+                .filter(|kind| *kind != ValueKind::Null);
+            let mut declared = FieldSchema::one_of(kinds);
+            if !field.mixed_elements {
+                declared.object = field.object.as_ref().map(InferredSchema::to_schema);
+            }
+            // Elements are only checked against the schema of their objects.
+            let null_elements = field.null_elements && declared.object.is_some();
+            if field.kinds.contains_key(&ValueKind::Null) || null_elements {
+                declared = declared.nullable();
+            }
+            if field.present < self.objects {
+                declared = declared.optional();
+            }
+            schema.insert(key.clone(), declared);
+        }
+        schema
+    }
+
+    fn observe_entries<K, V>(&mut self, entries: &[(K, V)])
+    where
+        K: AsRef<str>,
+        V: Observe,
+    {
+        self.objects += 1;
+        let object = self.objects;
+
+        for (key, value) in entries {
+            let field = self.field_mut(key.as_ref());
+            if field.last_object != object {
+                field.last_object = object;
+                field.present += 1;
+            }
+            value.observe(field);
+        }
+    }
+
+    fn field_mut(&mut self, key: &str) -> &mut InferredField {
+        let pos = match self.index.get(key) {
+            Some(pos) => *pos,
+            None => {
+                let pos = self.fields.len();
+                self.index.insert(key.to_string(), pos);
+                self.fields
+                    .push((key.to_string(), InferredField::default()));
+                pos
+            },
+        };
+        &mut self.fields[pos].1
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// The observations made for a single field.
+pub struct InferredField {
+    present: u64,
+    /// The last object the field was counted as present in, so keys repeated
+    /// within an object are only counted once.
+    last_object: u64,
+    kinds: BTreeMap<ValueKind, u64>,
+    elements: BTreeMap<ValueKind, u64>,
+    u64_range: Option<NumericRange<u64>>,
+    i64_range: Option<NumericRange<i64>>,
+    f64_range: Option<NumericRange<f64>>,
+    date_range: Option<NumericRange<i64>>,
+    object: Option<InferredSchema>,
+    /// If a dynamic array held a `null` element.
+    null_elements: bool,
+    /// If a dynamic array held an element which was neither an object
+    /// nor `null`.
+    mixed_elements: bool,
+}
+
+impl InferredField {
+    #[inline]
+    /// The number of objects which contained the field.
+    pub fn present(&self) -> u64 {
+        self.present
+    }
+
+    #[inline]
+    /// The kinds of value observed and the number of times each was seen.
+    pub fn kinds(&self) -> &BTreeMap<ValueKind, u64> {
+        &self.kinds
+    }
+
+    #[inline]
+    /// The kinds of array element observed and the number of times each was
+    /// seen, across every array held by the field.
+    pub fn element_kinds(&self) -> &BTreeMap<ValueKind, u64> {
+        &self.elements
+    }
+
+    #[inline]
+    /// The range of `u64` values, including array elements.
+    pub fn u64_range(&self) -> Option<NumericRange<u64>> {
+        self.u64_range
+    }
+
+    #[inline]
+    /// The range of `i64` values, including array elements.
+    pub fn i64_range(&self) -> Option<NumericRange<i64>> {
+        self.i64_range
+    }
+
+    #[inline]
+    /// The range of `f64` values, including array elements.
+    ///
+    /// `NaN` values are ignored.
+    pub fn f64_range(&self) -> Option<NumericRange<f64>> {
+        self.f64_range
+    }
+
+    #[inline]
+    /// The range of date offsets, including array elements.
+    pub fn date_range(&self) -> Option<NumericRange<i64>> {
+        self.date_range
+    }
+
+    #[inline]
+    /// The schema inferred from any objects held by the field.
+    pub fn object(&self) -> Option<&InferredSchema> {
+        self.object.as_ref()
+    }
+
+    fn observe_elements<T: Copy + PartialOrd>(
+        &mut self,
+        kind: ValueKind,
+        values: &[T],
+        range: fn(&mut Self) -> &mut Option<NumericRange<T>>,
+    ) {
+        if values.is_empty() {
+            return;
+        }
+        *self.elements.entry(kind).or_default() += values.len() as u64;
+        let range = range(self);
+        for value in values {
+            NumericRange::extend(range, *value);
+        }
+    }
+
+    fn object_mut(&mut self) -> &mut InferredSchema {
+        self.object.get_or_insert_with(InferredSchema::default)
+    }
+}
+
+impl PartialEq for InferredField {
+    fn eq(&self, other: &Self) -> bool {
+        self.present == other.present
+            && self.kinds == other.kinds
+            && self.elements == other.elements
+            && self.u64_range == other.u64_range
+            && self.i64_range == other.i64_range
+            && self.f64_range == other.f64_range
+            && self.date_range == other.date_range
+            && self.object == other.object
+            && self.null_elements == other.null_elements
+            && self.mixed_elements == other.mixed_elements
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// The smallest and largest observed values.
+pub struct NumericRange<T> {
+    pub min: T,
+    pub max: T,
+}
+
+impl<T: Copy + PartialOrd> NumericRange<T> {
+    fn extend(range: &mut Option<Self>, value: T) {
+        // Skips `NaN`, which is not comparable to itself.
+        if value.partial_cmp(&value).is_none() {
+            return;
+        }
+
+        match range {
+            None => {
+                *range = Some(Self {
+                    min: value,
+                    max: value,
+                })
+            },
+            Some(range) => {
+                if value < range.min {
+                    range.min = value;
+                }
+                if value > range.max {
+                    range.max = value;
+                }
+            },
+        }
+    }
+}
+
+/// Records a value against the field which held it, implemented for both the
+/// owned and archived values.
+trait Observe {
+    fn observe(&self, field: &mut InferredField);
+}
+
+macro_rules! impl_observe {
+    ($value:ident) => {
+        impl Observe for $value {
+            fn observe(&self, field: &mut InferredField) {
+                *field.kinds.entry(self.kind()).or_default() += 1;
+
+                match self {
+                    $value::U64(v) => NumericRange::extend(&mut field.u64_range, *v),
+                    $value::I64(v) => NumericRange::extend(&mut field.i64_range, *v),
+                    $value::F64(v) => NumericRange::extend(&mut field.f64_range, *v),
+                    $value::Date(v) => NumericRange::extend(&mut field.date_range, *v),
+                    $value::ArrayBool(values) => {
+                        *field.elements.entry(ValueKind::Bool).or_default() +=
+                            values.len() as u64
+                    },
+                    $value::ArrayString(values) => {
+                        *field.elements.entry(ValueKind::String).or_default() +=
+                            values.len() as u64
+                    },
+                    $value::ArrayBytes(values) => {
+                        *field.elements.entry(ValueKind::Bytes).or_default() +=
+                            values.len() as u64
+                    },
+                    $value::ArrayU64(values) => {
+                        field.observe_elements(ValueKind::U64, values, |f| {
+                            &mut f.u64_range
+                        })
+                    },
+                    $value::ArrayI64(values) => {
+                        field.observe_elements(ValueKind::I64, values, |f| {
+                            &mut f.i64_range
+                        })
+                    },
+                    $value::ArrayF64(values) => {
+                        field.observe_elements(ValueKind::F64, values, |f| {
+                            &mut f.f64_range
+                        })
+                    },
+                    $value::ArrayDate(values) => {
+                        field.observe_elements(ValueKind::Date, values, |f| {
+                            &mut f.date_range
+                        })
+                    },
+                    $value::ArrayDynamic(values) => {
+                        for value in values.iter() {
+                            *field.elements.entry(value.kind()).or_default() += 1;
+                            match value {
+                                $value::Object(_) => {},
+                                $value::Null => field.null_elements = true,
+                                _ => field.mixed_elements = true,
+                            }
+                            match value {
+                                $value::U64(v) => {
+                                    NumericRange::extend(&mut field.u64_range, *v)
+                                },
+                                $value::I64(v) => {
+                                    NumericRange::extend(&mut field.i64_range, *v)
+                                },
+                                $value::F64(v) => {
+                                    NumericRange::extend(&mut field.f64_range, *v)
+                                },
+                                $value::Date(v) => {
+                                    NumericRange::extend(&mut field.date_range, *v)
+                                },
+                                $value::Object(entries) => {
+                                    field.object_mut().observe_entries(entries)
+                                },
+                                _ => {},
+                            }
+                        }
+                    },
+                    $value::Object(entries) => {
+                        field.object_mut().observe_entries(entries)
+                    },
+                    $value::Null
+                    | $value::Bool(_)
+                    | $value::String(_)
+                    | $value::Bytes(_) => {},
+                }
+            }
+        }
+    };
+}
+
+impl_observe!(Value);
+impl_observe!(ArchivedValue);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Decoder, Encoder, Text, DEFAULT_SCRATCH_SPACE};
+
+    fn object(entries: Vec<(&str, Value)>) -> Value {
+        Value::Object(
+            entries
+                .into_iter()
+                .map(|(k, v)| (Text::from(k), v))
+                .collect(),
+        )
+    }
+
+    fn test_documents() -> Vec<Document> {
+        let mut a = Document::default();
+        a.insert("name", Value::from("a"));
+        a.insert("score", Value::U64(3));
+        a.insert("seen", Value::ArrayDate(vec![10, -5]));
+        a.insert(
+            "address",
+            object(vec![
+                ("city", Value::from("london")),
+                ("zip", Value::U64(1)),
+            ]),
+        );
+
+        let mut b = Document::default();
+        b.insert("name", Value::from("b"));
+        b.insert(
+            "score",
+            Value::ArrayDynamic(vec![Value::I64(-2), Value::F64(0.5)]),
+        );
+        b.insert("seen", Value::Null);
+        b.insert(
+            "address",
+            Value::ArrayDynamic(vec![
+                object(vec![("city", Value::from("paris"))]),
+                object(vec![("city", Value::Null)]),
+            ]),
+        );
+
+        let mut c = Document::default();
+        c.insert("name", Value::from("c"));
+        c.insert("score", Value::ArrayU64(vec![7, 1]));
+
+        vec![a, b, c]
+    }
+
+    #[test]
+    fn test_infer_schema() {
+        let documents = test_documents();
+        let inferred = InferredSchema::from_documents(&documents);
+        assert_eq!(inferred.objects(), 3);
+        assert_eq!(inferred.presence("name"), Some(1.0));
+        assert_eq!(inferred.presence("missing"), None);
+
+        let score = inferred.get("score").unwrap();
+        assert_eq!(
+            score.kinds(),
+            &BTreeMap::from_iter([
+                (ValueKind::U64, 1),
+                (ValueKind::ArrayU64, 1),
+                (ValueKind::ArrayDynamic, 1)
+            ])
+        );
+        assert_eq!(
+            score.element_kinds(),
+            &BTreeMap::from_iter([
+                (ValueKind::U64, 2),
+                (ValueKind::I64, 1),
+                (ValueKind::F64, 1)
+            ])
+        );
+        assert_eq!(score.u64_range(), Some(NumericRange { min: 1, max: 7 }));
+        assert_eq!(score.i64_range(), Some(NumericRange { min: -2, max: -2 }));
+        assert_eq!(score.f64_range(), Some(NumericRange { min: 0.5, max: 0.5 }));
+
+        let seen = inferred.get("seen").unwrap();
+        assert_eq!(inferred.presence("seen"), Some(2.0 / 3.0));
+        assert_eq!(seen.date_range(), Some(NumericRange { min: -5, max: 10 }));
+
+        let address = inferred.get("address").unwrap().object().unwrap();
+        assert_eq!(address.objects(), 3);
+        assert_eq!(address.presence("city"), Some(1.0));
+        assert_eq!(address.presence("zip"), Some(1.0 / 3.0));
+
+        let schema = inferred.to_schema();
+        for document in &documents {
+            assert_eq!(schema.validate(document), Ok(()));
+        }
+        let name = schema.get("name").unwrap();
+        assert!(name.is_required() && !name.is_nullable());
+        let seen = schema.get("seen").unwrap();
+        assert!(!seen.is_required() && seen.is_nullable());
+        assert_eq!(seen.kinds(), &[ValueKind::ArrayDate]);
+        let address = schema.get("address").unwrap();
+        assert_eq!(address.kinds().len(), 2);
+        assert!(address.kinds().contains(&ValueKind::Object));
+        assert!(address.kinds().contains(&ValueKind::ArrayDynamic));
+        let city = address.object_schema().unwrap().get("city").unwrap();
+        assert!(city.is_required() && city.is_nullable());
+    }
+
+    #[test]
+    fn test_infer_array_element_schema() {
+        let mut a = Document::default();
+        a.insert(
+            "previous",
+            Value::ArrayDynamic(vec![object(vec![("city", Value::from("paris"))])]),
+        );
+        a.insert(
+            "mixed",
+            Value::ArrayDynamic(vec![
+                object(vec![("id", Value::U64(1))]),
+                Value::U64(2),
+            ]),
+        );
+        a.insert("nulls", Value::ArrayDynamic(vec![Value::Null]));
+        let mut b = Document::default();
+        b.insert("previous", Value::ArrayDynamic(vec![Value::Null]));
+        b.insert("nulls", Value::ArrayDynamic(vec![Value::Null]));
+        let documents = [a, b];
+
+        let schema = InferredSchema::from_documents(&documents).to_schema();
+        for document in &documents {
+            assert_eq!(schema.validate(document), Ok(()));
+        }
+
+        let previous = schema.get("previous").unwrap();
+        assert_eq!(previous.kinds(), &[ValueKind::ArrayDynamic]);
+        assert!(previous.is_nullable(), "Null elements should be accepted");
+        let city = previous.object_schema().unwrap().get("city").unwrap();
+        assert_eq!(city.kinds(), &[ValueKind::String]);
+
+        let nulls = schema.get("nulls").unwrap();
+        assert_eq!(nulls.kinds(), &[ValueKind::ArrayDynamic]);
+        assert!(
+            !nulls.is_nullable(),
+            "Null elements without objects should not make the field nullable"
+        );
+
+        let mixed = schema.get("mixed").unwrap();
+        assert_eq!(mixed.kinds(), &[ValueKind::ArrayDynamic]);
+        assert!(mixed.object_schema().is_none());
+
+        let mut document = Document::default();
+        document.insert("previous", object(vec![("city", Value::from("rome"))]));
+        assert!(
+            schema.validate(&document).is_err(),
+            "Array element fields should not be declared as an object"
+        );
+    }
+
+    #[test]
+    fn test_infer_archived_schema() {
+        let documents = test_documents();
+        let mut buffer = rkyv::AlignedVec::new();
+        let mut encoder = Encoder::<_, DEFAULT_SCRATCH_SPACE>::new(&mut buffer);
+        for document in &documents {
+            encoder.encode(document).expect("Encode document");
+        }
+        let _ = encoder.into_writer();
+
+        let decoder = Decoder::new(&buffer);
+        let inferred = InferredSchema::from_archived(decoder.checked_archived_iter())
+            .expect("Infer schema");
+        assert_eq!(inferred, InferredSchema::from_documents(&documents));
+    }
+}
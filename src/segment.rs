@@ -0,0 +1,937 @@
+//! A self describing file format wrapping the encoded documents
+//!
+//! A segment is laid out as:
+//! `| header | padding | documents | position index | trailer |`
+//!
+//! The header identifies the format and the layout the documents were archived
+//! with, so segments written by an incompatible version of bellini are rejected
+//! by [Decoder::open](crate::Decoder::open) rather than being misread. It also
+//! records the checksum algorithm of the document footers, so the decoder
+//! verifies them without being configured. The trailer holds the number of
+//! documents, where the position index starts and a checksum of everything
+//! before it.
+
+use std::error::Error as StdError;
+use std::hash::Hasher;
+use std::io::{self, Write};
+use std::{fmt, mem};
+
+use crate::encoder::{assert_valid_alignment, padding_for};
+use crate::{
+    Alignment,
+    ChecksumAlgorithm,
+    Document,
+    Encoder,
+    Framing,
+    FramingWidth,
+    Result,
+    Schema,
+    ScratchStats,
+    DEFAULT_SCRATCH_SPACE,
+    MIN_ALIGNMENT,
+};
+
+/// The magic bytes at the start and end of every segment.
+pub const SEGMENT_MAGIC: [u8; 4] = *b"BLNI";
+
+/// The version of the segment format written by this build.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// The version of the archived [Document] layout written by this build.
+///
+/// This must be bumped whenever the archived representation of a document,
+/// or any of its values, changes.
+pub const LAYOUT_VERSION: u16 = 1;
+
+/// The size of the segment header, before it is padded to the alignment.
+pub const SEGMENT_HEADER_SIZE: usize = 24;
+
+/// The size of the segment trailer.
+pub const SEGMENT_TRAILER_SIZE: usize = 24;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+/// The options a segment was encoded with.
+///
+/// Segments with flags unknown to this build are rejected, as they may
+/// require features this build does not support.
+pub struct SegmentFlags(u32);
+
+impl SegmentFlags {
+    /// The documents were encoded with the canonical layout.
+    pub const CANONICAL: Self = Self(1 << 0);
+    /// The documents were encoded with [Framing::Bidirectional].
+    pub const BIDIRECTIONAL: Self = Self(1 << 1);
+    /// The documents were encoded with [FramingWidth::Wide].
+    pub const WIDE: Self = Self(1 << 2);
+
+    const KNOWN: u32 = Self::CANONICAL.0 | Self::BIDIRECTIONAL.0 | Self::WIDE.0;
+
+    #[inline]
+    /// Creates the flags from their raw bits.
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    #[inline]
+    /// The raw bits of the flags.
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    #[inline]
+    /// Returns if all of the given flags are set.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    #[inline]
+    /// Sets the given flags.
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    #[inline]
+    /// The bits which are not known to this build.
+    pub fn unknown_bits(self) -> u32 {
+        self.0 & !Self::KNOWN
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// The header at the start of every segment.
+///
+/// The header is written in the format of:
+/// `| magic(4) | format version(2) | layout version(2) | flags(4) | alignment(4) |`
+/// `| archived usize size(1) | big endian(1) | checksum algorithm(1) | reserved(1) |`
+/// `| header checksum(4) |`
+///
+/// The header is zero padded to the alignment so the first document is aligned.
+pub struct SegmentHeader {
+    /// The version of the segment format.
+    pub format_version: u16,
+    /// The version of the archived document layout.
+    pub layout_version: u16,
+    /// The options the segment was encoded with.
+    pub flags: SegmentFlags,
+    /// The alignment of each document.
+    pub alignment: u32,
+    /// The size of an archived `usize`, which depends on rkyv's features.
+    pub archived_usize_size: u8,
+    /// If the documents were archived on a big endian target.
+    pub big_endian: bool,
+    /// The checksum algorithm of each document's footer.
+    pub checksum: ChecksumAlgorithm,
+}
+
+impl SegmentHeader {
+    /// Creates the header written by this build for the given options.
+    pub fn new(
+        flags: SegmentFlags,
+        alignment: usize,
+        checksum: ChecksumAlgorithm,
+    ) -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            layout_version: LAYOUT_VERSION,
+            flags,
+            alignment: alignment as u32,
+            archived_usize_size: mem::size_of::<rkyv::Archived<usize>>() as u8,
+            big_endian: cfg!(target_endian = "big"),
+            checksum,
+        }
+    }
+
+    /// Reads the header from the start of the given buffer.
+    ///
+    /// This only checks the header is intact, not that it is compatible
+    /// with this build, see [SegmentHeader::check_compatible].
+    pub fn read(buf: &[u8]) -> Result<Self, SegmentError> {
+        let header = buf
+            .get(..SEGMENT_HEADER_SIZE)
+            .ok_or(SegmentError::Truncated { len: buf.len() })?;
+
+        if header[..4] != SEGMENT_MAGIC {
+            return Err(SegmentError::BadMagic);
+        }
+
+        let expected = read_u32(&header[20..]);
+        let actual = crc32fast::hash(&header[..20]);
+        if expected != actual {
+            return Err(SegmentError::HeaderChecksumMismatch { expected, actual });
+        }
+
+        let checksum = ChecksumAlgorithm::from_id(header[18])
+            .ok_or(SegmentError::UnknownChecksum { id: header[18] })?;
+
+        Ok(Self {
+            format_version: u16::from_le_bytes([header[4], header[5]]),
+            layout_version: u16::from_le_bytes([header[6], header[7]]),
+            flags: SegmentFlags(read_u32(&header[8..])),
+            alignment: read_u32(&header[12..]),
+            archived_usize_size: header[16],
+            big_endian: header[17] != 0,
+            checksum,
+        })
+    }
+
+    /// Encodes the header, without its padding.
+    pub fn to_bytes(&self) -> [u8; SEGMENT_HEADER_SIZE] {
+        let mut header = [0; SEGMENT_HEADER_SIZE];
+        header[..4].copy_from_slice(&SEGMENT_MAGIC);
+        header[4..6].copy_from_slice(&self.format_version.to_le_bytes());
+        header[6..8].copy_from_slice(&self.layout_version.to_le_bytes());
+        header[8..12].copy_from_slice(&self.flags.0.to_le_bytes());
+        header[12..16].copy_from_slice(&self.alignment.to_le_bytes());
+        header[16] = self.archived_usize_size;
+        header[17] = self.big_endian as u8;
+        header[18] = self.checksum.id();
+        let checksum = crc32fast::hash(&header[..20]);
+        header[20..].copy_from_slice(&checksum.to_le_bytes());
+        header
+    }
+
+    /// Checks the segment can be decoded by this build.
+    pub fn check_compatible(&self) -> Result<(), SegmentError> {
+        if self.format_version != FORMAT_VERSION {
+            return Err(SegmentError::UnsupportedFormatVersion {
+                found: self.format_version,
+            });
+        }
+
+        let expected = Self::new(self.flags, MIN_ALIGNMENT, self.checksum);
+        if self.layout_version != expected.layout_version
+            || self.archived_usize_size != expected.archived_usize_size
+            || self.big_endian != expected.big_endian
+        {
+            return Err(SegmentError::IncompatibleLayout {
+                layout_version: self.layout_version,
+                archived_usize_size: self.archived_usize_size,
+                big_endian: self.big_endian,
+            });
+        }
+
+        if self.flags.unknown_bits() != 0 {
+            return Err(SegmentError::UnknownFlags {
+                bits: self.flags.unknown_bits(),
+            });
+        }
+
+        let alignment = self.alignment as usize;
+        if !alignment.is_power_of_two() || alignment < MIN_ALIGNMENT {
+            return Err(SegmentError::InvalidAlignment {
+                alignment: self.alignment,
+            });
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    /// How each document in the segment is framed.
+    pub fn framing(&self) -> Framing {
+        if self.flags.contains(SegmentFlags::BIDIRECTIONAL) {
+            Framing::Bidirectional
+        } else {
+            Framing::Backward
+        }
+    }
+
+    #[inline]
+    /// The width of the lengths framing each document in the segment.
+    pub fn width(&self) -> FramingWidth {
+        if self.flags.contains(SegmentFlags::WIDE) {
+            FramingWidth::Wide
+        } else {
+            FramingWidth::Narrow
+        }
+    }
+
+    #[inline]
+    /// The size of the header once padded to the alignment.
+    pub fn padded_size(&self) -> usize {
+        SEGMENT_HEADER_SIZE + padding_for(SEGMENT_HEADER_SIZE, self.alignment as usize)
+    }
+}
+
+#[derive(Debug)]
+/// The reason a segment could not be opened.
+pub enum SegmentError {
+    /// The buffer is too short to hold a segment.
+    Truncated {
+        /// The length of the buffer.
+        len: usize,
+    },
+    /// The buffer does not start or end with [SEGMENT_MAGIC].
+    BadMagic,
+    /// The header is corrupt.
+    HeaderChecksumMismatch {
+        /// The checksum stored in the header.
+        expected: u32,
+        /// The checksum of the header.
+        actual: u32,
+    },
+    /// The segment was written with a different format version.
+    UnsupportedFormatVersion {
+        /// The format version in the header.
+        found: u16,
+    },
+    /// The documents were archived with a layout this build cannot read.
+    IncompatibleLayout {
+        /// The layout version in the header.
+        layout_version: u16,
+        /// The size of an archived `usize` in the header.
+        archived_usize_size: u8,
+        /// If the header says the documents were archived on a big endian target.
+        big_endian: bool,
+    },
+    /// The segment was written with options unknown to this build.
+    UnknownFlags {
+        /// The flag bits unknown to this build.
+        bits: u32,
+    },
+    /// The documents were written with a checksum algorithm unknown to this build.
+    UnknownChecksum {
+        /// The identifier of the checksum algorithm in the header.
+        id: u8,
+    },
+    /// The alignment in the header is not a valid document alignment.
+    InvalidAlignment {
+        /// The alignment in the header.
+        alignment: u32,
+    },
+    /// The buffer does not start on a multiple of the segment's alignment,
+    /// so its documents cannot be aligned.
+    MisalignedBuffer {
+        /// The alignment in the header.
+        alignment: u32,
+    },
+    /// The checksum of the segment did not match the one in its trailer.
+    ChecksumMismatch {
+        /// The checksum stored in the trailer.
+        expected: u32,
+        /// The checksum of the segment.
+        actual: u32,
+    },
+    /// The position index offset in the trailer lies outside the segment.
+    InvalidIndexOffset {
+        /// The offset in the trailer.
+        offset: u64,
+    },
+    /// The position index does not hold a position for every document.
+    DocumentCountMismatch {
+        /// The number of documents in the trailer.
+        expected: u64,
+        /// The number of positions in the position index.
+        found: usize,
+    },
+}
+
+impl fmt::Display for SegmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated { len } => {
+                write!(f, "buffer of length {len} is too short to be a segment")
+            },
+            Self::BadMagic => write!(f, "buffer is not a bellini segment"),
+            Self::HeaderChecksumMismatch { expected, actual } => write!(
+                f,
+                "segment header has checksum {actual:#010x}, expected {expected:#010x}"
+            ),
+            Self::UnsupportedFormatVersion { found } => write!(
+                f,
+                "segment format version {found} is not supported, expected {FORMAT_VERSION}"
+            ),
+            Self::IncompatibleLayout {
+                layout_version,
+                archived_usize_size,
+                big_endian,
+            } => write!(
+                f,
+                "segment layout version {layout_version} with {archived_usize_size} byte \
+                 usizes{} is not compatible with this build",
+                if *big_endian { " in big endian" } else { "" }
+            ),
+            Self::UnknownFlags { bits } => {
+                write!(f, "segment has unknown flags {bits:#010x}")
+            },
+            Self::UnknownChecksum { id } => {
+                write!(f, "segment has an unknown checksum algorithm {id}")
+            },
+            Self::InvalidAlignment { alignment } => {
+                write!(f, "segment has an invalid alignment of {alignment}")
+            },
+            Self::MisalignedBuffer { alignment } => {
+                write!(f, "segment buffer is not aligned to {alignment} bytes")
+            },
+            Self::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "segment has checksum {actual:#010x}, expected {expected:#010x}"
+            ),
+            Self::InvalidIndexOffset { offset } => {
+                write!(f, "segment position index offset {offset} is out of bounds")
+            },
+            Self::DocumentCountMismatch { expected, found } => write!(
+                f,
+                "segment holds {expected} documents but its position index has {found}"
+            ),
+        }
+    }
+}
+
+impl StdError for SegmentError {}
+
+#[cfg(any(feature = "validation", test))]
+/// The location of each part of a segment which has been checked to be intact.
+pub(crate) struct SegmentBounds {
+    pub(crate) header: SegmentHeader,
+    pub(crate) documents: u64,
+    /// The byte offset of the first document.
+    pub(crate) documents_start: usize,
+    /// The byte offset of the position index, after the last document.
+    pub(crate) index_offset: usize,
+    /// The byte offset the position index ends at.
+    pub(crate) index_end: usize,
+}
+
+#[cfg(any(feature = "validation", test))]
+/// Checks the header, trailer and checksum of the segment.
+pub(crate) fn read_segment(buf: &[u8]) -> Result<SegmentBounds, SegmentError> {
+    let header = SegmentHeader::read(buf)?;
+    header.check_compatible()?;
+
+    let documents_start = header.padded_size();
+    let index_end = buf
+        .len()
+        .checked_sub(SEGMENT_TRAILER_SIZE)
+        .filter(|end| *end >= documents_start)
+        .ok_or(SegmentError::Truncated { len: buf.len() })?;
+
+    let trailer = &buf[index_end..];
+    if trailer[20..] != SEGMENT_MAGIC {
+        return Err(SegmentError::BadMagic);
+    }
+
+    let expected = read_u32(&trailer[16..]);
+    let actual = crc32fast::hash(&buf[..index_end + 16]);
+    if expected != actual {
+        return Err(SegmentError::ChecksumMismatch { expected, actual });
+    }
+
+    let documents = read_u64(trailer);
+    let offset = read_u64(&trailer[8..]);
+    let index_offset = usize::try_from(offset)
+        .ok()
+        .filter(|index_offset| (documents_start..=index_end).contains(index_offset))
+        .ok_or(SegmentError::InvalidIndexOffset { offset })?;
+
+    Ok(SegmentBounds {
+        header,
+        documents,
+        documents_start,
+        index_offset,
+        index_end,
+    })
+}
+
+#[inline]
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+#[cfg(any(feature = "validation", test))]
+#[inline]
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+/// A document encoder that writes a self describing segment.
+///
+/// The header is written before the first document, so the encoder must be
+/// configured before encoding. The segment is incomplete until
+/// [SegmentEncoder::finish] writes the position index and trailer.
+pub struct SegmentEncoder<W: Write, const N: usize = DEFAULT_SCRATCH_SPACE> {
+    encoder: Encoder<BlockChecksumWriter<W>, N>,
+    flags: SegmentFlags,
+    header_size: Option<usize>,
+}
+
+impl<W: Write, const N: usize> SegmentEncoder<W, N> {
+    #[inline]
+    /// Create a new segment encoder.
+    pub fn new(writer: W) -> Self {
+        Self::with_alignment(writer, MIN_ALIGNMENT)
+    }
+
+    /// Create a new segment encoder which aligns each document
+    /// to the given number of bytes.
+    ///
+    /// Documents within a segment are always padded, the alignment
+    /// defaults to [MIN_ALIGNMENT].
+    ///
+    /// ### Panics
+    /// If the alignment is not a power of two or is less than [MIN_ALIGNMENT].
+    pub fn with_alignment(writer: W, alignment: usize) -> Self {
+        assert_valid_alignment(alignment);
+
+        let mut encoder = Encoder::with_alignment(
+            BlockChecksumWriter::new(writer),
+            Alignment::Padded(alignment),
+        );
+        encoder.enable_position_index();
+
+        Self {
+            encoder,
+            flags: SegmentFlags::default(),
+            header_size: None,
+        }
+    }
+
+    /// Enables the canonical document layout, see
+    /// [Encoder::enable_canonical_layout].
+    ///
+    /// The layout is recorded with [SegmentFlags::CANONICAL], see
+    /// [Decoder::as_canonical](crate::Decoder::as_canonical).
+    ///
+    /// ### Panics
+    /// If the header has already been written.
+    pub fn enable_canonical_layout(&mut self) {
+        self.assert_header_unwritten();
+        self.encoder.enable_canonical_layout();
+        self.flags.insert(SegmentFlags::CANONICAL);
+    }
+
+    /// Sets how each document is framed, see [Framing].
+    ///
+    /// ### Panics
+    /// If the header has already been written.
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.assert_header_unwritten();
+        self.encoder.set_framing(framing);
+    }
+
+    /// Sets the width of the lengths framing each document and of the
+    /// positions in the position index, see [FramingWidth].
+    ///
+    /// Segments over 4 GiB must use [FramingWidth::Wide].
+    ///
+    /// ### Panics
+    /// If the header has already been written.
+    pub fn set_framing_width(&mut self, width: FramingWidth) {
+        self.assert_header_unwritten();
+        self.encoder.set_framing_width(width);
+    }
+
+    /// Sets the checksum algorithm of each document's footer,
+    /// see [Encoder::set_checksum_algorithm].
+    ///
+    /// ### Panics
+    /// If the header has already been written.
+    pub fn set_checksum_algorithm(&mut self, algorithm: ChecksumAlgorithm) {
+        self.assert_header_unwritten();
+        self.encoder.set_checksum_algorithm(algorithm);
+    }
+
+    /// Validates every document against the schema before encoding it,
+    /// see [Encoder::set_schema].
+    pub fn set_schema(&mut self, schema: Schema) {
+        self.encoder.set_schema(schema);
+    }
+
+    /// Limits the heap scratch space used while serializing a single document,
+    /// see [Encoder::set_scratch_heap_limit].
+    pub fn set_scratch_heap_limit(&mut self, limit: Option<usize>) {
+        self.encoder.set_scratch_heap_limit(limit);
+    }
+
+    #[inline]
+    /// Returns how often serializing has spilled out of the stack scratch space.
+    pub fn scratch_stats(&self) -> ScratchStats {
+        self.encoder.scratch_stats()
+    }
+
+    #[inline]
+    /// The number of documents which have been encoded.
+    pub fn documents(&self) -> usize {
+        self.encoder.documents()
+    }
+
+    #[inline]
+    /// Return a reference to the given writer.
+    pub fn writer(&self) -> &W {
+        &self.encoder.writer().writer
+    }
+
+    /// Encode a document and write the output to the writer.
+    ///
+    /// The header is written before the first document.
+    pub fn encode(&mut self, document: &Document) -> Result<()> {
+        self.write_header()?;
+        self.encoder.encode(document)
+    }
+
+    /// Writes the position index and trailer, completing the segment,
+    /// and returns the inner writer.
+    pub fn finish(mut self) -> Result<W> {
+        let header_size = self.write_header()?;
+        let documents = self.encoder.documents() as u64;
+        let index_offset = (header_size + self.encoder.position()) as u64;
+        self.encoder.write_index_record()?;
+
+        let mut writer = self.encoder.into_writer();
+        writer.write_all(&documents.to_le_bytes())?;
+        writer.write_all(&index_offset.to_le_bytes())?;
+        let checksum = writer.hasher.finalize();
+
+        let mut writer = writer.writer;
+        writer.write_all(&checksum.to_le_bytes())?;
+        writer.write_all(&SEGMENT_MAGIC)?;
+        Ok(writer)
+    }
+
+    /// Writes the header if it has not been written yet, returning its size.
+    fn write_header(&mut self) -> Result<usize> {
+        if let Some(header_size) = self.header_size {
+            return Ok(header_size);
+        }
+
+        let mut flags = self.flags;
+        if self.encoder.framing() == Framing::Bidirectional {
+            flags.insert(SegmentFlags::BIDIRECTIONAL);
+        }
+        if self.encoder.framing_width() == FramingWidth::Wide {
+            flags.insert(SegmentFlags::WIDE);
+        }
+        let header = SegmentHeader::new(
+            flags,
+            self.encoder.alignment().get(),
+            self.encoder.checksum_algorithm(),
+        );
+        let header_size = header.padded_size();
+
+        let writer = self.encoder.writer_mut();
+        writer.write_all(&header.to_bytes())?;
+        writer.write_all(&vec![0; header_size - SEGMENT_HEADER_SIZE])?;
+
+        self.header_size = Some(header_size);
+        Ok(header_size)
+    }
+
+    #[inline]
+    fn assert_header_unwritten(&self) {
+        assert!(
+            self.header_size.is_none(),
+            "The segment must be configured before encoding any documents"
+        );
+    }
+}
+
+/// A writer which calculates the checksum of everything written to it.
+struct BlockChecksumWriter<W> {
+    writer: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W> BlockChecksumWriter<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            writer,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for BlockChecksumWriter<W> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.writer.write(buf)?;
+        self.hasher.write(&buf[..n]);
+        Ok(n)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rkyv::AlignedVec;
+
+    use super::*;
+    use crate::decoder::RecordLayout;
+    use crate::test_utils::{documents, encode_segment};
+    use crate::{Decoder, Error, Value};
+
+    /// Replaces the header of the segment, keeping the checksums intact.
+    fn rewrite_header(buf: &mut AlignedVec, header: SegmentHeader) {
+        buf[..SEGMENT_HEADER_SIZE].copy_from_slice(&header.to_bytes());
+        let index_end = buf.len() - SEGMENT_TRAILER_SIZE;
+        let checksum = crc32fast::hash(&buf[..index_end + 16]);
+        buf[index_end + 16..index_end + 20].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    fn open_error(buf: &[u8]) -> SegmentError {
+        match Decoder::open(buf) {
+            Err(Error::Segment(e)) => e,
+            Err(e) => panic!("Expected a segment error, got {e}"),
+            Ok(_) => panic!("Segment should be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_segment_roundtrip() {
+        for (framing, width, checksum) in [
+            (
+                Framing::Backward,
+                FramingWidth::Narrow,
+                ChecksumAlgorithm::Crc32,
+            ),
+            #[cfg(feature = "crc32c")]
+            (
+                Framing::Bidirectional,
+                FramingWidth::Narrow,
+                ChecksumAlgorithm::Crc32c,
+            ),
+            #[cfg(feature = "xxh3")]
+            (
+                Framing::Backward,
+                FramingWidth::Wide,
+                ChecksumAlgorithm::Xxh3,
+            ),
+            (
+                Framing::Bidirectional,
+                FramingWidth::Wide,
+                ChecksumAlgorithm::None,
+            ),
+        ] {
+            let documents = documents(5);
+            let layout = RecordLayout {
+                framing,
+                width,
+                checksum,
+                ..RecordLayout::default()
+            };
+            let buf = encode_segment(&documents, layout);
+
+            let header = SegmentHeader::read(&buf).expect("Read header");
+            assert_eq!(
+                header,
+                SegmentHeader::new(header.flags, MIN_ALIGNMENT, checksum)
+            );
+            assert_eq!(header.framing(), framing);
+            assert_eq!(header.width(), width);
+            assert_eq!(header.checksum, checksum);
+
+            let mut decoder = Decoder::open(&buf).expect("Open segment");
+            decoder.enable_checksum_validation();
+            for (idx, document) in documents.iter().enumerate() {
+                assert_eq!(
+                    decoder.deserialize_at(idx).unwrap().as_ref(),
+                    Some(document)
+                );
+            }
+
+            let mut returned = decoder
+                .deserializer_iter()
+                .collect::<Result<Vec<_>>>()
+                .expect("Every document should be readable");
+            returned.reverse();
+            assert_eq!(returned, documents);
+        }
+    }
+
+    #[test]
+    fn test_empty_segment() {
+        let buf = encode_segment(&[], RecordLayout::default());
+        let decoder = Decoder::open(&buf).expect("Open segment");
+        assert_eq!(decoder.deserializer_iter().count(), 0);
+        assert!(decoder.deserialize_at(0).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_segment_flags() {
+        let mut encoder = SegmentEncoder::<_, DEFAULT_SCRATCH_SPACE>::with_alignment(
+            AlignedVec::new(),
+            64,
+        );
+        encoder.enable_canonical_layout();
+        encoder.set_framing(Framing::Bidirectional);
+        let buf = encoder.finish().expect("Finish segment");
+
+        let header = SegmentHeader::read(&buf).expect("Read header");
+        assert!(header.flags.contains(SegmentFlags::CANONICAL));
+        assert!(header.flags.contains(SegmentFlags::BIDIRECTIONAL));
+        assert_eq!(header.alignment, 64);
+        assert_eq!(header.padded_size(), 64);
+    }
+
+    #[test]
+    fn test_canonical_segment() {
+        let mut encoder =
+            SegmentEncoder::<_, DEFAULT_SCRATCH_SPACE>::new(AlignedVec::new());
+        encoder.enable_canonical_layout();
+        let mut document = Document::default();
+        document.insert("b", Value::U64(2));
+        document.insert("a", Value::U64(1));
+        encoder.encode(&document).expect("Encode document");
+        let buf = encoder.finish().expect("Finish segment");
+
+        let decoder = Decoder::open(&buf).expect("Open segment");
+        assert!(decoder.is_canonical());
+        let archived = decoder.checked_archived_at(0).unwrap().unwrap();
+        let canonical = decoder
+            .as_canonical(archived)
+            .expect("Segment should be canonical");
+        assert_eq!(canonical.get_u64("a"), Some(1));
+        assert_eq!(canonical.get_u64("b"), Some(2));
+
+        let buf = encode_segment(&documents(1), RecordLayout::default());
+        let decoder = Decoder::open(&buf).expect("Open segment");
+        assert!(!decoder.is_canonical());
+        let archived = decoder.checked_archived_at(0).unwrap().unwrap();
+        assert!(decoder.as_canonical(archived).is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_segment_configured_after_encoding() {
+        let mut encoder = SegmentEncoder::<_, DEFAULT_SCRATCH_SPACE>::new(Vec::new());
+        encoder
+            .encode(&Document::default())
+            .expect("Encode document");
+        encoder.set_framing(Framing::Bidirectional);
+    }
+
+    #[test]
+    fn test_incompatible_segments() {
+        let buf = encode_segment(&documents(3), RecordLayout::default());
+        let header = SegmentHeader::read(&buf).unwrap();
+
+        let mut incompatible = buf.clone();
+        incompatible[0] = b'X';
+        assert!(matches!(open_error(&incompatible), SegmentError::BadMagic));
+
+        let mut incompatible = buf.clone();
+        incompatible[4] ^= 0xFF;
+        assert!(matches!(
+            open_error(&incompatible),
+            SegmentError::HeaderChecksumMismatch { .. }
+        ));
+
+        let mut incompatible = buf.clone();
+        rewrite_header(
+            &mut incompatible,
+            SegmentHeader {
+                format_version: FORMAT_VERSION + 1,
+                ..header
+            },
+        );
+        assert!(matches!(
+            open_error(&incompatible),
+            SegmentError::UnsupportedFormatVersion { found } if found == FORMAT_VERSION + 1
+        ));
+
+        let mut incompatible = buf.clone();
+        rewrite_header(
+            &mut incompatible,
+            SegmentHeader {
+                layout_version: LAYOUT_VERSION + 1,
+                ..header
+            },
+        );
+        assert!(matches!(
+            open_error(&incompatible),
+            SegmentError::IncompatibleLayout { .. }
+        ));
+
+        let mut incompatible = buf.clone();
+        rewrite_header(
+            &mut incompatible,
+            SegmentHeader {
+                big_endian: !header.big_endian,
+                ..header
+            },
+        );
+        assert!(matches!(
+            open_error(&incompatible),
+            SegmentError::IncompatibleLayout { .. }
+        ));
+
+        let mut incompatible = buf.clone();
+        rewrite_header(
+            &mut incompatible,
+            SegmentHeader {
+                flags: SegmentFlags::from_bits(1 << 31),
+                ..header
+            },
+        );
+        assert!(matches!(
+            open_error(&incompatible),
+            SegmentError::UnknownFlags { bits: 0x8000_0000 }
+        ));
+
+        let mut incompatible = buf.clone();
+        rewrite_header(
+            &mut incompatible,
+            SegmentHeader {
+                alignment: 24,
+                ..header
+            },
+        );
+        assert!(matches!(
+            open_error(&incompatible),
+            SegmentError::InvalidAlignment { alignment: 24 }
+        ));
+
+        let mut incompatible = buf.clone();
+        let mut bytes = header.to_bytes();
+        bytes[18] = 0xFF;
+        let checksum = crc32fast::hash(&bytes[..20]);
+        bytes[20..].copy_from_slice(&checksum.to_le_bytes());
+        incompatible[..SEGMENT_HEADER_SIZE].copy_from_slice(&bytes);
+        assert!(matches!(
+            open_error(&incompatible),
+            SegmentError::UnknownChecksum { id: 0xFF }
+        ));
+    }
+
+    #[test]
+    fn test_corrupt_segments() {
+        let buf = encode_segment(&documents(3), RecordLayout::default());
+
+        let mut corrupt = buf.clone();
+        let header_size = SegmentHeader::read(&buf).unwrap().padded_size();
+        corrupt[header_size] ^= 0xFF;
+        assert!(matches!(
+            open_error(&corrupt),
+            SegmentError::ChecksumMismatch { .. }
+        ));
+
+        let mut corrupt = buf.clone();
+        let len = corrupt.len();
+        corrupt[len - 1] = b'X';
+        assert!(matches!(open_error(&corrupt), SegmentError::BadMagic));
+
+        assert!(matches!(
+            open_error(&buf[..buf.len() - 1]),
+            SegmentError::BadMagic
+        ));
+        assert!(matches!(
+            open_error(&buf[..SEGMENT_HEADER_SIZE]),
+            SegmentError::Truncated { .. }
+        ));
+        assert!(matches!(
+            open_error(&[]),
+            SegmentError::Truncated { len: 0 }
+        ));
+
+        let mut misaligned = AlignedVec::new();
+        misaligned.extend_from_slice(&[0; 8]);
+        misaligned.extend_from_slice(&buf);
+        assert!(matches!(
+            open_error(&misaligned[8..]),
+            SegmentError::MisalignedBuffer { alignment: 16 }
+        ));
+    }
+}
@@ -1,143 +1,409 @@
 use std::fmt;
 
-use serde::de::value::SeqAccessDeserializer;
-use serde::de::{Error, MapAccess, SeqAccess, Visitor};
-use serde::{Deserialize, Deserializer};
-
-use crate::core::{Bytes, Document, Text, Value};
-
-impl<'de> Deserialize<'de> for Value {
-    #[inline]
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        struct ValueVisitor;
-
-        impl<'de> Visitor<'de> for ValueVisitor {
-            type Value = Value;
+use serde::de::{DeserializeSeed, Error, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, SerializeMap};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::array_builder::ArrayBuilder;
+use crate::core::{
+    ArchivedBytes,
+    ArchivedDocument,
+    ArchivedText,
+    ArchivedValue,
+    Bytes,
+    Document,
+    Text,
+    Value,
+};
+use crate::date::{parse_rfc3339, Rfc3339};
+use crate::ingest::IngestConfig;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An error produced while converting between bellini values and other types
+/// using serde.
+pub struct SerdeError(String);
+
+impl fmt::Display for SerdeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
 
-            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
-                formatter
-                    .write_str("a valid JSON object (null, str, int, object, array)")
-            }
+impl std::error::Error for SerdeError {}
 
-            #[inline]
-            fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E>
-            where
-                E: Error,
-            {
-                Ok(Value::Bool(v))
-            }
+impl serde::de::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
 
-            #[inline]
-            fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
-                Ok(Value::I64(v))
-            }
+impl ser::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
 
-            #[inline]
-            fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> {
-                Ok(Value::U64(v))
-            }
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+/// The representation used for date values when serializing.
+pub enum DateFormat {
+    #[default]
+    /// An integer offset from `UNIX_EPOCH` in microseconds.
+    Micros,
+    /// An RFC3339 formatted UTC timestamp string.
+    Rfc3339,
+}
 
-            #[inline]
-            fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> {
-                Ok(Value::F64(v))
-            }
+/// A wrapper which serializes the inner value using the given [DateFormat].
+///
+/// The plain `Serialize` implementations use [DateFormat::Micros].
+pub struct WithDateFormat<'a, T: ?Sized> {
+    value: &'a T,
+    format: DateFormat,
+}
 
-            #[inline]
-            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
-                Ok(Value::String(Text::from(v.to_owned())))
-            }
+impl<'a, T: ?Sized> WithDateFormat<'a, T> {
+    #[inline]
+    /// Wrap a value to be serialized with the given date format.
+    pub fn new(value: &'a T, format: DateFormat) -> Self {
+        Self { value, format }
+    }
+}
 
-            #[inline]
-            fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E> {
-                Ok(Value::String(Text::from(v)))
-            }
+struct Date(i64, DateFormat);
 
-            #[inline]
-            fn visit_string<E>(self, v: String) -> Result<Self::Value, E> {
-                Ok(Value::String(Text::from(v)))
-            }
+impl Serialize for Date {
+    #[inline]
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.1 {
+            DateFormat::Micros => serializer.serialize_i64(self.0),
+            DateFormat::Rfc3339 => {
+                let date = Rfc3339(self.0);
+                if !date.is_representable() {
+                    return Err(ser::Error::custom(format_args!(
+                        "date offset {} is outside of the RFC3339 range",
+                        self.0,
+                    )));
+                }
+                serializer.collect_str(&date)
+            },
+        }
+    }
+}
 
-            #[inline]
-            fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+macro_rules! impl_serialize {
+    ($document:ty, $value:ident, $text:ty, $bytes:ty) => {
+        impl Serialize for WithDateFormat<'_, $value> {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
             where
-                E: Error,
+                S: Serializer,
             {
-                Ok(Value::Bytes(Bytes::from(v.to_owned())))
+                let format = self.format;
+                match self.value {
+                    $value::Null => serializer.serialize_unit(),
+                    $value::Bool(v) => serializer.serialize_bool(*v),
+                    $value::String(v) => v.serialize(serializer),
+                    $value::Bytes(v) => v.serialize(serializer),
+                    $value::U64(v) => serializer.serialize_u64(*v),
+                    $value::I64(v) => serializer.serialize_i64(*v),
+                    $value::F64(v) => serializer.serialize_f64(*v),
+                    $value::Date(v) => Date(*v, format).serialize(serializer),
+                    $value::ArrayBool(values) => serializer.collect_seq(values.iter()),
+                    $value::ArrayString(values) => serializer.collect_seq(values.iter()),
+                    $value::ArrayBytes(values) => serializer.collect_seq(values.iter()),
+                    $value::ArrayU64(values) => serializer.collect_seq(values.iter()),
+                    $value::ArrayI64(values) => serializer.collect_seq(values.iter()),
+                    $value::ArrayF64(values) => serializer.collect_seq(values.iter()),
+                    $value::ArrayDate(values) => {
+                        serializer.collect_seq(values.iter().map(|v| Date(*v, format)))
+                    },
+                    $value::ArrayDynamic(values) => serializer.collect_seq(
+                        values.iter().map(|v| WithDateFormat::new(v, format)),
+                    ),
+                    $value::Object(entries) => {
+                        serialize_entries(entries, format, serializer)
+                    },
+                }
             }
+        }
 
+        impl Serialize for $value {
             #[inline]
-            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
             where
-                E: Error,
+                S: Serializer,
             {
-                Ok(Value::Bytes(Bytes::from(v)))
+                WithDateFormat::new(self, DateFormat::default()).serialize(serializer)
             }
+        }
 
+        /// The document is serialized as a map of its fields, the document ID is
+        /// not a field and is omitted so the output deserializes back into the
+        /// same fields.
+        impl Serialize for WithDateFormat<'_, $document> {
             #[inline]
-            fn visit_none<E>(self) -> Result<Self::Value, E>
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
             where
-                E: Error,
+                S: Serializer,
             {
-                Ok(Value::Null)
+                serialize_entries(self.value.fields(), self.format, serializer)
             }
+        }
 
+        /// The document ID is omitted, see [WithDateFormat].
+        impl Serialize for $document {
             #[inline]
-            fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
             where
-                D: Deserializer<'de>,
+                S: Serializer,
             {
-                Deserialize::deserialize(deserializer)
+                WithDateFormat::new(self, DateFormat::default()).serialize(serializer)
             }
+        }
 
+        impl Serialize for $text {
             #[inline]
-            fn visit_unit<E>(self) -> Result<Self::Value, E>
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
             where
-                E: Error,
+                S: Serializer,
             {
-                Ok(Value::Null)
+                serializer.serialize_str(self.as_ref())
             }
+        }
 
+        impl Serialize for $bytes {
             #[inline]
-            fn visit_seq<V>(self, visitor: V) -> Result<Self::Value, V::Error>
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
             where
-                V: SeqAccess<'de>,
+                S: Serializer,
             {
-                match <TypedVec as Deserialize>::deserialize(SeqAccessDeserializer::new(
-                    visitor,
-                )) {
-                    Ok(TypedVec::String(values)) => Ok(Value::ArrayString(values)),
-                    Ok(TypedVec::U64(values)) => Ok(Value::ArrayU64(values)),
-                    Ok(TypedVec::I64(values)) => Ok(Value::ArrayI64(values)),
-                    Ok(TypedVec::F64(values)) => Ok(Value::ArrayF64(values)),
-                    Ok(TypedVec::Bool(values)) => Ok(Value::ArrayBool(values)),
-                    Ok(TypedVec::Dynamic(values)) => Ok(Value::ArrayDynamic(values)),
-                    Ok(TypedVec::Bytes(values)) => Ok(Value::ArrayBytes(values)),
-                    Err(e) => Err(e),
-                }
+                serializer.serialize_bytes(self.as_ref())
             }
+        }
+    };
+}
 
-            #[inline]
-            fn visit_map<V>(self, mut visitor: V) -> Result<Self::Value, V::Error>
-            where
-                V: MapAccess<'de>,
-            {
-                let mut values = Vec::with_capacity(visitor.size_hint().unwrap_or(0));
+impl_serialize!(Document, Value, Text, Bytes);
+impl_serialize!(ArchivedDocument, ArchivedValue, ArchivedText, ArchivedBytes);
+
+#[inline]
+fn serialize_entries<'a, K, V, S>(
+    entries: &'a [(K, V)],
+    format: DateFormat,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    K: AsRef<str>,
+    WithDateFormat<'a, V>: Serialize,
+    S: Serializer,
+{
+    let mut map = serializer.serialize_map(Some(entries.len()))?;
+    for (key, value) in entries {
+        map.serialize_entry(key.as_ref(), &WithDateFormat::new(value, format))?;
+    }
+    map.end()
+}
 
-                while let Some((key, value)) = visitor.next_entry()? {
-                    values.push((key, value));
-                }
+impl<'de> Deserialize<'de> for Value {
+    #[inline]
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ValueVisitor::new(&IngestConfig::DEFAULT))
+    }
+}
 
-                Ok(Value::Object(values))
-            }
+/// Builds a value, applying the date rules of the ingestion config.
+pub(crate) struct ValueVisitor<'a> {
+    config: &'a IngestConfig,
+    /// If integers should be taken as milliseconds since `UNIX_EPOCH`.
+    epoch_millis: bool,
+}
+
+impl<'a> ValueVisitor<'a> {
+    #[inline]
+    pub(crate) fn new(config: &'a IngestConfig) -> Self {
+        Self {
+            config,
+            epoch_millis: false,
         }
+    }
 
-        deserializer.deserialize_any(ValueVisitor)
+    #[inline]
+    fn with_epoch_millis(config: &'a IngestConfig, epoch_millis: bool) -> Self {
+        Self {
+            config,
+            epoch_millis,
+        }
+    }
+
+    #[inline]
+    fn string(&self, v: &str) -> Option<Value> {
+        if !self.config.detects_dates() {
+            return None;
+        }
+        parse_rfc3339(v).map(Value::Date)
+    }
+}
+
+impl<'a, 'de> DeserializeSeed<'de> for ValueVisitor<'a> {
+    type Value = Value;
+
+    #[inline]
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
     }
 }
 
+impl<'a, 'de> Visitor<'de> for ValueVisitor<'a> {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a valid JSON object (null, str, int, object, array)")
+    }
+
+    #[inline]
+    fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        Ok(Value::Bool(v))
+    }
+
+    #[inline]
+    fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
+        match v.checked_mul(1000) {
+            Some(micros) if self.epoch_millis => Ok(Value::Date(micros)),
+            _ => Ok(Value::I64(v)),
+        }
+    }
+
+    #[inline]
+    fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> {
+        let micros = i64::try_from(v).ok().and_then(|v| v.checked_mul(1000));
+        match micros {
+            Some(micros) if self.epoch_millis => Ok(Value::Date(micros)),
+            _ => Ok(Value::U64(v)),
+        }
+    }
+
+    #[inline]
+    fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(Value::F64(v))
+    }
+
+    #[inline]
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(self
+            .string(v)
+            .unwrap_or_else(|| Value::String(Text::from(v.to_owned()))))
+    }
+
+    #[inline]
+    fn visit_borrowed_str<E>(self, v: &'de str) -> Result<Self::Value, E> {
+        Ok(self
+            .string(v)
+            .unwrap_or_else(|| Value::String(Text::from(v))))
+    }
+
+    #[inline]
+    fn visit_string<E>(self, v: String) -> Result<Self::Value, E> {
+        Ok(self
+            .string(&v)
+            .unwrap_or_else(|| Value::String(Text::from(v))))
+    }
+
+    #[inline]
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        Ok(Value::Bytes(Bytes::from(v.to_owned())))
+    }
+
+    #[inline]
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        Ok(Value::Bytes(Bytes::from(v)))
+    }
+
+    #[inline]
+    fn visit_none<E>(self) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        Ok(Value::Null)
+    }
+
+    #[inline]
+    fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(self)
+    }
+
+    #[inline]
+    fn visit_unit<E>(self) -> Result<Self::Value, E>
+    where
+        E: Error,
+    {
+        Ok(Value::Null)
+    }
+
+    #[inline]
+    fn visit_seq<V>(self, mut visitor: V) -> Result<Self::Value, V::Error>
+    where
+        V: SeqAccess<'de>,
+    {
+        let mut builder = ArrayBuilder::with_capacity(visitor.size_hint().unwrap_or(0));
+
+        let seed = || ValueVisitor::with_epoch_millis(self.config, self.epoch_millis);
+        while let Some(value) = visitor.next_element_seed(seed())? {
+            builder.push(value);
+        }
+
+        Ok(builder.finish())
+    }
+
+    #[inline]
+    fn visit_map<V>(self, visitor: V) -> Result<Self::Value, V::Error>
+    where
+        V: MapAccess<'de>,
+    {
+        visit_entries(self.config, visitor).map(Value::Object)
+    }
+}
+
+/// Collects the entries of a map, treating the values of any configured
+/// epoch millisecond fields as dates.
+pub(crate) fn visit_entries<'de, V>(
+    config: &IngestConfig,
+    mut visitor: V,
+) -> Result<Vec<(Text, Value)>, V::Error>
+where
+    V: MapAccess<'de>,
+{
+    let mut values = Vec::with_capacity(visitor.size_hint().unwrap_or(0));
+
+    while let Some(key) = visitor.next_key::<Text>()? {
+        let epoch_millis = config.is_epoch_millis_field(key.as_ref());
+        let value = visitor
+            .next_value_seed(ValueVisitor::with_epoch_millis(config, epoch_millis))?;
+        values.push((key, value));
+    }
+
+    Ok(values)
+}
+
 impl<'de> Deserialize<'de> for Text {
     #[inline]
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -231,50 +497,96 @@ impl<'de> Deserialize<'de> for Document {
     where
         D: Deserializer<'de>,
     {
-        struct ValuesVisitor;
-
-        impl<'de> Visitor<'de> for ValuesVisitor {
-            type Value = Document;
-
-            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
-                formatter.write_str("a JSON object")
-            }
-
-            #[inline]
-            fn visit_some<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
-            where
-                D: Deserializer<'de>,
-            {
-                Deserialize::deserialize(deserializer)
-            }
+        IngestConfig::DEFAULT
+            .document_seed()
+            .deserialize(deserializer)
+    }
+}
 
-            #[inline]
-            fn visit_map<V>(self, mut visitor: V) -> Result<Self::Value, V::Error>
-            where
-                V: MapAccess<'de>,
-            {
-                let mut values = Vec::with_capacity(visitor.size_hint().unwrap_or(0));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_document() -> Document {
+        let mut document = Document::default();
+        document.insert("name", Value::String(Text::from("bellini")));
+        document.insert("created", Value::Date(1_686_830_400_000_000));
+        document.insert("scores", Value::ArrayI64(vec![-1, 2]));
+        document.insert(
+            "nested",
+            Value::Object(vec![
+                (Text::from("seen"), Value::ArrayDate(vec![0])),
+                (Text::from("any"), Value::ArrayDynamic(vec![Value::Null])),
+            ]),
+        );
+        document
+    }
 
-                while let Some((key, value)) = visitor.next_entry()? {
-                    values.push((key, value));
-                }
+    #[test]
+    fn test_serialize_document() {
+        let document = test_document();
+
+        let json = serde_json::to_string(&document).expect("Serialize document");
+        assert_eq!(
+            json,
+            r#"{"name":"bellini","created":1686830400000000,"scores":[-1,2],"nested":{"seen":[0],"any":[null]}}"#,
+        );
+
+        let json =
+            serde_json::to_string(&WithDateFormat::new(&document, DateFormat::Rfc3339))
+                .expect("Serialize document");
+        assert_eq!(
+            json,
+            r#"{"name":"bellini","created":"2023-06-15T12:00:00Z","scores":[-1,2],"nested":{"seen":["1970-01-01T00:00:00Z"],"any":[null]}}"#,
+        );
+    }
 
-                Ok(Document::from(values))
-            }
-        }
+    #[test]
+    fn test_serialize_document_omits_id() {
+        let mut document = test_document();
+        document.set_id(42);
+
+        let json = serde_json::to_string(&document).expect("Serialize document");
+        let expected =
+            serde_json::to_string(&test_document()).expect("Serialize document");
+        assert_eq!(json, expected, "The document ID should not be serialized");
+
+        let bytes = rkyv::to_bytes::<_, 256>(&document).expect("Serialize document");
+        let archived = rkyv::check_archived_root::<Document>(&bytes)
+            .expect("Archived document should be valid");
+        assert_eq!(archived.id(), 42);
+        let json = serde_json::to_string(archived).expect("Serialize archived document");
+        assert_eq!(json, expected, "The document ID should not be serialized");
+    }
 
-        deserializer.deserialize_map(ValuesVisitor)
+    #[test]
+    fn test_serialize_archived_document() {
+        let document = test_document();
+        let bytes = rkyv::to_bytes::<_, 256>(&document).expect("Serialize document");
+        let archived = rkyv::check_archived_root::<Document>(&bytes)
+            .expect("Archived document should be valid");
+
+        let expected = serde_json::to_string(&document).expect("Serialize document");
+        let json = serde_json::to_string(archived).expect("Serialize archived document");
+        assert_eq!(json, expected, "Owned and archived output should match");
+
+        let expected =
+            serde_json::to_string(&WithDateFormat::new(&document, DateFormat::Rfc3339))
+                .expect("Serialize document");
+        let json =
+            serde_json::to_string(&WithDateFormat::new(archived, DateFormat::Rfc3339))
+                .expect("Serialize archived document");
+        assert_eq!(json, expected, "Owned and archived output should match");
     }
-}
 
-#[derive(Deserialize)]
-#[serde(untagged)] // This sucks, but we cant really do anything about it.
-pub enum TypedVec {
-    String(Vec<Text>),
-    U64(Vec<u64>),
-    I64(Vec<i64>),
-    F64(Vec<f64>),
-    Bool(Vec<bool>),
-    Bytes(Vec<Bytes>),
-    Dynamic(Vec<Value>),
+    #[test]
+    fn test_serialize_out_of_range_date() {
+        let value = Value::Date(i64::MAX);
+        assert!(serde_json::to_string(&value).is_ok());
+        assert!(
+            serde_json::to_string(&WithDateFormat::new(&value, DateFormat::Rfc3339))
+                .is_err(),
+            "Dates outside of RFC3339 should be rejected"
+        );
+    }
 }
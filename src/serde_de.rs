@@ -0,0 +1,405 @@
+//! A serde deserializer over archived documents
+//!
+//! This allows user types to be deserialized straight out of the archive
+//! buffer, strings and bytes are borrowed from the buffer rather than copied.
+
+use serde::de::value::BorrowedStrDeserializer;
+use serde::de::{
+    self,
+    DeserializeSeed,
+    EnumAccess,
+    IntoDeserializer,
+    MapAccess,
+    SeqAccess,
+    Unexpected,
+    VariantAccess,
+    Visitor,
+};
+use serde::{forward_to_deserialize_any, Deserialize, Deserializer};
+
+use crate::core::{ArchivedDocument, ArchivedText, ArchivedValue};
+use crate::path::ArchivedValueRef;
+use crate::serde_compat::SerdeError;
+
+/// Deserialize a type from an archived document without first deserializing
+/// the document itself.
+///
+/// Date values are produced as their microsecond offsets.
+pub fn from_archived<'a, T>(document: &'a ArchivedDocument) -> Result<T, SerdeError>
+where
+    T: Deserialize<'a>,
+{
+    T::deserialize(ArchivedValueRef::Document(document))
+}
+
+/// Deserialize a type from an archived value.
+pub fn from_archived_value<'a, T>(value: &'a ArchivedValue) -> Result<T, SerdeError>
+where
+    T: Deserialize<'a>,
+{
+    T::deserialize(ArchivedValueRef::Value(value))
+}
+
+impl<'de> IntoDeserializer<'de, SerdeError> for ArchivedValueRef<'de> {
+    type Deserializer = Self;
+
+    #[inline]
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for ArchivedValueRef<'de> {
+    type Error = SerdeError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        let value = match self {
+            ArchivedValueRef::Document(document) => {
+                return visitor.visit_map(EntriesAccess::new(document.fields()))
+            },
+            ArchivedValueRef::Value(value) => value,
+            ArchivedValueRef::Bool(v) => return visitor.visit_bool(v),
+            ArchivedValueRef::String(v) => {
+                return visitor.visit_borrowed_str(v.as_ref())
+            },
+            ArchivedValueRef::Bytes(v) => {
+                return visitor.visit_borrowed_bytes(v.as_ref())
+            },
+            ArchivedValueRef::U64(v) => return visitor.visit_u64(v),
+            ArchivedValueRef::I64(v) | ArchivedValueRef::Date(v) => {
+                return visitor.visit_i64(v)
+            },
+            ArchivedValueRef::F64(v) => return visitor.visit_f64(v),
+        };
+
+        match value {
+            ArchivedValue::Null => visitor.visit_unit(),
+            ArchivedValue::Bool(v) => visitor.visit_bool(*v),
+            ArchivedValue::String(v) => visitor.visit_borrowed_str(v.as_ref()),
+            ArchivedValue::Bytes(v) => visitor.visit_borrowed_bytes(v.as_ref()),
+            ArchivedValue::U64(v) => visitor.visit_u64(*v),
+            ArchivedValue::I64(v) | ArchivedValue::Date(v) => visitor.visit_i64(*v),
+            ArchivedValue::F64(v) => visitor.visit_f64(*v),
+            ArchivedValue::Object(entries) => {
+                visitor.visit_map(EntriesAccess::new(entries))
+            },
+            array => visitor.visit_seq(ArrayAccess::new(array)),
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            ArchivedValueRef::Value(ArchivedValue::Null) => visitor.visit_none(),
+            other => visitor.visit_some(other),
+        }
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            ArchivedValueRef::String(variant) => {
+                visitor.visit_enum(variant.as_ref().into_deserializer())
+            },
+            ArchivedValueRef::Value(ArchivedValue::String(variant)) => {
+                visitor.visit_enum(variant.as_ref().into_deserializer())
+            },
+            ArchivedValueRef::Value(ArchivedValue::Object(entries))
+                if entries.len() == 1 =>
+            {
+                let (variant, value) = &entries[0];
+                visitor.visit_enum(VariantDeserializer { variant, value })
+            },
+            other => Err(de::Error::invalid_type(
+                Unexpected::Other(other.as_type()),
+                &"a string or an object with a single key",
+            )),
+        }
+    }
+
+    #[inline]
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier
+    }
+}
+
+/// Walks the elements of any of the array variants.
+struct ArrayAccess<'de> {
+    array: &'de ArchivedValue,
+    index: usize,
+}
+
+impl<'de> ArrayAccess<'de> {
+    fn new(array: &'de ArchivedValue) -> Self {
+        Self { array, index: 0 }
+    }
+}
+
+impl<'de> SeqAccess<'de> for ArrayAccess<'de> {
+    type Error = SerdeError;
+
+    #[inline]
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        let element = match self.array.array_element(self.index) {
+            None => return Ok(None),
+            Some(element) => element,
+        };
+        self.index += 1;
+        seed.deserialize(element).map(Some)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> Option<usize> {
+        self.array.array_len().map(|len| len - self.index)
+    }
+}
+
+/// Walks the entries of a document or object.
+struct EntriesAccess<'de> {
+    entries: std::slice::Iter<'de, (ArchivedText, ArchivedValue)>,
+    value: Option<&'de ArchivedValue>,
+}
+
+impl<'de> EntriesAccess<'de> {
+    fn new(entries: &'de [(ArchivedText, ArchivedValue)]) -> Self {
+        Self {
+            entries: entries.iter(),
+            value: None,
+        }
+    }
+}
+
+impl<'de> MapAccess<'de> for EntriesAccess<'de> {
+    type Error = SerdeError;
+
+    #[inline]
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        let (key, value) = match self.entries.next() {
+            None => return Ok(None),
+            Some(entry) => entry,
+        };
+        self.value = Some(value);
+        seed.deserialize(BorrowedStrDeserializer::new(key.as_ref()))
+            .map(Some)
+    }
+
+    #[inline]
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| de::Error::custom("value requested before key"))?;
+        seed.deserialize(ArchivedValueRef::Value(value))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+/// An externally tagged enum variant stored as a single entry object.
+struct VariantDeserializer<'de> {
+    variant: &'de ArchivedText,
+    value: &'de ArchivedValue,
+}
+
+impl<'de> EnumAccess<'de> for VariantDeserializer<'de> {
+    type Error = SerdeError;
+    type Variant = ArchivedValueRef<'de>;
+
+    #[inline]
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant =
+            seed.deserialize(BorrowedStrDeserializer::new(self.variant.as_ref()))?;
+        Ok((variant, ArchivedValueRef::Value(self.value)))
+    }
+}
+
+impl<'de> VariantAccess<'de> for ArchivedValueRef<'de> {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_map(visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::{Bytes, Document, Text, Value};
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Product<'a> {
+        name: &'a str,
+        tags: Vec<&'a str>,
+        #[serde(borrow)]
+        blob: &'a [u8],
+        price: f64,
+        stock: Option<u32>,
+        sizes: Vec<i32>,
+        kind: Kind,
+        shipping: Shipping,
+        extra: BTreeMap<&'a str, serde_json::Value>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Kind {
+        Physical,
+        Digital,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    enum Shipping {
+        Weight(u64),
+        Box { width: u64, height: u64 },
+    }
+
+    #[test]
+    fn test_from_archived() {
+        let mut document = Document::default();
+        document.insert("name", Value::String(Text::from("Bellini")));
+        document.insert(
+            "tags",
+            Value::ArrayString(vec![Text::from("a"), Text::from("b")]),
+        );
+        document.insert("blob", Value::Bytes(Bytes::from(vec![1, 2, 3])));
+        document.insert("price", Value::U64(12));
+        document.insert("stock", Value::Null);
+        document.insert("sizes", Value::ArrayI64(vec![-1, 2]));
+        document.insert("kind", Value::String(Text::from("Digital")));
+        document.insert(
+            "shipping",
+            Value::Object(vec![(
+                Text::from("Box"),
+                Value::Object(vec![
+                    (Text::from("width"), Value::U64(2)),
+                    (Text::from("height"), Value::U64(3)),
+                ]),
+            )]),
+        );
+        document.insert(
+            "extra",
+            Value::Object(vec![(
+                Text::from("seen"),
+                Value::ArrayDynamic(vec![Value::Bool(true), Value::Date(5)]),
+            )]),
+        );
+
+        let bytes = rkyv::to_bytes::<_, 256>(&document).expect("Serialize document");
+        let archived = rkyv::check_archived_root::<Document>(&bytes)
+            .expect("Archived document should be valid");
+
+        let product: Product = from_archived(archived).expect("Deserialize product");
+        assert_eq!(
+            product,
+            Product {
+                name: "Bellini",
+                tags: vec!["a", "b"],
+                blob: &[1, 2, 3],
+                price: 12.0,
+                stock: None,
+                sizes: vec![-1, 2],
+                kind: Kind::Digital,
+                shipping: Shipping::Box {
+                    width: 2,
+                    height: 3
+                },
+                extra: BTreeMap::from_iter([("seen", serde_json::json!([true, 5]))]),
+            }
+        );
+
+        let name_ptr = archived.get_str("name").unwrap().as_ptr();
+        assert_eq!(
+            product.name.as_ptr(),
+            name_ptr,
+            "Strings should be borrowed"
+        );
+
+        let shipping: Shipping = from_archived_value(archived.get("shipping").unwrap())
+            .expect("Deserialize shipping");
+        assert_eq!(
+            shipping,
+            Shipping::Box {
+                width: 2,
+                height: 3
+            }
+        );
+
+        let err = from_archived_value::<Shipping>(archived.get("price").unwrap());
+        assert!(err.is_err(), "Numbers cannot be deserialized as an enum");
+        let weight: Result<Shipping, _> =
+            from_archived_value(archived.get("extra").unwrap().get("seen").unwrap());
+        assert!(weight.is_err(), "Arrays cannot be deserialized as an enum");
+    }
+}
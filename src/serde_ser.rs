@@ -0,0 +1,711 @@
+//! A serde serializer which builds documents and values
+//!
+//! This converts any `Serialize` type directly into bellini's value model,
+//! keeping the integer signedness of the source type and producing the
+//! specialised array variants for sequences of a single primitive type.
+
+use serde::ser::{self, Impossible, Serialize};
+
+use crate::array_builder::ArrayBuilder;
+use crate::core::{Bytes, Document, Text, Value};
+use crate::serde_compat::SerdeError;
+
+/// Serialize a struct or map into a document.
+pub fn to_document<T>(value: &T) -> Result<Document, SerdeError>
+where
+    T: Serialize + ?Sized,
+{
+    match value.serialize(ValueSerializer)? {
+        Value::Object(entries) => Ok(Document::from(entries)),
+        other => Err(ser::Error::custom(format_args!(
+            "expected a struct or map to produce a document, found {}",
+            other.as_type(),
+        ))),
+    }
+}
+
+/// Serialize any type into a value.
+pub fn to_value<T>(value: &T) -> Result<Value, SerdeError>
+where
+    T: Serialize + ?Sized,
+{
+    value.serialize(ValueSerializer)
+}
+
+/// A serializer producing a [Value].
+pub struct ValueSerializer;
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = SerdeError;
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeVariant<SerializeArray>;
+    type SerializeMap = SerializeObject;
+    type SerializeStruct = SerializeObject;
+    type SerializeStructVariant = SerializeVariant<SerializeObject>;
+
+    #[inline]
+    fn serialize_bool(self, v: bool) -> Result<Value, SerdeError> {
+        Ok(Value::Bool(v))
+    }
+
+    #[inline]
+    fn serialize_i8(self, v: i8) -> Result<Value, SerdeError> {
+        Ok(Value::I64(v as i64))
+    }
+
+    #[inline]
+    fn serialize_i16(self, v: i16) -> Result<Value, SerdeError> {
+        Ok(Value::I64(v as i64))
+    }
+
+    #[inline]
+    fn serialize_i32(self, v: i32) -> Result<Value, SerdeError> {
+        Ok(Value::I64(v as i64))
+    }
+
+    #[inline]
+    fn serialize_i64(self, v: i64) -> Result<Value, SerdeError> {
+        Ok(Value::I64(v))
+    }
+
+    #[inline]
+    fn serialize_i128(self, v: i128) -> Result<Value, SerdeError> {
+        i64::try_from(v)
+            .map(Value::I64)
+            .map_err(|_| ser::Error::custom(format_args!("{v} does not fit into a i64")))
+    }
+
+    #[inline]
+    fn serialize_u8(self, v: u8) -> Result<Value, SerdeError> {
+        Ok(Value::U64(v as u64))
+    }
+
+    #[inline]
+    fn serialize_u16(self, v: u16) -> Result<Value, SerdeError> {
+        Ok(Value::U64(v as u64))
+    }
+
+    #[inline]
+    fn serialize_u32(self, v: u32) -> Result<Value, SerdeError> {
+        Ok(Value::U64(v as u64))
+    }
+
+    #[inline]
+    fn serialize_u64(self, v: u64) -> Result<Value, SerdeError> {
+        Ok(Value::U64(v))
+    }
+
+    #[inline]
+    fn serialize_u128(self, v: u128) -> Result<Value, SerdeError> {
+        u64::try_from(v)
+            .map(Value::U64)
+            .map_err(|_| ser::Error::custom(format_args!("{v} does not fit into a u64")))
+    }
+
+    #[inline]
+    fn serialize_f32(self, v: f32) -> Result<Value, SerdeError> {
+        Ok(Value::F64(v as f64))
+    }
+
+    #[inline]
+    fn serialize_f64(self, v: f64) -> Result<Value, SerdeError> {
+        Ok(Value::F64(v))
+    }
+
+    #[inline]
+    fn serialize_char(self, v: char) -> Result<Value, SerdeError> {
+        Ok(Value::String(Text::from(v.to_string())))
+    }
+
+    #[inline]
+    fn serialize_str(self, v: &str) -> Result<Value, SerdeError> {
+        Ok(Value::String(Text::from(v)))
+    }
+
+    #[inline]
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, SerdeError> {
+        Ok(Value::Bytes(Bytes::from(v.to_vec())))
+    }
+
+    #[inline]
+    fn serialize_none(self) -> Result<Value, SerdeError> {
+        Ok(Value::Null)
+    }
+
+    #[inline]
+    fn serialize_some<T>(self, value: &T) -> Result<Value, SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    #[inline]
+    fn serialize_unit(self) -> Result<Value, SerdeError> {
+        Ok(Value::Null)
+    }
+
+    #[inline]
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, SerdeError> {
+        Ok(Value::Null)
+    }
+
+    #[inline]
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Value, SerdeError> {
+        Ok(Value::String(Text::from(variant)))
+    }
+
+    #[inline]
+    fn serialize_newtype_struct<T>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value, SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    #[inline]
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        let value = value.serialize(self)?;
+        Ok(Value::Object(vec![(Text::from(variant), value)]))
+    }
+
+    #[inline]
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeArray, SerdeError> {
+        Ok(SerializeArray {
+            builder: ArrayBuilder::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    #[inline]
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    #[inline]
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    #[inline]
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, SerdeError> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    #[inline]
+    fn serialize_map(self, len: Option<usize>) -> Result<SerializeObject, SerdeError> {
+        Ok(SerializeObject {
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+
+    #[inline]
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeObject, SerdeError> {
+        self.serialize_map(Some(len))
+    }
+
+    #[inline]
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, SerdeError> {
+        Ok(SerializeVariant {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+/// Builds a typed array from a sequence or tuple.
+pub struct SerializeArray {
+    builder: ArrayBuilder,
+}
+
+impl ser::SerializeSeq for SerializeArray {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    #[inline]
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.builder.push(value.serialize(ValueSerializer)?);
+        Ok(())
+    }
+
+    #[inline]
+    fn end(self) -> Result<Value, SerdeError> {
+        Ok(self.builder.finish())
+    }
+}
+
+impl ser::SerializeTuple for SerializeArray {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    #[inline]
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    #[inline]
+    fn end(self) -> Result<Value, SerdeError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeArray {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    #[inline]
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    #[inline]
+    fn end(self) -> Result<Value, SerdeError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+/// Builds an object from a map or struct.
+pub struct SerializeObject {
+    entries: Vec<(Text, Value)>,
+    key: Option<Text>,
+}
+
+impl ser::SerializeMap for SerializeObject {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    #[inline]
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    #[inline]
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| ser::Error::custom("value serialized before key"))?;
+        self.entries.push((key, value.serialize(ValueSerializer)?));
+        Ok(())
+    }
+
+    #[inline]
+    fn end(self) -> Result<Value, SerdeError> {
+        Ok(Value::Object(self.entries))
+    }
+}
+
+impl ser::SerializeStruct for SerializeObject {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    #[inline]
+    fn serialize_field<T>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.entries
+            .push((Text::from(key), value.serialize(ValueSerializer)?));
+        Ok(())
+    }
+
+    #[inline]
+    fn end(self) -> Result<Value, SerdeError> {
+        Ok(Value::Object(self.entries))
+    }
+}
+
+/// Wraps the value of an enum variant in a single entry object.
+pub struct SerializeVariant<T> {
+    variant: &'static str,
+    inner: T,
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeArray> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    #[inline]
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        ser::SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    #[inline]
+    fn end(self) -> Result<Value, SerdeError> {
+        let value = ser::SerializeSeq::end(self.inner)?;
+        Ok(Value::Object(vec![(Text::from(self.variant), value)]))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeObject> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    #[inline]
+    fn serialize_field<T>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    #[inline]
+    fn end(self) -> Result<Value, SerdeError> {
+        let value = ser::SerializeStruct::end(self.inner)?;
+        Ok(Value::Object(vec![(Text::from(self.variant), value)]))
+    }
+}
+
+/// Serializes map keys, only strings and primitives which can be displayed
+/// as a string are supported.
+struct KeySerializer;
+
+macro_rules! serialize_key_display {
+    ($($method:ident => $t:ty),* $(,)?) => {
+        $(
+            #[inline]
+            fn $method(self, v: $t) -> Result<Text, SerdeError> {
+                Ok(Text::from(v.to_string()))
+            }
+        )*
+    };
+}
+
+impl ser::Serializer for KeySerializer {
+    type Ok = Text;
+    type Error = SerdeError;
+    type SerializeSeq = Impossible<Text, SerdeError>;
+    type SerializeTuple = Impossible<Text, SerdeError>;
+    type SerializeTupleStruct = Impossible<Text, SerdeError>;
+    type SerializeTupleVariant = Impossible<Text, SerdeError>;
+    type SerializeMap = Impossible<Text, SerdeError>;
+    type SerializeStruct = Impossible<Text, SerdeError>;
+    type SerializeStructVariant = Impossible<Text, SerdeError>;
+
+    serialize_key_display! {
+        serialize_bool => bool,
+        serialize_i8 => i8,
+        serialize_i16 => i16,
+        serialize_i32 => i32,
+        serialize_i64 => i64,
+        serialize_i128 => i128,
+        serialize_u8 => u8,
+        serialize_u16 => u16,
+        serialize_u32 => u32,
+        serialize_u64 => u64,
+        serialize_u128 => u128,
+        serialize_char => char,
+    }
+
+    #[inline]
+    fn serialize_str(self, v: &str) -> Result<Text, SerdeError> {
+        Ok(Text::from(v))
+    }
+
+    #[inline]
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Text, SerdeError> {
+        Ok(Text::from(variant))
+    }
+
+    #[inline]
+    fn serialize_newtype_struct<T>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Text, SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Text, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Text, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Text, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_none(self) -> Result<Text, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_some<T>(self, _value: &T) -> Result<Text, SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_unit(self) -> Result<Text, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Text, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Text, SerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_seq(
+        self,
+        _len: Option<usize>,
+    ) -> Result<Self::SerializeSeq, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_map(
+        self,
+        _len: Option<usize>,
+    ) -> Result<Self::SerializeMap, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+}
+
+fn key_must_be_a_string() -> SerdeError {
+    ser::Error::custom("map keys must be a string")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::Serialize;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Product<'a> {
+        name: &'a str,
+        tags: Vec<&'a str>,
+        sizes: Vec<i32>,
+        stock: u32,
+        weights: [f32; 2],
+        flags: (bool, bool),
+        mixed: (u8, &'a str),
+        missing: Option<u64>,
+        kind: Kind,
+        shipping: Shipping,
+        counts: BTreeMap<u32, u64>,
+    }
+
+    #[derive(Serialize)]
+    enum Kind {
+        Digital,
+    }
+
+    #[derive(Serialize)]
+    enum Shipping {
+        Box { width: u64 },
+    }
+
+    #[test]
+    fn test_to_document() {
+        let product = Product {
+            name: "Bellini",
+            tags: vec!["a", "b"],
+            sizes: vec![1, 2],
+            stock: 3,
+            weights: [1.5, 2.5],
+            flags: (true, false),
+            mixed: (1, "a"),
+            missing: None,
+            kind: Kind::Digital,
+            shipping: Shipping::Box { width: 4 },
+            counts: BTreeMap::from_iter([(1, 2)]),
+        };
+
+        let document = to_document(&product).expect("Serialize document");
+        assert_eq!(
+            document.fields(),
+            &[
+                (Text::from("name"), Value::String(Text::from("Bellini"))),
+                (
+                    Text::from("tags"),
+                    Value::ArrayString(vec![Text::from("a"), Text::from("b")])
+                ),
+                (Text::from("sizes"), Value::ArrayI64(vec![1, 2])),
+                (Text::from("stock"), Value::U64(3)),
+                (Text::from("weights"), Value::ArrayF64(vec![1.5, 2.5])),
+                (Text::from("flags"), Value::ArrayBool(vec![true, false])),
+                (
+                    Text::from("mixed"),
+                    Value::ArrayDynamic(vec![
+                        Value::U64(1),
+                        Value::String(Text::from("a"))
+                    ])
+                ),
+                (Text::from("missing"), Value::Null),
+                (Text::from("kind"), Value::String(Text::from("Digital"))),
+                (
+                    Text::from("shipping"),
+                    Value::Object(vec![(
+                        Text::from("Box"),
+                        Value::Object(vec![(Text::from("width"), Value::U64(4))])
+                    )])
+                ),
+                (
+                    Text::from("counts"),
+                    Value::Object(vec![(Text::from("1"), Value::U64(2))])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_value() {
+        assert_eq!(to_value(&-1i8).unwrap(), Value::I64(-1));
+        assert_eq!(
+            to_value(&Vec::<u64>::new()).unwrap(),
+            Value::ArrayDynamic(vec![])
+        );
+        assert_eq!(
+            to_value(&vec![vec![1u64], vec![2]]).unwrap(),
+            Value::ArrayDynamic(vec![
+                Value::ArrayU64(vec![1]),
+                Value::ArrayU64(vec![2])
+            ])
+        );
+        assert!(to_value(&u128::MAX).is_err());
+        assert!(
+            to_document(&1u64).is_err(),
+            "Documents must be a struct or map"
+        );
+        assert!(
+            to_value(&BTreeMap::from_iter([(vec![1u8], 1u64)])).is_err(),
+            "Map keys must be strings"
+        );
+    }
+}
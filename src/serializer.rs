@@ -1,11 +1,16 @@
 use std::alloc::Layout;
 use std::error::Error;
+use std::fmt;
 use std::ptr::NonNull;
-use std::{fmt, io};
 
-use rkyv::ser::serializers::{BufferScratch, SharedSerializeMap};
+use rkyv::ser::serializers::{
+    AllocScratch,
+    AllocScratchError,
+    BufferScratch,
+    SharedSerializeMap,
+};
 use rkyv::ser::{ScratchSpace, Serializer, SharedSerializeRegistry};
-use rkyv::{AlignedBytes, Archive, ArchiveUnsized, Fallible, Infallible};
+use rkyv::{AlignedBytes, AlignedVec, Archive, ArchiveUnsized, Fallible, Infallible};
 
 /// The default serializer error.
 #[derive(Debug)]
@@ -13,7 +18,7 @@ pub enum BelliniSerializerError<const N: usize, S> {
     /// An error occurred while serializing
     SerializerError(S),
     /// An error occurred while using scratch space
-    ScratchSpaceError(<StackScratch<N> as Fallible>::Error),
+    ScratchSpaceError(<HeapFallbackScratch<N> as Fallible>::Error),
     /// An error occurred while serializing shared memory
     SharedError(<SharedSerializeMap as Fallible>::Error),
 }
@@ -48,7 +53,7 @@ where
 #[derive(Debug)]
 pub struct BelliniSerializer<const N: usize, S = Infallible> {
     serializer: S,
-    scratch: StackScratch<N>,
+    scratch: HeapFallbackScratch<N>,
     shared: SharedSerializeMap,
 }
 
@@ -58,7 +63,7 @@ impl<const N: usize, S> BelliniSerializer<N, S> {
     pub fn new(serializer: S) -> Self {
         Self {
             serializer,
-            scratch: StackScratch::new(),
+            scratch: HeapFallbackScratch::new(),
             shared: SharedSerializeMap::new(),
         }
     }
@@ -75,8 +80,24 @@ impl<const N: usize, S> BelliniSerializer<N, S> {
         &self.serializer
     }
 
-    pub fn into_inner_serializer(self) -> S {
-        self.serializer
+    #[inline]
+    /// Returns a reference to the scratch space.
+    pub fn scratch(&self) -> &HeapFallbackScratch<N> {
+        &self.scratch
+    }
+
+    #[inline]
+    /// Returns a mutable reference to the scratch space.
+    pub fn scratch_mut(&mut self) -> &mut HeapFallbackScratch<N> {
+        &mut self.scratch
+    }
+
+    #[inline]
+    /// Releases any scratch space and shared pointers left behind by a
+    /// previous, possibly failed, serialization.
+    pub fn reset(&mut self) {
+        self.scratch.clear();
+        self.shared = SharedSerializeMap::new();
     }
 }
 
@@ -85,7 +106,7 @@ impl<S: Default, const N: usize> Default for BelliniSerializer<N, S> {
     fn default() -> Self {
         Self {
             serializer: S::default(),
-            scratch: StackScratch::new(),
+            scratch: HeapFallbackScratch::new(),
             shared: SharedSerializeMap::new(),
         }
     }
@@ -207,6 +228,15 @@ impl<const N: usize> StackScratch<N> {
             inner: BufferScratch::new(AlignedBytes::default()),
         }
     }
+
+    #[inline]
+    /// Releases all of the scratch space.
+    ///
+    /// The buffer scratch caches a pointer to the stack buffer on first use,
+    /// so it is recreated rather than cleared in case we have since been moved.
+    pub fn clear(&mut self) {
+        self.inner = BufferScratch::new(AlignedBytes::default());
+    }
 }
 
 impl<const N: usize> Default for StackScratch<N> {
@@ -238,84 +268,199 @@ impl<const N: usize> ScratchSpace for StackScratch<N> {
     }
 }
 
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+/// Statistics on how often the stack scratch space overflowed onto the heap.
+pub struct ScratchStats {
+    /// The number of scratch allocations which did not fit on the stack.
+    pub spills: u64,
+    /// The total number of bytes allocated on the heap.
+    pub spilled_bytes: u64,
+    /// The size of the largest heap allocation in bytes.
+    pub largest_spill: usize,
+}
+
 #[derive(Debug)]
-pub(crate) struct BelliniWriteSerializer<W: io::Write> {
-    inner: W,
-    pos: usize,
+/// A scratch space using a fixed size stack buffer first and falling
+/// back to heap allocations once it is exhausted.
+///
+/// Once an allocation has spilled onto the heap all further allocations
+/// also go to the heap until the spilled allocations have been popped,
+/// this keeps the pushes and pops of each scratch space in reverse order.
+pub struct HeapFallbackScratch<const N: usize> {
+    stack: StackScratch<N>,
+    heap: AllocScratch,
+    heap_limit: Option<usize>,
+    heap_in_use: usize,
+    heap_depth: usize,
+    stats: ScratchStats,
+}
+
+impl<const N: usize> HeapFallbackScratch<N> {
+    /// Creates a new scratch space with no limit on heap allocations.
+    pub fn new() -> Self {
+        Self {
+            stack: StackScratch::new(),
+            heap: AllocScratch::new(),
+            heap_limit: None,
+            heap_in_use: 0,
+            heap_depth: 0,
+            stats: ScratchStats::default(),
+        }
+    }
+
+    /// Limits the number of bytes which can be in use on the heap at once.
+    pub fn set_heap_limit(&mut self, limit: Option<usize>) {
+        self.heap_limit = limit;
+    }
+
+    #[inline]
+    /// Returns the spill statistics collected since creation.
+    pub fn stats(&self) -> ScratchStats {
+        self.stats
+    }
+
+    #[inline]
+    /// Releases all of the scratch space, keeping the statistics.
+    pub fn clear(&mut self) {
+        self.stack.clear();
+        // Dropping the old allocator frees anything left by a failed serialization.
+        self.heap = AllocScratch::new();
+        self.heap_in_use = 0;
+        self.heap_depth = 0;
+    }
+}
+
+impl<const N: usize> Default for HeapFallbackScratch<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Fallible for HeapFallbackScratch<N> {
+    type Error = AllocScratchError;
 }
 
-impl<W: io::Write> BelliniWriteSerializer<W> {
-    /// Creates a new serializer from a writer.
+impl<const N: usize> ScratchSpace for HeapFallbackScratch<N> {
     #[inline]
-    pub(crate) fn new(inner: W) -> Self {
-        Self { inner, pos: 0 }
+    unsafe fn push_scratch(
+        &mut self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, Self::Error> {
+        if self.heap_depth == 0 {
+            if let Ok(ptr) = self.stack.push_scratch(layout) {
+                return Ok(ptr);
+            }
+        }
+
+        if let Some(limit) = self.heap_limit {
+            let remaining = limit.saturating_sub(self.heap_in_use);
+            if remaining < layout.size() {
+                return Err(AllocScratchError::ExceededLimit {
+                    requested: layout.size(),
+                    remaining,
+                });
+            }
+        }
+
+        let ptr = self.heap.push_scratch(layout)?;
+        self.heap_in_use += layout.size();
+        self.heap_depth += 1;
+        self.stats.spills += 1;
+        self.stats.spilled_bytes += layout.size() as u64;
+        self.stats.largest_spill = self.stats.largest_spill.max(layout.size());
+        Ok(ptr)
     }
 
     #[inline]
-    /// Returns a mutable reference to the inner writer.
-    pub(crate) fn writer_mut(&mut self) -> &mut W {
-        &mut self.inner
+    unsafe fn pop_scratch(
+        &mut self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+    ) -> Result<(), Self::Error> {
+        if self.heap_depth == 0 {
+            return self
+                .stack
+                .pop_scratch(ptr, layout)
+                .map_err(|_| AllocScratchError::NoAllocationsToPop);
+        }
+
+        self.heap.pop_scratch(ptr, layout)?;
+        self.heap_in_use -= layout.size();
+        self.heap_depth -= 1;
+        Ok(())
     }
+}
+
+#[derive(Debug, Default)]
+/// A serializer staging a single document in a reusable aligned buffer.
+///
+/// Positions start from the beginning of the buffer, so every document is
+/// serialized as if it were at the start of its own archive.
+pub(crate) struct BelliniBufferSerializer {
+    buffer: AlignedVec,
+}
 
+impl BelliniBufferSerializer {
     #[inline]
-    /// Returns a reference to the inner writer.
-    pub(crate) fn writer(&self) -> &W {
-        &self.inner
+    /// Returns the staged bytes.
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        self.buffer.as_slice()
     }
 
     #[inline]
-    /// Returns the inner writer
-    pub(crate) fn into_inner(self) -> W {
-        self.inner
+    /// Discards the staged bytes while keeping the allocation.
+    pub(crate) fn clear(&mut self) {
+        self.buffer.clear();
     }
 }
 
-impl<W: io::Write> Fallible for BelliniWriteSerializer<W> {
-    type Error = io::Error;
+impl Fallible for BelliniBufferSerializer {
+    type Error = std::convert::Infallible;
 }
 
-impl<W: io::Write> Serializer for BelliniWriteSerializer<W> {
+impl Serializer for BelliniBufferSerializer {
     #[inline]
     fn pos(&self) -> usize {
-        self.pos
+        self.buffer.len()
     }
 
     #[inline]
     fn write(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
-        self.inner.write_all(bytes)?;
-        self.pos += bytes.len();
+        self.buffer.extend_from_slice(bytes);
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::Write;
-
-    use rkyv::ser::serializers::{AlignedSerializer, AllocScratch, CompositeSerializer};
-    use rkyv::AlignedVec;
+    use rkyv::ser::serializers::{AllocScratch, CompositeSerializer};
 
     use super::*;
 
     #[test]
     fn test_bellini_serializer() {
         let mut serializer =
-            BelliniSerializer::<64, _>::new(AlignedSerializer::new(AlignedVec::new()));
+            BelliniSerializer::<64, _>::new(BelliniBufferSerializer::default());
 
         let msg = "Hello, world!".to_string();
         let pos = serializer.serialize_value(&msg).expect("Serialize string");
 
         assert_eq!(pos, 16, "Position start should be 0");
-        let data = serializer.into_inner_serializer().into_inner();
+        let data = serializer.inner().as_slice();
 
-        let msg_returned = rkyv::from_bytes::<String>(&data).expect("Deserialize type.");
+        let msg_returned = rkyv::from_bytes::<String>(data).expect("Deserialize type.");
         assert_eq!(msg, msg_returned);
+
+        serializer.reset();
+        serializer.inner_mut().clear();
+        let pos = serializer.serialize_value(&msg).expect("Serialize string");
+        assert_eq!(pos, 16, "Reset serializer should start from 0 again");
     }
 
     #[test]
-    fn test_bellini_writer_serializer() {
+    fn test_bellini_buffer_serializer() {
         let mut serializer = CompositeSerializer::new(
-            BelliniWriteSerializer::new(AlignedVec::new()),
+            BelliniBufferSerializer::default(),
             AllocScratch::new(),
             SharedSerializeMap::new(),
         );
@@ -325,10 +470,64 @@ mod tests {
 
         assert_eq!(pos, 16, "Position start should be 0");
         let mut serializer = serializer.into_serializer();
-        serializer.writer_mut().flush().expect("Flush data");
-        let data = serializer.into_inner();
-
-        let msg_returned = rkyv::from_bytes::<String>(&data).expect("Deserialize type.");
+        let msg_returned = rkyv::from_bytes::<String>(serializer.as_slice())
+            .expect("Deserialize type.");
         assert_eq!(msg, msg_returned);
+
+        serializer.clear();
+        assert_eq!(serializer.pos(), 0, "Clearing should reset the position");
+    }
+
+    #[test]
+    fn test_heap_fallback_scratch() {
+        let mut scratch = HeapFallbackScratch::<64>::new();
+        let small = Layout::from_size_align(32, 8).unwrap();
+        let large = Layout::from_size_align(128, 8).unwrap();
+
+        unsafe {
+            let a = scratch.push_scratch(small).expect("Allocate on the stack");
+            let b = scratch.push_scratch(large).expect("Spill onto the heap");
+            let c = scratch.push_scratch(small).expect("Follow the spill");
+            assert_eq!(
+                scratch.stats().spills,
+                2,
+                "Allocations after a spill stay on the heap"
+            );
+
+            scratch
+                .pop_scratch(c.cast(), small)
+                .expect("Pop heap allocation");
+            scratch
+                .pop_scratch(b.cast(), large)
+                .expect("Pop heap allocation");
+            scratch
+                .pop_scratch(a.cast(), small)
+                .expect("Pop stack allocation");
+        }
+
+        assert_eq!(
+            scratch.stats(),
+            ScratchStats {
+                spills: 2,
+                spilled_bytes: 160,
+                largest_spill: 128,
+            }
+        );
+
+        scratch.set_heap_limit(Some(64));
+        unsafe {
+            let err = scratch
+                .push_scratch(large)
+                .expect_err("Heap limit should be enforced");
+            assert!(matches!(
+                err,
+                AllocScratchError::ExceededLimit {
+                    requested: 128,
+                    remaining: 64
+                }
+            ));
+        }
+        scratch.clear();
+        assert_eq!(scratch.stats().spills, 2, "Clearing should keep the stats");
     }
 }
@@ -0,0 +1,99 @@
+//! Fixtures shared by the unit tests.
+
+use rkyv::AlignedVec;
+
+use crate::decoder::RecordLayout;
+use crate::{
+    Alignment,
+    Document,
+    Encoder,
+    SegmentEncoder,
+    Text,
+    Value,
+    DEFAULT_SCRATCH_SPACE,
+    MIN_ALIGNMENT,
+};
+
+/// A document with the given id, named by the digits of the id so
+/// documents with ids of the same length have the same size.
+pub(crate) fn document(id: u64) -> Document {
+    let mut document = Document::default();
+    document.insert("id", Value::U64(id));
+    document.insert("name", Value::String(Text::from(id.to_string())));
+    document
+}
+
+/// The documents with ids `0..count`.
+pub(crate) fn documents(count: u64) -> Vec<Document> {
+    (0..count).map(document).collect()
+}
+
+/// Encodes the documents into a raw stream with the given layout.
+pub(crate) fn encode_documents(
+    documents: &[Document],
+    layout: RecordLayout,
+) -> AlignedVec {
+    encode_with_positions(documents, layout).0
+}
+
+/// Encodes the documents into a raw stream with the given layout, returning
+/// the end position of every document.
+pub(crate) fn encode_with_positions(
+    documents: &[Document],
+    layout: RecordLayout,
+) -> (AlignedVec, Vec<u64>) {
+    let encoder = encode_all(documents, layout);
+    let positions = encoder.positions().to_vec();
+    (encoder.into_writer(), positions)
+}
+
+/// Encodes the documents into a raw stream with the given layout, ending
+/// with a position index.
+pub(crate) fn encode_indexed(
+    documents: &[Document],
+    layout: RecordLayout,
+) -> AlignedVec {
+    encode_all(documents, layout)
+        .write_position_index()
+        .expect("Write position index")
+}
+
+/// An encoder with the given layout which has encoded every document,
+/// recording their positions.
+fn encode_all(
+    documents: &[Document],
+    layout: RecordLayout,
+) -> Encoder<AlignedVec, DEFAULT_SCRATCH_SPACE> {
+    let mut encoder = Encoder::with_alignment(AlignedVec::new(), layout.alignment);
+    encoder.set_framing(layout.framing);
+    encoder.set_framing_width(layout.width);
+    encoder.set_checksum_algorithm(layout.checksum);
+    encoder.enable_position_index();
+    for document in documents {
+        encoder.encode(document).expect("Encode document");
+    }
+    encoder
+}
+
+/// Encodes the documents into a segment with the given layout,
+/// [Alignment::None] uses the minimum segment alignment.
+pub(crate) fn encode_segment(
+    documents: &[Document],
+    layout: RecordLayout,
+) -> AlignedVec {
+    let alignment = match layout.alignment {
+        Alignment::None => MIN_ALIGNMENT,
+        Alignment::Padded(alignment) => alignment,
+    };
+    let mut encoder = SegmentEncoder::<_, DEFAULT_SCRATCH_SPACE>::with_alignment(
+        AlignedVec::new(),
+        alignment,
+    );
+    encoder.set_framing(layout.framing);
+    encoder.set_framing_width(layout.width);
+    encoder.set_checksum_algorithm(layout.checksum);
+    for document in documents {
+        encoder.encode(document).expect("Encode document");
+    }
+    encoder.finish().expect("Finish segment")
+}
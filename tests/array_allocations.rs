@@ -0,0 +1,142 @@
+//! Counts the allocations made while deserializing JSON arrays into values
+//! compared against the old untagged `TypedVec` approach.
+//!
+//! Arrays containing nulls are not typed, they still deserialize into
+//! `Value::ArrayDynamic` as the typed variants cannot represent a null.
+#![cfg(feature = "serde")]
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use bellini::{Bytes, Text, Value};
+use serde::Deserialize;
+
+struct CountingAllocator;
+
+thread_local! {
+    /// Tests run in parallel so each thread only counts its own allocations.
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn record_allocation() {
+    // The counter may already be destroyed while the thread is exiting.
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// The untagged array representation which was used before single pass
+/// inference.
+#[derive(Deserialize)]
+#[serde(untagged)]
+#[allow(dead_code)]
+enum UntaggedTypedVec {
+    String(Vec<Text>),
+    U64(Vec<u64>),
+    I64(Vec<i64>),
+    F64(Vec<f64>),
+    Bool(Vec<bool>),
+    Bytes(Vec<Bytes>),
+    Dynamic(Vec<Value>),
+}
+
+fn count_allocations<T>(func: impl FnOnce() -> T) -> (usize, T) {
+    let start = ALLOCATIONS.with(Cell::get);
+    let result = func();
+    (ALLOCATIONS.with(Cell::get) - start, result)
+}
+
+fn compare(json: &str, expected: Value) {
+    let (untagged, _) = count_allocations(|| {
+        serde_json::from_str::<UntaggedTypedVec>(json).expect("Deserialize untagged")
+    });
+    let (inferred, value) = count_allocations(|| {
+        serde_json::from_str::<Value>(json).expect("Deserialize value")
+    });
+
+    assert_eq!(value, expected);
+    assert!(
+        inferred < untagged,
+        "Single pass inference should allocate less, {inferred} >= {untagged} for {json}",
+    );
+}
+
+fn array(len: usize, element: impl Fn(usize) -> String) -> String {
+    let elements = (0..len).map(element).collect::<Vec<_>>();
+    format!("[{}]", elements.join(","))
+}
+
+#[test]
+fn test_u64_array_allocations() {
+    let json = array(256, |i| i.to_string());
+    compare(&json, Value::ArrayU64((0..256).collect()));
+}
+
+#[test]
+fn test_f64_array_allocations() {
+    let json = array(256, |i| format!("{i}.5"));
+    compare(
+        &json,
+        Value::ArrayF64((0..256).map(|i| i as f64 + 0.5).collect()),
+    );
+}
+
+#[test]
+fn test_mixed_integer_array_allocations() {
+    let json = array(256, |i| {
+        if i % 2 == 0 {
+            i.to_string()
+        } else {
+            format!("-{i}")
+        }
+    });
+    let expected = (0..256).map(|i| if i % 2 == 0 { i } else { -i }).collect();
+    compare(&json, Value::ArrayI64(expected));
+}
+
+#[test]
+fn test_string_array_allocations() {
+    let json = array(256, |i| format!("\"{i}\""));
+    let expected = (0..256).map(|i| Text::from(i.to_string())).collect();
+    compare(&json, Value::ArrayString(expected));
+}
+
+#[test]
+fn test_nullable_array_allocations() {
+    let json = array(256, |i| {
+        if i % 2 == 0 {
+            i.to_string()
+        } else {
+            "null".to_string()
+        }
+    });
+    let expected = (0..256)
+        .map(|i| {
+            if i % 2 == 0 {
+                Value::U64(i)
+            } else {
+                Value::Null
+            }
+        })
+        .collect();
+    // Nulls keep the array dynamic but it is still not buffered and retried
+    // against every untagged variant.
+    compare(&json, Value::ArrayDynamic(expected));
+}